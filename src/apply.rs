@@ -0,0 +1,185 @@
+use crate::{
+    build::{self, explain_arg},
+    config::Config,
+    lock::{self, LockError, LockedPackage, Lockfile},
+    manifest::Manifest,
+    store::{Record, Store},
+};
+use clap::{App, Arg, ArgMatches};
+use quick_error::quick_error;
+use std::{error::Error, path::Path};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ApplyError {
+        Duplicate(package: String) {
+            display("'{}' is listed more than once in the manifest", package)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Action {
+    Install(Record),
+    Replace(Record, Record),
+    Reconfigure(Record),
+    Remove(Record),
+    Unlisted(Record),
+}
+
+impl Action {
+    fn describe(&self) -> String {
+        match self {
+            Action::Install(new) => format!("install {}", new),
+            Action::Replace(old, new) => format!("replace {} with {}", old, new),
+            Action::Reconfigure(new) => format!("change sandbox for {}", new),
+            Action::Remove(old) => format!("uninstall {}", old),
+            Action::Unlisted(old) => format!("keep {} (not in manifest; --prune removes it)", old),
+        }
+    }
+}
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Install and remove packages to match a manifest")
+        .arg(
+            Arg::with_name("manifest")
+                .index(1)
+                .default_value("storm.toml")
+                .help("Manifest listing the packages to install"),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .short("p")
+                .help("Uninstall packages that aren't in the manifest"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .short("n")
+                .help("Show the plan without changing anything"),
+        )
+        .arg(explain_arg())
+        .arg(lock::locked_arg().conflicts_with("lock"))
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .short("l")
+                .help("Pin the manifest's packages in its lockfile once they're installed"),
+        )
+}
+
+fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest_path = Path::new(args.value_of_os("manifest").unwrap());
+    let manifest = Manifest::load(manifest_path)?;
+    // storm.toml is locked by storm.lock
+    let lockfile_path = manifest_path.with_extension("lock");
+    let lock = if args.is_present("locked") {
+        Some(Lockfile::load_required(&lockfile_path)?)
+    } else {
+        None
+    };
+    let specs = manifest
+        .packages
+        .iter()
+        .map(|p| Ok((p.spec()?, p)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    let config = Config::load()?;
+    let mut store = Store::from_args(args)?;
+
+    let mut wanted: Vec<Record> = Vec::new();
+    for (spec, package) in &specs {
+        let mut record = build::resolve(
+            &config,
+            &store,
+            spec,
+            lock.as_ref(),
+            args.is_present("explain"),
+        )?;
+        if wanted.iter().any(|r| r.is(&record.repo, &record.name)) {
+            return Err(Box::new(ApplyError::Duplicate(spec.to_string())));
+        }
+        record.sandbox = package.sandbox.clone();
+        wanted.push(record);
+    }
+
+    let installed = store
+        .records()
+        .iter()
+        .filter(|r| r.installed)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let listed = wanted
+        .iter()
+        .map(|r| (r.repo.clone(), r.name.clone()))
+        .collect::<Vec<_>>();
+
+    let mut plan = Vec::new();
+    for new in wanted {
+        match installed.iter().find(|r| r.is(&new.repo, &new.name)) {
+            None => plan.push(Action::Install(new)),
+            Some(old) if !old.same_build(&new) => plan.push(Action::Replace(old.clone(), new)),
+            Some(old) if old.sandbox != new.sandbox => plan.push(Action::Reconfigure(new)),
+            Some(_) => {}
+        }
+    }
+    for old in installed {
+        if !listed.iter().any(|(repo, name)| old.is(repo, name)) {
+            plan.push(if args.is_present("prune") {
+                Action::Remove(old)
+            } else {
+                Action::Unlisted(old)
+            });
+        }
+    }
+
+    if plan.is_empty() {
+        println!("nothing to do");
+    } else {
+        println!("plan:");
+        for action in &plan {
+            println!("    {}", action.describe());
+        }
+    }
+
+    if args.is_present("dry-run") {
+        return Ok(());
+    }
+
+    for action in &plan {
+        match action {
+            Action::Install(new) | Action::Replace(_, new) | Action::Reconfigure(new) => {
+                build::build_record(&config, &mut store, new)?;
+                store.install(new);
+            }
+            Action::Remove(old) => {
+                store.uninstall(|r| r.same_build(old));
+            }
+            Action::Unlisted(_) => continue,
+        }
+        store.save()?;
+        println!("{}", action.describe());
+    }
+
+    if args.is_present("lock") {
+        let mut lock = Lockfile::default();
+        for (repo, name) in &listed {
+            let record = store.installed(repo, name).unwrap();
+            if record.source.is_some() {
+                eprintln!("warning: {}", LockError::LocalPackage(record.to_string()));
+                continue;
+            }
+            lock.insert(LockedPackage::new(
+                record,
+                &config.repo.index(&store, repo)?,
+            ));
+        }
+        lock.save(&lockfile_path)?;
+    }
+
+    Ok(())
+}
+
+pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
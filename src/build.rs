@@ -1,22 +1,171 @@
-use crate::package::Package;
+use crate::{
+    config::Config,
+    local::{self, LOCAL_REPO},
+    lock::{LockError, Lockfile},
+    package::{self, PackageSpec},
+    store::{Record, Store},
+};
 use clap::{App, Arg, ArgMatches};
-use std::error::Error;
+use std::{error::Error, fs, path::Path};
 
 fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
-    app.about("Build packages").arg(
-        Arg::with_name("package")
-            .required(true)
-            .multiple(true)
-            .index(1),
-    )
+    app.about("Build packages")
+        .arg(
+            Arg::with_name("package")
+                .required(true)
+                .multiple(true)
+                .index(1),
+        )
+        .arg(explain_arg())
+}
+
+pub fn explain_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("explain")
+        .long("explain")
+        .short("e")
+        .help("Show which repositories were considered for each package")
+}
+
+/// Resolve a package to the record its build would have. With a lockfile, only what the
+/// lockfile pins will resolve.
+pub fn resolve(
+    config: &Config,
+    store: &Store,
+    package: &PackageSpec,
+    lock: Option<&Lockfile>,
+    explain: bool,
+) -> Result<Record, Box<dyn Error>> {
+    if let Some(path) = package.path() {
+        if lock.is_some() {
+            return Err(Box::new(LockError::LocalPackage(package.to_string())));
+        }
+        return resolve_local(path, explain);
+    }
+
+    if let Some(lock) = lock {
+        let locked = lock.find(package, |repo| config.repo.compare(repo))?;
+        locked.verify(&config.repo.index(store, &locked.repo)?)?;
+        if explain {
+            println!("{}:", package);
+            println!("    {}: pinned by the lockfile", locked.record());
+        }
+        return Ok(locked.record());
+    }
+
+    let resolution = config.repo.resolve(store, package)?;
+
+    if explain {
+        println!("{}:", package);
+        for (repo, reason) in &resolution.considered {
+            println!("    {}: {}", repo, reason);
+        }
+        for (version, reason) in &resolution.masked {
+            println!("    {}: {} is masked: {}", resolution.repo, version, reason);
+        }
+        println!(
+            "    {}: provides {} (selected)",
+            resolution.repo, resolution.version
+        );
+        for repo in &resolution.not_consulted {
+            println!("    {}: not consulted", repo);
+        }
+    } else if package.repo().is_none() {
+        println!("using {} from {}", package, resolution.repo);
+    }
+
+    Ok(Record {
+        repo: resolution.repo,
+        name: resolution.name,
+        version: resolution.version,
+        variants: package.variants().clone(),
+        installed: false,
+        sandbox: None,
+        source: None,
+        digest: None,
+    })
+}
+
+fn resolve_local(path: &Path, explain: bool) -> Result<Record, Box<dyn Error>> {
+    let path = fs::canonicalize(path)
+        .map_err(|e| local::LocalError::Unreadable(path.display().to_string(), e))?;
+    let package = local::inspect(&path)?;
+
+    let record = Record {
+        repo: LOCAL_REPO.to_string(),
+        name: package.name,
+        version: package.version,
+        variants: Default::default(),
+        installed: false,
+        sandbox: None,
+        source: Some(path),
+        digest: Some(package.digest),
+    };
+    if explain {
+        println!("file:{}:", record.source.as_ref().unwrap().display());
+        println!("    {}: read from a local {}", record, package.format);
+    }
+
+    Ok(record)
+}
+
+/// Build a resolved package unless it was already built, in its own sandbox if it has one.
+pub fn build_record(
+    config: &Config,
+    store: &mut Store,
+    record: &Record,
+) -> Result<(), Box<dyn Error>> {
+    if store.find(record).is_none() {
+        let dir = store.build_dir(record);
+        fs::create_dir_all(&dir)?;
+        let built = match &record.source {
+            Some(path) => local::unpack(path, &dir),
+            None => {
+                let sandbox = record.sandbox.as_ref().unwrap_or(&config.sandbox);
+                config.repo.build(store, record, sandbox, &dir)
+            }
+        };
+        if let Err(e) = built {
+            fs::remove_dir_all(&dir)?;
+            return Err(e);
+        }
+
+        store.add_build(record.clone());
+        println!("built {}", record);
+    }
+
+    Ok(())
+}
+
+/// Resolve a package and build it if necessary, returning its store record.
+pub fn build(
+    config: &Config,
+    store: &mut Store,
+    package: &PackageSpec,
+    lock: Option<&Lockfile>,
+    explain: bool,
+) -> Result<Record, Box<dyn Error>> {
+    let record = resolve(config, store, package, lock, explain)?;
+    build_record(config, store, &record)?;
+    Ok(record)
 }
 
 fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let _packages = args
-        .values_of("package")
-        .unwrap()
-        .map(Package::parse)
-        .collect::<Vec<_>>();
+    let packages = package::parse_specs(args.values_of("package").unwrap())?;
+
+    let config = Config::load()?;
+    let mut store = Store::from_args(args)?;
+
+    for package in &packages {
+        build(
+            &config,
+            &mut store,
+            package,
+            None,
+            args.is_present("explain"),
+        )?;
+        store.save()?;
+    }
+
     Ok(())
 }
 
@@ -43,7 +43,7 @@ pub struct CliConfig {
 }
 
 impl Config {
-    fn get_path<'a, P: AsRef<Path>>(path: &'a Option<P>) -> Result<&'a Path, Box<dyn Error>> {
+    fn get_path<P: AsRef<Path>>(path: &Option<P>) -> Result<&Path, Box<dyn Error>> {
         path.as_ref()
             .map(|p| p.as_ref())
             .or_else(|| DEFAULT_CONFIG_FILE.as_ref().map(|p| p.as_path()))
@@ -73,12 +73,12 @@ impl Config {
             .create(true)
             .truncate(true)
             .open(Self::get_path(&path)?)?;
-        config_file.write(&toml::to_string_pretty(&config)?.into_bytes())?;
+        config_file.write_all(&toml::to_string_pretty(&config)?.into_bytes())?;
         Ok(())
     }
 
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
-        Self::save_raw::<PathBuf, _>(None, self.into())
+        Self::save_raw::<PathBuf, _>(None, self)
     }
 }
 
@@ -109,7 +109,7 @@ fn find_key<'a>(
             Value::Array(arr) => arr
                 .get_mut(
                     leaf.parse::<usize>()
-                        .or_else(|_| Err(ConfigError::NoSuchKey(leaf.to_string())))?,
+                        .map_err(|_| ConfigError::NoSuchKey(leaf.to_string()))?,
                 )
                 .ok_or_else(|| ConfigError::NoSuchKey(leaf.to_string()))?,
             _ => return Err(Box::new(ConfigError::NoSuchKey(leaf.to_string()))),
@@ -165,13 +165,13 @@ fn unset(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         let (key_path, key_name) = key_path.split_at(idx + 1);
         // chop off the last period
         let key_path = &key_path[..key_path.len() - 1];
-        (find_key(&mut config, &key_path, false)?, key_name)
+        (find_key(&mut config, key_path, false)?, key_name)
     } else {
         (&mut config, key_path)
     };
 
     if let Value::Table(tbl) = key_parent {
-        if let None = tbl.remove(key_name) {
+        if tbl.remove(key_name).is_none() {
             return Err(Box::new(ConfigError::NoSuchKey(key_name.to_string())));
         }
     } else {
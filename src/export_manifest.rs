@@ -0,0 +1,44 @@
+use crate::{
+    manifest::{Manifest, ManifestPackage},
+    store::Store,
+};
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Write a manifest pinning every installed package")
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the manifest to a file instead of standard output"),
+        )
+}
+
+fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let store = Store::from_args(args)?;
+
+    let mut manifest = Manifest {
+        packages: store
+            .records()
+            .iter()
+            .filter(|r| r.installed)
+            .map(ManifestPackage::from)
+            .collect(),
+    };
+    manifest
+        .packages
+        .sort_by(|a, b| (&a.repo, &a.name).cmp(&(&b.repo, &b.name)));
+
+    match args.value_of_os("output") {
+        Some(path) => manifest.save(path),
+        None => {
+            print!("{}", manifest.to_toml()?);
+            Ok(())
+        }
+    }
+}
+
+pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
@@ -0,0 +1,148 @@
+use crate::version::{Compare, Version};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Everything storm knows about one version of a package in a repo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub version: Version,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub homepage: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub license: String,
+    /// Runtime dependencies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends: Vec<String>,
+    /// Dependencies needed to build the package, for repos that build from source.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_depends: Vec<String>,
+    /// Dependencies that run on the build machine itself (e.g. compilers) when cross-compiling.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub host_depends: Vec<String>,
+    /// Other names (like virtual packages) the package can stand in for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub provides: Vec<String>,
+    /// Packages that can't be installed alongside this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+    /// Build variants the package supports, with a leading `+` if enabled by default.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<String>,
+    /// Versions in different slots can be installed side by side.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub slot: String,
+    /// Architectures the version is marked stable (`amd64`) or testing (`~amd64`) on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Source files mapped to checksums written as `algorithm:hex`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl IndexEntry {
+    pub fn new(version: Version) -> Self {
+        Self {
+            version,
+            summary: String::new(),
+            homepage: String::new(),
+            license: String::new(),
+            depends: Vec::new(),
+            build_depends: Vec::new(),
+            host_depends: Vec::new(),
+            provides: Vec::new(),
+            conflicts: Vec::new(),
+            variants: Vec::new(),
+            slot: String::new(),
+            keywords: Vec::new(),
+            checksums: BTreeMap::new(),
+        }
+    }
+}
+
+/// The packages a repo provides, as of its last sync.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PackageIndex {
+    /// The revision of the repo that was synced, if the repo has revisions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default)]
+    packages: BTreeMap<String, Vec<IndexEntry>>,
+}
+
+impl PackageIndex {
+    /// Load an index, or `None` if the repo has never been synced.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("json.tmp");
+        fs::File::create(&tmp_path)?.write_all(&serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, name: String, entry: IndexEntry) {
+        self.packages.entry(name).or_default().push(entry);
+    }
+
+    /// Keep only the packages whose names `keep` accepts.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut keep: F) {
+        self.packages.retain(|name, _| keep(name));
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn get(&self, name: &str) -> &[IndexEntry] {
+        self.packages.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn entry(&self, name: &str, version: &Version) -> Option<&IndexEntry> {
+        self.get(name).iter().find(|e| e.version == *version)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.packages.keys().map(String::as_str)
+    }
+
+    pub fn versions(&self, name: &str) -> Vec<Version> {
+        self.get(name).iter().map(|e| e.version.clone()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &IndexEntry)> {
+        self.packages
+            .iter()
+            .flat_map(|(name, entries)| entries.iter().map(move |e| (name.as_str(), e)))
+    }
+
+    /// Each package along with its newest version, as ordered by `compare`.
+    pub fn newest(&self, compare: Compare) -> impl Iterator<Item = (&str, &IndexEntry)> {
+        self.packages.iter().filter_map(move |(name, entries)| {
+            entries
+                .iter()
+                .max_by(|a, b| {
+                    compare(&a.version, &b.version).then_with(|| a.version.cmp(&b.version))
+                })
+                .map(|entry| (name.as_str(), entry))
+        })
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    config::Config,
+    index::PackageIndex,
+    list::State,
+    package::{self, PackageSpec},
+    sandbox::SandboxConfig,
+    store::{self, Store},
+    version::Version,
+};
+use clap::{App, Arg, ArgMatches};
+use quick_error::quick_error;
+use serde::Serialize;
+use std::{error::Error, path::PathBuf};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum InfoError {
+        NotBuilt(package: String) {
+            display("'{}' hasn't been built or installed", package)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BuildInfo {
+    version: Version,
+    variants: String,
+    state: State,
+    path: PathBuf,
+    /// Missing if the build directory can't be read (or no longer exists).
+    size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Info<'a> {
+    repo: &'a str,
+    name: &'a str,
+    version: &'a Version,
+    available: Vec<&'a Version>,
+    description: &'a str,
+    homepage: &'a str,
+    license: &'a str,
+    depends: &'a [String],
+    builds: Vec<BuildInfo>,
+    sandbox: &'a SandboxConfig,
+}
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Show everything known about packages")
+        .arg(
+            Arg::with_name("package")
+                .required(true)
+                .multiple(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .short("j")
+                .help("Print a JSON array with an object per package"),
+        )
+}
+
+/// A package to show, with its repo's index if the repo can still be read.
+struct Found {
+    repo: String,
+    name: String,
+    version: Version,
+    index: Option<PackageIndex>,
+}
+
+/// Resolve a package through its repos, or failing that, find it in the store. Packages from
+/// local files, and ones whose repo was removed (or never synced), are only in the store.
+fn find(config: &Config, store: &Store, package: &PackageSpec) -> Result<Found, Box<dyn Error>> {
+    let resolved = match package.path() {
+        Some(_) => None,
+        None => Some(config.repo.resolve(store, package)),
+    };
+    if let Some(Ok(resolution)) = resolved {
+        return Ok(Found {
+            index: Some(config.repo.index(store, &resolution.repo)?),
+            repo: resolution.repo,
+            name: resolution.name,
+            version: resolution.version,
+        });
+    }
+
+    // the installed build if there is one, or else the newest
+    let record = store
+        .records()
+        .iter()
+        .filter(|r| r.matches(package))
+        .max_by(|a, b| {
+            let compare = config.repo.compare(&a.repo);
+            (a.installed, &a.repo, &a.name)
+                .cmp(&(b.installed, &b.repo, &b.name))
+                .then_with(|| compare(&a.version, &b.version))
+        });
+    match (record, resolved) {
+        (Some(record), _) => Ok(Found {
+            repo: record.repo.clone(),
+            name: record.name.clone(),
+            version: record.version.clone(),
+            index: config.repo.index(store, &record.repo).ok(),
+        }),
+        (None, Some(Err(e))) => Err(e),
+        (None, _) => Err(Box::new(InfoError::NotBuilt(package.to_string()))),
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in &["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+fn print_info(info: &Info) {
+    let or_unknown = |s: &str| if s.is_empty() { "unknown" } else { s }.to_string();
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+
+    println!("{}:{} {}", info.repo, info.name, info.version);
+    println!("    description: {}", or_unknown(info.description));
+    println!("    homepage: {}", or_unknown(info.homepage));
+    println!("    license: {}", or_unknown(info.license));
+    println!(
+        "    versions: {}",
+        list(info.available.iter().map(|v| v.to_string()).collect())
+    );
+    println!("    depends: {}", list(info.depends.to_vec()));
+    println!("    sandbox: {}", info.sandbox);
+    if info.builds.is_empty() {
+        println!("    builds: none");
+    } else {
+        println!("    builds:");
+        for build in &info.builds {
+            println!(
+                "        {}{} ({}) {} [{}]",
+                build.version,
+                build.variants,
+                build.state,
+                build.path.display(),
+                match build.size {
+                    Some(size) => human_size(size),
+                    None if !build.path.exists() => "missing".to_string(),
+                    None => "unknown size".to_string(),
+                }
+            );
+        }
+    }
+}
+
+fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let packages = package::parse_specs(args.values_of("package").unwrap())?;
+
+    let config = Config::load()?;
+    let store = Store::from_args(args)?;
+
+    let found = packages
+        .iter()
+        .map(|package| find(&config, &store, package))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut infos = Vec::new();
+    for found in &found {
+        let entries = found.index.as_ref().map_or(&[][..], |i| i.get(&found.name));
+        // the index may have been synced again since the package was resolved
+        let entry = entries.iter().find(|e| e.version == found.version);
+
+        let compare = config.repo.compare(&found.repo);
+        let mut available = entries.iter().map(|e| &e.version).collect::<Vec<_>>();
+        available.sort_by(|a, b| compare(a, b).then_with(|| a.cmp(b)));
+
+        let builds = store
+            .records()
+            .iter()
+            .filter(|r| r.is(&found.repo, &found.name))
+            .map(|r| {
+                let path = store.build_dir(r);
+                BuildInfo {
+                    version: r.version.clone(),
+                    variants: if r.variants.is_empty() {
+                        String::new()
+                    } else {
+                        r.variants.to_string()
+                    },
+                    state: if r.installed {
+                        State::Installed
+                    } else {
+                        State::Built
+                    },
+                    size: store::disk_usage(&path).ok(),
+                    path,
+                }
+            })
+            .collect();
+
+        infos.push(Info {
+            repo: &found.repo,
+            name: &found.name,
+            version: &found.version,
+            available,
+            description: entry.map_or("", |e| &e.summary),
+            homepage: entry.map_or("", |e| &e.homepage),
+            license: entry.map_or("", |e| &e.license),
+            depends: entry.map_or(&[], |e| &e.depends),
+            builds,
+            sandbox: store
+                .installed(&found.repo, &found.name)
+                .and_then(|r| r.sandbox.as_ref())
+                .unwrap_or(&config.sandbox),
+        });
+    }
+
+    if args.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+    } else {
+        for info in &infos {
+            print_info(info);
+        }
+    }
+
+    Ok(())
+}
+
+pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
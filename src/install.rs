@@ -1,24 +1,81 @@
-use crate::package::Package;
+use crate::{
+    build::{build_record, explain_arg, resolve},
+    config::Config,
+    lock::{self, LockError, LockedPackage, Lockfile},
+    package,
+    store::Store,
+};
 use clap::{App, Arg, ArgMatches};
 use std::error::Error;
 
 fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
-    app.about("Install packages").arg(
-        Arg::with_name("package")
-            .required(true)
-            .multiple(true)
-            .index(1),
-    )
+    app.about("Install packages")
+        .arg(
+            Arg::with_name("package")
+                .required(true)
+                .multiple(true)
+                .index(1),
+        )
+        .arg(explain_arg())
+        .arg(lock::locked_arg().conflicts_with("lock"))
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .short("l")
+                .help("Pin the installed packages in the lockfile"),
+        )
+        .arg(
+            Arg::with_name("lockfile")
+                .long("lockfile")
+                .takes_value(true)
+                .value_name("FILE")
+                .default_value("storm.lock")
+                .help("Lockfile used by --lock and --locked"),
+        )
 }
 
 fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let packages = args
-        .values_of("package")
-        .unwrap()
-        .map(Package::parse)
-        .collect::<Vec<_>>();
+    let packages = package::parse_specs(args.values_of("package").unwrap())?;
+
+    let config = Config::load()?;
+    let mut store = Store::from_args(args)?;
+
+    let lockfile_path = args.value_of_os("lockfile").unwrap();
+    let mut lock = if args.is_present("locked") {
+        Some(Lockfile::load_required(lockfile_path)?)
+    } else if args.is_present("lock") {
+        Some(Lockfile::load(lockfile_path)?.unwrap_or_default())
+    } else {
+        None
+    };
+
+    for package in &packages {
+        let mut record = resolve(
+            &config,
+            &store,
+            package,
+            lock.as_ref().filter(|_| args.is_present("locked")),
+            args.is_present("explain"),
+        )?;
+        // keep (and build in) any sandbox override a manifest gave the package
+        record.sandbox = store
+            .installed(&record.repo, &record.name)
+            .and_then(|r| r.sandbox.clone());
+        build_record(&config, &mut store, &record)?;
+        store.install(&record);
+        store.save()?;
+        println!("installed {}", record);
+
+        if args.is_present("lock") && record.source.is_some() {
+            eprintln!("warning: {}", LockError::LocalPackage(package.to_string()));
+        } else if args.is_present("lock") {
+            let index = config.repo.index(&store, &record.repo)?;
+            let lock = lock.as_mut().unwrap();
+            lock.insert(LockedPackage::new(&record, &index));
+            lock.save(lockfile_path)?;
+        }
+    }
 
-    dbg!(packages);
     Ok(())
 }
 
@@ -1,5 +1,50 @@
+use crate::{
+    config::Config, index::PackageIndex, package::Variants, repo::RepoError, store::Store,
+    version::Version,
+};
 use clap::{App, Arg, ArgGroup, ArgMatches};
-use std::error::Error;
+use glob::Pattern;
+use serde::Serialize;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Installed,
+    Built,
+    Available,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            State::Installed => "installed",
+            State::Built => "built",
+            State::Available => "available",
+        })
+    }
+}
+
+struct Entry<'a> {
+    repo: &'a str,
+    name: &'a str,
+    variants: Option<&'a Variants>,
+    version: &'a Version,
+    state: State,
+}
+
+impl<'a> Display for Entry<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.repo, self.name)?;
+        if let Some(variants) = self.variants.filter(|v| !v.is_empty()) {
+            write!(f, "{}", variants)?;
+        }
+        write!(f, " {} ({})", self.version, self.state)
+    }
+}
 
 fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
     app.about("List installed/built packages")
@@ -13,7 +58,7 @@ fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             Arg::with_name("built")
                 .long("built")
                 .short("b")
-                .help("List packages with saved builds"),
+                .help("List saved builds that aren't installed"),
         )
         .arg(
             Arg::with_name("installed")
@@ -26,15 +71,95 @@ fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
                 .args(&["all", "built", "installed"])
                 .required(false),
         )
-    /*.arg(
-        Arg::with_name("glob")
-            .index(1),
-    )*/
+        .arg(
+            Arg::with_name("glob")
+                .index(1)
+                .help("Only list packages matching a glob, e.g. 'gentoo:media-video/*'"),
+        )
 }
 
 fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    println!("list");
-    dbg!(args);
+    let config = Config::load()?;
+    let store = Store::from_args(args)?;
+
+    // globs with a repo match against repo:name, and otherwise against just the name
+    let glob = args.value_of("glob").map(Pattern::new).transpose()?;
+    let matches = |repo: &str, name: &str| match &glob {
+        Some(glob) if glob.as_str().contains(':') => glob.matches(&format!("{}:{}", repo, name)),
+        Some(glob) => glob.matches(name),
+        None => true,
+    };
+
+    let mut entries = store
+        .records()
+        .iter()
+        .filter(|r| {
+            if args.is_present("installed") {
+                r.installed
+            } else if args.is_present("built") {
+                !r.installed
+            } else {
+                true
+            }
+        })
+        .map(|r| Entry {
+            repo: &r.repo,
+            name: &r.name,
+            variants: Some(&r.variants),
+            version: &r.version,
+            state: if r.installed {
+                State::Installed
+            } else {
+                State::Built
+            },
+        })
+        .filter(|e| matches(e.repo, e.name))
+        .collect::<Vec<_>>();
+
+    let repos = config.repo.by_precedence();
+    let mut indexes = Vec::new();
+    if args.is_present("all") {
+        for repo in &repos {
+            match PackageIndex::load(store.index_path(repo))? {
+                Some(index) => indexes.push((*repo, index)),
+                None => eprintln!("warning: {}", RepoError::NotSynced(repo.to_string())),
+            }
+        }
+    }
+
+    for (repo, index) in &indexes {
+        for (name, entry) in index.iter() {
+            if matches(repo, name)
+                && !store
+                    .records()
+                    .iter()
+                    .any(|r| r.is(repo, name) && r.version == entry.version)
+            {
+                entries.push(Entry {
+                    repo,
+                    name,
+                    variants: None,
+                    version: &entry.version,
+                    state: State::Available,
+                });
+            }
+        }
+    }
+
+    // repos that have since been removed from the config sort last
+    let precedence = |repo: &str| repos.iter().position(|r| *r == repo).unwrap_or(repos.len());
+    entries.sort_by(|a, b| {
+        precedence(a.repo)
+            .cmp(&precedence(b.repo))
+            .then_with(|| a.repo.cmp(b.repo))
+            .then_with(|| a.name.cmp(b.name))
+            .then_with(|| a.version.cmp(b.version))
+    });
+
+    for entry in entries {
+        println!("{}", entry);
+    }
+
     Ok(())
 }
 
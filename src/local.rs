@@ -0,0 +1,392 @@
+use crate::version::{Version, VersionError};
+use ar::Archive as ArArchive;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use quick_error::quick_error;
+use ruzstd::streaming_decoder::StreamingDecoder;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, BufRead, BufReader, Cursor, Read},
+    os::unix::{self, ffi::OsStrExt},
+    path::{Component, Path},
+};
+use tar::Archive;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LocalError {
+        Unreadable(path: String, err: io::Error) {
+            display("couldn't read '{}': {}", path, err)
+            cause(err)
+        }
+        UnknownFormat(path: String) {
+            display("'{}' isn't a .deb, .apk, .pkg.tar.* or storm recipe directory", path)
+        }
+        NoRecipe(path: String) {
+            display("'{}' is a directory but has no {}", path, RECIPE_FILE)
+        }
+        UnsupportedCompression(path: String, compression: &'static str) {
+            display("'{}' uses {} compression, which isn't supported", path, compression)
+        }
+        MissingMetadata(path: String, what: &'static str) {
+            display("'{}' has no {}", path, what)
+        }
+        InvalidName(path: String, name: String) {
+            display("'{}' has an invalid package name '{}'", path, name)
+        }
+        InvalidVersion(path: String, err: VersionError) {
+            display("'{}' has an invalid version: {}", path, err)
+            cause(err)
+        }
+    }
+}
+
+/// The repo name local packages are recorded under. It can't be used for a real repo, since
+/// `file:` specs always refer to a path.
+pub const LOCAL_REPO: &str = "file";
+
+/// Describes a package in a storm recipe directory, whose `files/` subdirectory is its image.
+const RECIPE_FILE: &str = "recipe.toml";
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Deb,
+    Apk,
+    Pacman,
+    Recipe,
+}
+
+impl Format {
+    /// Work out what a path holds from its contents, not its name.
+    fn detect(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let unreadable = |e| LocalError::Unreadable(path.display().to_string(), e);
+
+        if path.is_dir() {
+            return if path.join(RECIPE_FILE).is_file() {
+                Ok(Format::Recipe)
+            } else {
+                Err(Box::new(LocalError::NoRecipe(path.display().to_string())))
+            };
+        }
+
+        let mut magic = Vec::new();
+        fs::File::open(path)
+            .and_then(|f| f.take(AR_MAGIC.len() as u64).read_to_end(&mut magic))
+            .map_err(unreadable)?;
+
+        if magic.starts_with(AR_MAGIC) {
+            Ok(Format::Deb)
+        } else if magic.starts_with(GZIP_MAGIC) {
+            Self::detect_gzipped(path)
+        } else if magic.starts_with(ZSTD_MAGIC) || magic.starts_with(XZ_MAGIC) {
+            Ok(Format::Pacman)
+        } else {
+            Err(Box::new(LocalError::UnknownFormat(
+                path.display().to_string(),
+            )))
+        }
+    }
+
+    /// Tell gzipped apks from gzipped pacman packages by their metadata. An apk's first
+    /// stream holds a `.SIGN.*` signature or just the `.PKGINFO` (and install scripts), while
+    /// a pacman package lists its files in `.MTREE`, next to `.PKGINFO` and `.BUILDINFO`.
+    fn detect_gzipped(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // only the first stream, so an apk's metadata ends before its files start
+        let mut tar = Archive::new(GzDecoder::new(BufReader::new(fs::File::open(path)?)));
+        for entry in tar.entries()? {
+            let entry = entry?;
+            let entry_path = entry.path()?;
+            if !is_metadata(&entry_path) {
+                break;
+            }
+            let name = entry_path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with(".SIGN.") {
+                return Ok(Format::Apk);
+            } else if name == ".MTREE" || name == ".BUILDINFO" {
+                return Ok(Format::Pacman);
+            }
+        }
+        Ok(Format::Apk)
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Deb => "deb",
+            Format::Apk => "apk",
+            Format::Pacman => "pacman package",
+            Format::Recipe => "recipe",
+        })
+    }
+}
+
+/// What a local package says about itself.
+#[derive(Debug)]
+pub struct LocalPackage {
+    pub format: Format,
+    pub name: String,
+    pub version: Version,
+    /// A hash of the archive or recipe directory, since its name and version alone don't say
+    /// whether it has changed.
+    pub digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recipe {
+    name: String,
+    version: String,
+}
+
+/// Wrap a (possibly) compressed stream in the right decompressor, going by its magic number.
+pub fn decompress<'a, R: BufRead + 'a>(
+    path: &Path,
+    mut reader: R,
+) -> Result<Box<dyn Read + 'a>, Box<dyn Error>> {
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(StreamingDecoder::new(reader)?))
+    } else if magic.starts_with(XZ_MAGIC) {
+        let mut decompressed = Vec::new();
+        lzma_rs::xz_decompress(&mut reader, &mut decompressed)?;
+        Ok(Box::new(Cursor::new(decompressed)))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Err(Box::new(LocalError::UnsupportedCompression(
+            path.display().to_string(),
+            "bzip2",
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Parse `Key: value` (deb control) or `key = value` (.PKGINFO) lines. Continuation lines and
+/// repeated keys are ignored, since only the first line of a few fields is needed.
+pub fn parse_fields(contents: &str, separator: char) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with(' ') && !line.starts_with('#'))
+        .filter_map(|line| {
+            let idx = line.find(separator)?;
+            Some((
+                line[..idx].trim().to_lowercase(),
+                line[idx + 1..].trim().to_string(),
+            ))
+        })
+        .fold(HashMap::new(), |mut fields, (key, value)| {
+            fields.entry(key).or_insert(value);
+            fields
+        })
+}
+
+/// Top-level dotfiles in apks and pacman packages (`.PKGINFO`, `.MTREE`, `.SIGN.*`, install
+/// scripts, ...) describe the package rather than being part of it.
+fn is_metadata(path: &Path) -> bool {
+    let mut components = path
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str().to_string_lossy());
+    match (components.next(), components.next()) {
+        (Some(name), None) => name.starts_with('.'),
+        _ => false,
+    }
+}
+
+/// Read (and optionally unpack) a .deb: an ar archive holding control.tar.* and data.tar.*.
+fn read_deb(path: &Path, dir: Option<&Path>) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut fields = None;
+    let mut archive = ArArchive::new(fs::File::open(path)?);
+    while let Some(member) = archive.next_entry() {
+        let member = member?;
+        let name = String::from_utf8_lossy(member.header().identifier()).into_owned();
+
+        if name.starts_with("control.tar") {
+            let mut tar = Archive::new(decompress(path, BufReader::new(member))?);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if entry.path()?.file_name().is_some_and(|n| n == "control") {
+                    let mut control = String::new();
+                    entry.read_to_string(&mut control)?;
+                    fields = Some(parse_fields(&control, ':'));
+                }
+            }
+            if dir.is_none() {
+                break;
+            }
+        } else if name.starts_with("data.tar") {
+            if let Some(dir) = dir {
+                // unpack refuses to write anything outside of the build directory
+                Archive::new(decompress(path, BufReader::new(member))?).unpack(dir)?;
+            }
+        }
+    }
+
+    fields.ok_or_else(|| {
+        LocalError::MissingMetadata(path.display().to_string(), "control file").into()
+    })
+}
+
+/// Read (and optionally unpack) an apk or pacman package: a tarball with a `.PKGINFO`.
+fn read_pkginfo(
+    path: &Path,
+    dir: Option<&Path>,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut tar = Archive::new(decompress(path, BufReader::new(fs::File::open(path)?))?);
+    // the segments of an apk each end early, so keep reading past end-of-archive markers
+    tar.set_ignore_zeros(true);
+
+    let mut fields = None;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        if is_metadata(&entry_path) {
+            if entry_path.file_name().is_some_and(|n| n == ".PKGINFO") {
+                let mut pkginfo = String::new();
+                entry.read_to_string(&mut pkginfo)?;
+                fields = Some(parse_fields(&pkginfo, '='));
+                if dir.is_none() {
+                    break;
+                }
+            }
+        } else if let Some(dir) = dir {
+            // unpack_in refuses to write anything outside of the build directory
+            entry.unpack_in(dir)?;
+        }
+    }
+
+    fields.ok_or_else(|| LocalError::MissingMetadata(path.display().to_string(), ".PKGINFO").into())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            unix::fs::symlink(fs::read_link(entry.path())?, target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hash a file, or everything under a directory (names, symlink targets and contents).
+fn hash_into(hasher: &mut Sha256, path: &Path, relative: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    hasher.update(relative.as_os_str().as_bytes());
+    hasher.update([0]);
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            hash_into(hasher, &entry.path(), &relative.join(entry.file_name()))?;
+        }
+    } else if metadata.file_type().is_symlink() {
+        hasher.update(fs::read_link(path)?.as_os_str().as_bytes());
+    } else {
+        io::copy(&mut fs::File::open(path)?, hasher)?;
+    }
+    hasher.update([0]);
+    Ok(())
+}
+
+fn read_recipe(path: &Path) -> Result<(String, String), Box<dyn Error>> {
+    let recipe: Recipe = toml::from_str(&fs::read_to_string(path.join(RECIPE_FILE))?)?;
+    Ok((recipe.name, recipe.version))
+}
+
+fn field(
+    path: &Path,
+    fields: &mut HashMap<String, String>,
+    key: &'static str,
+) -> Result<String, LocalError> {
+    fields
+        .remove(key)
+        .ok_or_else(|| LocalError::MissingMetadata(path.display().to_string(), key))
+}
+
+/// Read a local package's name and version.
+pub fn inspect(path: &Path) -> Result<LocalPackage, Box<dyn Error>> {
+    let format = Format::detect(path)?;
+    let (name, version) = match format {
+        Format::Deb => {
+            let mut fields = read_deb(path, None)?;
+            (
+                field(path, &mut fields, "package")?,
+                field(path, &mut fields, "version")?,
+            )
+        }
+        Format::Apk | Format::Pacman => {
+            let mut fields = read_pkginfo(path, None)?;
+            (
+                field(path, &mut fields, "pkgname")?,
+                field(path, &mut fields, "pkgver")?,
+            )
+        }
+        Format::Recipe => read_recipe(path)?,
+    };
+
+    // both end up as directory names in the store, so keep them from going anywhere odd
+    let starts_alphanumeric = |s: &str| s.starts_with(|c: char| c.is_ascii_alphanumeric());
+    if !starts_alphanumeric(&name)
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_+.".contains(c))
+    {
+        return Err(Box::new(LocalError::InvalidName(
+            path.display().to_string(),
+            name,
+        )));
+    }
+    let version = version
+        .parse::<Version>()
+        .map_err(|e| LocalError::InvalidVersion(path.display().to_string(), e))?;
+    if !starts_alphanumeric(&version.to_string()) {
+        return Err(Box::new(LocalError::InvalidVersion(
+            path.display().to_string(),
+            VersionError::InvalidChar(version.to_string().remove(0), version.to_string()),
+        )));
+    }
+
+    let mut hasher = Sha256::new();
+    hash_into(&mut hasher, path, Path::new(""))
+        .map_err(|e| LocalError::Unreadable(path.display().to_string(), e))?;
+
+    Ok(LocalPackage {
+        format,
+        name,
+        version,
+        digest: format!("{:x}", hasher.finalize()),
+    })
+}
+
+/// Unpack a local package's files into a build directory.
+pub fn unpack(path: &Path, dir: &Path) -> Result<(), Box<dyn Error>> {
+    match Format::detect(path)? {
+        Format::Deb => read_deb(path, Some(dir)).map(drop),
+        Format::Apk | Format::Pacman => read_pkginfo(path, Some(dir)).map(drop),
+        Format::Recipe => {
+            let files = path.join("files");
+            if files.is_dir() {
+                copy_tree(&files, dir)?;
+            }
+            Ok(())
+        }
+    }
+}
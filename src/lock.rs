@@ -0,0 +1,255 @@
+use crate::{
+    index::PackageIndex,
+    package::{PackageSpec, Variants},
+    store::Record,
+    version::{Compare, Version},
+};
+use clap::Arg;
+use quick_error::quick_error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LockError {
+        NoLockfile(path: String) {
+            display("--locked was given but there is no lockfile at '{}'", path)
+        }
+        NotLocked(package: String) {
+            display("'{}' isn't pinned by the lockfile", package)
+        }
+        NoLongerAvailable(package: String) {
+            display("locked package {} is no longer in its repo", package)
+        }
+        ChecksumMismatch(package: String, file: String) {
+            display("checksum of '{}' for locked package {} has changed since it was locked", file, package)
+        }
+        RevisionChanged(package: String, locked: String, current: String) {
+            display("locked package {} has no checksums, and its repo has changed since it was locked (from {} to {})",
+                    package, locked, current)
+        }
+        Unverifiable(package: String) {
+            display("locked package {} has neither checksums nor a repo revision to check it against", package)
+        }
+        LocalPackage(package: String) {
+            display("local package '{}' can't be pinned by a lockfile", package)
+        }
+    }
+}
+
+pub fn locked_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("locked")
+        .long("locked")
+        .help("Only install exactly what the lockfile pins, and nothing else")
+}
+
+/// A package pinned to exactly what was installed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub repo: String,
+    pub name: String,
+    pub version: Version,
+    #[serde(default, skip_serializing_if = "Variants::is_empty")]
+    pub variants: Variants,
+    /// The repo's sync revision when the package was locked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl LockedPackage {
+    pub fn new(record: &Record, index: &PackageIndex) -> Self {
+        Self {
+            repo: record.repo.clone(),
+            name: record.name.clone(),
+            version: record.version.clone(),
+            variants: record.variants.clone(),
+            revision: index.revision.clone(),
+            checksums: index
+                .entry(&record.name, &record.version)
+                .map(|e| e.checksums.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn record(&self) -> Record {
+        Record {
+            repo: self.repo.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            variants: self.variants.clone(),
+            installed: false,
+            sandbox: None,
+            source: None,
+            digest: None,
+        }
+    }
+
+    /// Make sure the repo still provides exactly what was locked.
+    pub fn verify(&self, index: &PackageIndex) -> Result<(), LockError> {
+        let record = self.record();
+        let entry = index
+            .entry(&self.name, &self.version)
+            .ok_or_else(|| LockError::NoLongerAvailable(record.to_string()))?;
+
+        for (file, checksum) in &self.checksums {
+            if entry.checksums.get(file) != Some(checksum) {
+                return Err(LockError::ChecksumMismatch(
+                    record.to_string(),
+                    file.clone(),
+                ));
+            }
+        }
+        if !self.checksums.is_empty() {
+            return Ok(());
+        }
+
+        // without checksums, nothing short of the whole repo being unchanged will do
+        match &self.revision {
+            Some(revision) if index.revision.as_ref() == Some(revision) => Ok(()),
+            Some(revision) => Err(LockError::RevisionChanged(
+                record.to_string(),
+                revision.clone(),
+                index.revision.clone().unwrap_or_else(|| "none".to_string()),
+            )),
+            None => Err(LockError::Unverifiable(record.to_string())),
+        }
+    }
+}
+
+/// Exact versions and checksums of a set of packages, written as `storm.lock`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    packages: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    /// Load a lockfile, or `None` if it doesn't exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(s) => Ok(Some(toml::from_str(&s)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Load a lockfile that must exist, for `--locked`.
+    pub fn load_required<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        Self::load(path)?
+            .ok_or_else(|| Box::new(LockError::NoLockfile(path.display().to_string())).into())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut contents =
+            String::from("# This file is generated by storm; don't edit it by hand.\n\n");
+        contents.push_str(&toml::to_string_pretty(self)?);
+        fs::File::create(path)?.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    /// Add a package, replacing any other version of it.
+    pub fn insert(&mut self, package: LockedPackage) {
+        self.packages
+            .retain(|p| p.repo != package.repo || p.name != package.name);
+        self.packages.push(package);
+        self.packages
+            .sort_by(|a, b| (&a.repo, &a.name).cmp(&(&b.repo, &b.name)));
+    }
+
+    /// Find the pinned package a spec refers to, which may leave out a Gentoo category or
+    /// some of the variants it was locked with. Versions are ordered by `compare` for the
+    /// pinned package's repo.
+    pub fn find<F: Fn(&str) -> Compare>(
+        &self,
+        package: &PackageSpec,
+        compare: F,
+    ) -> Result<&LockedPackage, LockError> {
+        self.packages
+            .iter()
+            .find(|p| {
+                package.repo().is_none_or(|r| r == p.repo)
+                    && (package.name() == p.name
+                        || p.name
+                            .rsplit_once('/')
+                            .is_some_and(|(_, n)| n == package.name()))
+                    && package.version().matches_by(&p.version, compare(&p.repo))
+                    && package.variants().is_subset(&p.variants)
+            })
+            .ok_or_else(|| LockError::NotLocked(package.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(repo: &str, name: &str, version: &str, variants: &str) -> LockedPackage {
+        LockedPackage {
+            repo: repo.to_string(),
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            variants: match variants {
+                "" => Variants::default(),
+                variants => variants.parse().unwrap(),
+            },
+            revision: None,
+            checksums: BTreeMap::new(),
+        }
+    }
+
+    fn lockfile() -> Lockfile {
+        let mut lock = Lockfile::default();
+        lock.insert(locked(
+            "gentoo",
+            "media-video/ffmpeg",
+            "4.2.2",
+            "[x264,-vaapi]",
+        ));
+        lock.insert(locked("signed", "libc6", "2.36-9", ""));
+        lock
+    }
+
+    fn find(lock: &Lockfile, spec: &str) -> Option<String> {
+        let spec: PackageSpec = spec.parse().unwrap();
+        lock.find(&spec, |_| Version::compare)
+            .ok()
+            .map(|p| p.record().to_string())
+    }
+
+    #[test]
+    fn finds_packages_by_some_of_their_variants() {
+        let lock = lockfile();
+        let ffmpeg = find(&lock, "gentoo:media-video/ffmpeg[x264,-vaapi]");
+        assert!(ffmpeg.is_some());
+        assert_eq!(find(&lock, "ffmpeg"), ffmpeg);
+        assert_eq!(find(&lock, "ffmpeg[x264]"), ffmpeg);
+        assert_eq!(find(&lock, "ffmpeg@>=4.2,<5"), ffmpeg);
+        assert_eq!(find(&lock, "ffmpeg[vaapi]"), None);
+        assert_eq!(find(&lock, "ffmpeg[x264,doc]"), None);
+        assert_eq!(find(&lock, "other:ffmpeg"), None);
+    }
+
+    #[test]
+    fn compares_pinned_versions_the_way_their_repo_does() {
+        let lock = lockfile();
+        let spec: PackageSpec = "libc6@>=3".parse().unwrap();
+        assert!(lock.find(&spec, |_| Version::compare).is_err());
+
+        // a repo whose ordering is the reverse of storm's
+        let reversed: Compare = |a, b| Version::compare(b, a);
+        let found = lock.find(&spec, |repo| {
+            assert_eq!(repo, "signed");
+            reversed
+        });
+        assert_eq!(found.unwrap().name, "libc6");
+    }
+}
@@ -14,14 +14,43 @@ use std::{
     process,
 };
 
+// Implement Serialize and Deserialize through a type's Display and FromStr impls.
+macro_rules! serde_via_str {
+    ($t:ty) => {
+        impl serde::Serialize for $t {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <String as serde::Deserialize>::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+mod apply;
 mod build;
 mod config;
+mod export_manifest;
+mod index;
+mod info;
 mod install;
 mod list;
+mod local;
+mod lock;
+mod manifest;
 mod package;
 mod repo;
 mod sandbox;
+mod search;
+mod store;
 mod uninstall;
+mod version;
 
 pub type SubCommandArgs = for<'a, 'b> fn(App<'a, 'b>) -> App<'a, 'b>;
 pub type SubCommandFn<T> = fn(&ArgMatches) -> Result<T, Box<dyn Error>>;
@@ -38,11 +67,15 @@ impl<T> Borrow<SubCommandFn<T>> for &SubCommand<T> {
 }
 
 static SUBCOMMANDS: phf::Map<&'static str, &'static SubCommand<()>> = phf_map! {
+    "apply" => &apply::CMD,
     "build" => &build::CMD,
     "config" => &config::CMD,
+    "export-manifest" => &export_manifest::CMD,
+    "info" => &info::CMD,
     "install" => &install::CMD,
     "list" => &list::CMD,
     "repo" => &repo::CMD,
+    "search" => &search::CMD,
     "uninstall" => &uninstall::CMD,
 };
 
@@ -57,7 +90,8 @@ fn main() {
                         .env_os(OsStr::new("STORMPATH"))
                         .long("pkgstore")
                         .short("s")
-                        .required(true)
+                        // global args can't be required; Store::from_args checks for it instead
+                        .global(true)
                         .takes_value(true)
                         .validator_os(|x| {
                             if x.is_empty() {
@@ -110,7 +144,7 @@ fn main() {
                     AppSettings::VersionlessSubcommands,
                 ]),
             |args, (name, subcommand)| {
-                args.subcommand((subcommand.args)(clap::SubCommand::with_name(*name)))
+                args.subcommand((subcommand.args)(clap::SubCommand::with_name(name)))
             },
         )
         .get_matches();
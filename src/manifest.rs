@@ -0,0 +1,87 @@
+use crate::{
+    package::PackageSpec,
+    sandbox::SandboxConfig,
+    store::Record,
+    version::{Constraint, Op, VersionReq},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    io::Write,
+    iter,
+    path::{Path, PathBuf},
+};
+
+/// A package as listed in a manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestPackage {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(default, skip_serializing_if = "VersionReq::is_any")]
+    pub version: VersionReq,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
+    /// Install from a local archive or recipe directory instead of a repo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl ManifestPackage {
+    pub fn spec(&self) -> Result<PackageSpec, Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            return Ok(PackageSpec::local(path.clone()));
+        }
+
+        Ok(PackageSpec::from_parts(
+            self.repo.as_deref(),
+            &self.name,
+            &self.variants,
+            self.version.clone(),
+        )?)
+    }
+}
+
+impl From<&Record> for ManifestPackage {
+    fn from(record: &Record) -> Self {
+        Self {
+            name: record.name.clone(),
+            repo: Some(record.repo.clone()),
+            version: iter::once(Constraint {
+                op: Op::Eq,
+                version: record.version.clone(),
+            })
+            .collect(),
+            variants: record.variants.flags().collect(),
+            sandbox: record.sandbox.clone(),
+            path: record.source.clone(),
+        }
+    }
+}
+
+/// The set of packages that should be installed, usually checked in as `storm.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<ManifestPackage>,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::File::create(path)?.write_all(self.to_toml()?.as_bytes())?;
+        Ok(())
+    }
+}
@@ -1,49 +1,476 @@
-use std::fmt::{Display, Formatter, Result};
+use crate::version::{Constraint, VersionError, VersionReq};
+use quick_error::quick_error;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-#[derive(Debug)]
-pub struct Package<'a> {
-    repo: Option<&'a str>,
-    name: &'a str,
+quick_error! {
+    /// A problem with a package spec. Columns are 1-based character offsets into the spec.
+    #[derive(Debug)]
+    pub enum SpecError {
+        Empty {
+            display("package spec is empty")
+        }
+        EmptyRepo(column: usize) {
+            display("missing repository name before ':' at column {}", column)
+        }
+        EmptyName(column: usize) {
+            display("missing package name at column {}", column)
+        }
+        UnexpectedColon(column: usize) {
+            display("unexpected ':' at column {} (only one repository may be given)", column)
+        }
+        InvalidChar(c: char, column: usize) {
+            display("invalid character {:?} at column {}", c, column)
+        }
+        InvalidSegment(column: usize) {
+            display("empty, '.' or '..' path segment at column {}", column)
+        }
+        UnclosedVariants(column: usize) {
+            display("'[' at column {} is never closed", column)
+        }
+        EmptyVariant(column: usize) {
+            display("missing variant name at column {}", column)
+        }
+        ConflictingVariant(name: String, column: usize) {
+            display("variant '{}' is given more than once at column {}", name, column)
+        }
+        InvalidVersion(err: VersionError, column: usize) {
+            display("{} at column {}", err, column)
+            cause(err)
+        }
+    }
+}
+
+impl SpecError {
+    pub fn column(&self) -> usize {
+        match *self {
+            SpecError::Empty => 1,
+            SpecError::EmptyRepo(column)
+            | SpecError::EmptyName(column)
+            | SpecError::UnexpectedColon(column)
+            | SpecError::InvalidChar(_, column)
+            | SpecError::InvalidSegment(column)
+            | SpecError::UnclosedVariants(column)
+            | SpecError::EmptyVariant(column)
+            | SpecError::ConflictingVariant(_, column)
+            | SpecError::InvalidVersion(_, column) => column,
+        }
+    }
 }
 
-impl<'a> Package<'a> {
-    pub fn new(name: &'a str) -> Self {
-        Self { repo: None, name }
+quick_error! {
+    /// A `SpecError` along with the spec it came from, displayed with a marker under the problem.
+    #[derive(Debug)]
+    pub enum InvalidSpec {
+        Spec(spec: String, err: SpecError) {
+            display("invalid package spec: {}\n    {}\n    {:>width$}", err, spec, "^", width = err.column())
+            cause(err)
+        }
+    }
+}
+
+/// Build-time variants (Gentoo USE flags), each enabled or disabled. They are kept sorted
+/// by name so the same set always prints, and builds, the same way.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variants(BTreeMap<String, bool>);
+
+impl Variants {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.0
+            .iter()
+            .map(|(name, &enabled)| (name.as_str(), enabled))
     }
 
-    pub fn with_repo(repo: &'a str, name: &'a str) -> Self {
+    /// Each variant as a flag, e.g. `x264` or `-vaapi`.
+    pub fn flags(&self) -> impl Iterator<Item = String> + '_ {
+        self.iter().map(|(name, enabled)| {
+            if enabled {
+                name.to_string()
+            } else {
+                format!("-{}", name)
+            }
+        })
+    }
+
+    /// Whether every variant in `self` is set the same way in `other`.
+    pub fn is_subset(&self, other: &Variants) -> bool {
+        self.0
+            .iter()
+            .all(|(name, enabled)| other.0.get(name) == Some(enabled))
+    }
+
+    fn parse(s: &str, offset: usize) -> Result<Self, SpecError> {
+        let mut variants = BTreeMap::new();
+        let mut start = 0;
+        for variant in s.split(',') {
+            let (name, enabled, name_start) = match variant.strip_prefix('-') {
+                Some(name) => (name, false, start + 1),
+                None => (variant, true, start),
+            };
+
+            match name.chars().next() {
+                None => return Err(SpecError::EmptyVariant(offset + column(s, name_start))),
+                Some(c) if !c.is_ascii_alphanumeric() => {
+                    return Err(SpecError::InvalidChar(c, offset + column(s, name_start)))
+                }
+                Some(_) => {}
+            }
+            check_chars(name, offset + column(s, name_start) - 1, |c| {
+                c.is_ascii_alphanumeric() || "+_-".contains(c)
+            })?;
+            if variants.insert(name.to_string(), enabled).is_some() {
+                return Err(SpecError::ConflictingVariant(
+                    name.to_string(),
+                    offset + column(s, start),
+                ));
+            }
+
+            start += variant.len() + 1;
+        }
+
+        Ok(Self(variants))
+    }
+}
+
+impl Display for Variants {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.flags().collect::<Vec<_>>().join(","))
+    }
+}
+
+impl FromStr for Variants {
+    type Err = SpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(inner) => Self::parse(inner, 1),
+            None => Err(SpecError::UnclosedVariants(1)),
+        }
+    }
+}
+
+serde_via_str!(Variants);
+
+/// A request for a package, written as `[repo:]name[[variant,...]][@constraint[,constraint...]]`,
+/// e.g. `gentoo:ffmpeg[x264,-vaapi]@>=4.2`.
+///
+/// A repo of `_` is the same as leaving it out. A local package (an archive or recipe
+/// directory) is given as `file:path`, or just as a path starting with `/`, `./` or `../`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    repo: Option<String>,
+    name: String,
+    variants: Variants,
+    version: VersionReq,
+    path: Option<PathBuf>,
+}
+
+impl PackageSpec {
+    /// A spec for a local package (an archive or recipe directory).
+    pub fn local(path: PathBuf) -> Self {
         Self {
-            repo: Some(repo),
-            name,
+            repo: None,
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            variants: Variants::default(),
+            version: VersionReq::default(),
+            path: Some(path),
         }
     }
 
-    pub fn parse(s: &'a str) -> Self {
-        match s.find(":") {
-            Some(idx) if s[..idx] == *"_" => Self {
-                repo: None,
-                name: &s[idx + 1..],
-            },
-            Some(idx) => Self {
-                repo: Some(&s[..idx]),
-                name: &s[idx + 1..],
-            },
-            None => Self {
-                repo: None,
-                name: s,
-            },
+    /// Build a spec from its parts, as they're given in a manifest. An invalid part is shown
+    /// on its own, with the problem marked.
+    pub fn from_parts(
+        repo: Option<&str>,
+        name: &str,
+        variants: &[String],
+        version: VersionReq,
+    ) -> Result<Self, InvalidSpec> {
+        let invalid = |part: &str, err| InvalidSpec::Spec(part.to_string(), err);
+
+        if let Some(repo) = repo {
+            check_repo(repo, 0).map_err(|e| invalid(repo, e))?;
         }
+        check_name(name, 0).map_err(|e| invalid(name, e))?;
+
+        let mut parsed = BTreeMap::new();
+        for flag in variants {
+            for (variant, enabled) in Variants::parse(flag, 0).map_err(|e| invalid(flag, e))?.0 {
+                if parsed.insert(variant.clone(), enabled).is_some() {
+                    return Err(invalid(flag, SpecError::ConflictingVariant(variant, 1)));
+                }
+            }
+        }
+
+        Ok(Self {
+            repo: repo.filter(|&r| r != "_").map(str::to_string),
+            name: name.to_string(),
+            variants: Variants(parsed),
+            version,
+            path: None,
+        })
+    }
+
+    /// The archive or recipe directory a local package comes from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn repo(&self) -> Option<&str> {
+        self.repo.as_deref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variants(&self) -> &Variants {
+        &self.variants
+    }
+
+    pub fn version(&self) -> &VersionReq {
+        &self.version
+    }
+}
+
+fn column(s: &str, byte_idx: usize) -> usize {
+    s[..byte_idx].chars().count() + 1
+}
+
+fn check_chars(s: &str, offset: usize, valid: fn(char) -> bool) -> Result<(), SpecError> {
+    match s.char_indices().find(|&(_, c)| !valid(c)) {
+        Some((idx, ':')) => Err(SpecError::UnexpectedColon(offset + column(s, idx))),
+        Some((idx, c)) => Err(SpecError::InvalidChar(c, offset + column(s, idx))),
+        None => Ok(()),
+    }
+}
+
+fn check_repo(repo: &str, offset: usize) -> Result<(), SpecError> {
+    check_chars(repo, offset, |c| {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    })
+}
+
+fn check_name(name: &str, offset: usize) -> Result<(), SpecError> {
+    if name.is_empty() {
+        return Err(SpecError::EmptyName(offset + 1));
+    }
+    check_chars(name, offset, |c| {
+        c.is_ascii_alphanumeric() || "-_+./".contains(c)
+    })?;
+
+    // names end up as paths in the store, so they can't lead anywhere else
+    let mut start = 0;
+    for segment in name.split('/') {
+        if ["", ".", ".."].contains(&segment) {
+            return Err(SpecError::InvalidSegment(offset + column(name, start)));
+        }
+        start += segment.len() + 1;
+    }
+    Ok(())
+}
+
+/// Check a package name from somewhere other than a spec, like a repo's index.
+pub fn check_package_name(name: &str) -> Result<(), SpecError> {
+    check_name(name, 0)
+}
+
+fn parse_version(s: &str, offset: usize) -> Result<VersionReq, SpecError> {
+    let mut start = 0;
+    let mut constraints = Vec::new();
+    for constraint in s.split(',') {
+        let err_column = |err: &VersionError| {
+            let idx = match err {
+                VersionError::InvalidChar(c, _) => constraint.find(*c).unwrap_or(0),
+                _ => 0,
+            };
+            offset + column(s, start + idx)
+        };
+
+        constraints.push(
+            constraint
+                .parse::<Constraint>()
+                .map_err(|e| SpecError::InvalidVersion(e.clone(), err_column(&e)))?,
+        );
+        start += constraint.len() + 1;
+    }
+
+    Ok(constraints.into_iter().collect())
+}
+
+impl FromStr for PackageSpec {
+    type Err = SpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(SpecError::Empty);
+        }
+
+        // paths may contain anything, so they aren't split into variants or versions
+        match s.strip_prefix("file:") {
+            Some("") => return Err(SpecError::EmptyName(6)),
+            Some(path) => return Ok(Self::local(path.into())),
+            None if s.starts_with('/') || s.starts_with("./") || s.starts_with("../") => {
+                return Ok(Self::local(s.into()))
+            }
+            None => {}
+        }
+
+        let (head, version) = match s.find('@') {
+            Some(idx) => (&s[..idx], parse_version(&s[idx + 1..], column(s, idx))?),
+            None => (s, VersionReq::default()),
+        };
+
+        let (head, variants) = match head.find('[') {
+            Some(idx) => match head[idx + 1..].strip_suffix(']') {
+                Some(inner) => (&head[..idx], Variants::parse(inner, column(s, idx))?),
+                None => return Err(SpecError::UnclosedVariants(column(s, idx))),
+            },
+            None => (head, Variants::default()),
+        };
+
+        let (repo, name, name_offset) = match head.find(':') {
+            Some(0) => return Err(SpecError::EmptyRepo(1)),
+            Some(idx) => {
+                let repo = &head[..idx];
+                check_repo(repo, 0)?;
+                let repo = if repo == "_" {
+                    None
+                } else {
+                    Some(repo.to_string())
+                };
+                (repo, &head[idx + 1..], column(head, idx))
+            }
+            None => (None, head, 0),
+        };
+
+        check_name(name, name_offset)?;
+
+        Ok(Self {
+            repo,
+            name: name.to_string(),
+            variants,
+            version,
+            path: None,
+        })
     }
 }
 
-impl<'a> Display for Package<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        if let Some(repo) = self.repo {
-            write!(f, "{}:{}", repo, self.name)
-        } else if self.name.contains(":") {
-            write!(f, "<no repo>:{}", self.name)
-        } else {
-            write!(f, "{}", self.name)
+impl Display for PackageSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            return write!(f, "file:{}", path.display());
+        }
+
+        if let Some(repo) = &self.repo {
+            write!(f, "{}:", repo)?;
         }
+
+        f.write_str(&self.name)?;
+
+        if !self.variants.is_empty() {
+            write!(f, "{}", self.variants)?;
+        }
+
+        if !self.version.is_any() {
+            write!(f, "@{}", self.version)?;
+        }
+
+        Ok(())
+    }
+}
+
+serde_via_str!(PackageSpec);
+
+/// Parse every spec up front so a typo fails the whole command before anything happens.
+pub fn parse_specs<'a, I: IntoIterator<Item = &'a str>>(
+    specs: I,
+) -> Result<Vec<PackageSpec>, Box<dyn Error>> {
+    specs
+        .into_iter()
+        .map(|s| {
+            s.parse()
+                .map_err(|e| -> Box<dyn Error> { Box::new(InvalidSpec::Spec(s.to_string(), e)) })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err(s: &str) -> SpecError {
+        s.parse::<PackageSpec>().unwrap_err()
+    }
+
+    #[test]
+    fn parses_full_spec() {
+        let spec: PackageSpec = "gentoo:ffmpeg[x264,-vaapi]@>=4.2,<5".parse().unwrap();
+        assert_eq!(spec.repo(), Some("gentoo"));
+        assert_eq!(spec.name(), "ffmpeg");
+        assert_eq!(spec.variants().to_string(), "[-vaapi,x264]");
+        assert_eq!(spec.version().to_string(), ">=4.2,<5");
+        assert_eq!(spec.to_string(), "gentoo:ffmpeg[-vaapi,x264]@>=4.2,<5");
+    }
+
+    #[test]
+    fn parses_local_and_default_repo_specs() {
+        let spec: PackageSpec = "_:hello".parse().unwrap();
+        assert_eq!(spec.repo(), None);
+        let spec: PackageSpec = "./pkgs/hello-1.0.tar.gz".parse().unwrap();
+        assert_eq!(spec.path(), Some(Path::new("./pkgs/hello-1.0.tar.gz")));
+        assert_eq!(spec.name(), "hello-1.0.tar.gz");
+        let spec: PackageSpec = "file:hello@1".parse().unwrap();
+        assert_eq!(spec.path(), Some(Path::new("hello@1")));
+    }
+
+    #[test]
+    fn reports_error_columns() {
+        assert!(matches!(err(""), SpecError::Empty));
+        assert!(matches!(err(":hello"), SpecError::EmptyRepo(1)));
+        assert!(matches!(err("gentoo:"), SpecError::EmptyName(8)));
+        assert!(matches!(err("file:"), SpecError::EmptyName(6)));
+        assert!(matches!(err("a:b:c"), SpecError::UnexpectedColon(4)));
+        assert!(matches!(err("hel*lo"), SpecError::InvalidChar('*', 4)));
+        assert!(matches!(
+            err("gentoo:../../etc"),
+            SpecError::InvalidSegment(8)
+        ));
+        assert!(matches!(err("gentoo:/etc"), SpecError::InvalidSegment(8)));
+        assert!(matches!(
+            err("app-misc/./hello"),
+            SpecError::InvalidSegment(10)
+        ));
+        assert!(matches!(err("app-misc/"), SpecError::InvalidSegment(10)));
+        assert!(matches!(err("ffmpeg[x264"), SpecError::UnclosedVariants(7)));
+        assert!(matches!(err("ffmpeg[x264,]"), SpecError::EmptyVariant(13)));
+        assert!(matches!(
+            err("ffmpeg[x264,-x264]"),
+            SpecError::ConflictingVariant(_, 13)
+        ));
+        assert!(matches!(
+            err("hello@>=1.0,<2/3"),
+            SpecError::InvalidVersion(VersionError::InvalidChar('/', _), 15)
+        ));
+    }
+
+    #[test]
+    fn marks_the_error_column() {
+        let err = parse_specs(["hel*lo"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid package spec: invalid character '*' at column 4\n    hel*lo\n       ^"
+        );
     }
 }
@@ -1,4 +1,12 @@
-use crate::config::Config;
+use crate::{
+    config::Config,
+    index::PackageIndex,
+    local::LOCAL_REPO,
+    package::{self, PackageSpec},
+    sandbox::SandboxConfig,
+    store::{Record, Store},
+    version::{Compare, Version},
+};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use phf::phf_map;
 use quick_error::quick_error;
@@ -6,30 +14,146 @@ use serde::{
     de::{self, SeqAccess, Visitor},
     Deserialize, Deserializer, Serialize,
 };
-use std::{borrow::Borrow, collections::HashMap, error::Error, fmt, iter, marker::PhantomData};
+use std::{
+    borrow::Borrow, collections::HashMap, error::Error, fmt, fs, io, iter, marker::PhantomData,
+    path::Path,
+};
 
 quick_error! {
     #[derive(Debug)]
     pub enum RepoError {
-        NoSuchRepo {
-            display("no repo exists with the specified name")
+        NoSuchRepo(name: String) {
+            display("no repo named '{}' exists", name)
+        }
+        NotSynced(name: String) {
+            display("repo '{}' hasn't been synced yet; run 'storm repo sync {}'", name, name)
+        }
+        IndexUnreadable(name: String, err: Box<dyn Error>) {
+            display("couldn't read the package index for repo '{}': {}", name, err)
+        }
+        NoDefaultRepos(package: String) {
+            display("no repo was specified for '{}' and no default repos are set", package)
+        }
+        NotInDefaults(package: String, considered: Vec<(String, RepoError)>) {
+            display("no default repo provides '{}':{}", package,
+                    considered.iter().map(|(_, e)| format!("\n    {}", e)).collect::<String>())
+        }
+        VariantsUnsupported(repo: String, package: String) {
+            display("repo '{}' doesn't support build variants (requested by '{}')", repo, package)
+        }
+        NotProvided(repo: String, package: String) {
+            display("repo '{}' doesn't provide '{}'", repo, package)
+        }
+        AmbiguousName(repo: String, package: String, candidates: Vec<String>) {
+            display("'{}' is ambiguous in repo '{}'; it could be {}", package, repo, candidates.join(" or "))
+        }
+        ReservedName(name: String) {
+            display("'{}' can't be used as a repo name", name)
+        }
+        InvalidName(name: String) {
+            display("invalid repo name '{}' (names can't be empty, contain '/' or start with '.')", name)
+        }
+        ProfileUnreadable(name: String, err: Box<dyn Error>) {
+            display("couldn't read the profile for repo '{}': {}", name, err)
+        }
+        AllMasked(repo: String, package: String, masked: Vec<(Version, String)>) {
+            display("repo '{}' only has masked versions of '{}':{}", repo, package,
+                    masked.iter().map(|(v, reason)| format!("\n        {}: {}", v, reason)).collect::<String>())
+        }
+        NoMatchingVersion(package: String, available: Vec<Version>) {
+            display("no available version of '{}' matches (found {})", package,
+                    available.iter().map(Version::to_string).collect::<Vec<_>>().join(", "))
         }
     }
 }
 
+mod alpine;
+mod debian;
 mod dummy;
+mod fetch;
 mod gentoo;
+mod solver;
+
+/// Repo names are used as directory names in the store, so they can't reach outside it.
+/// `file:` specs always name a local package, so a repo called `file` could never be used.
+fn check_name(name: &str) -> Result<(), RepoError> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        Err(RepoError::InvalidName(name.to_string()))
+    } else if name == LOCAL_REPO {
+        Err(RepoError::ReservedName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase", tag = "type")]
 enum Repo {
+    Alpine(alpine::AlpineRepo),
+    Debian(debian::DebianRepo),
     Dummy(dummy::DummyRepo),
     Gentoo(gentoo::GentooRepo),
 }
 
+/// Leave packages out of a synced index if their names couldn't be used safely as paths in
+/// the store (or written in a spec).
+fn drop_invalid_names(repo_name: &str, index: &mut PackageIndex) {
+    index.retain(|name| match package::check_package_name(name) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!(
+                "warning: skipping '{}' in repo '{}': {}",
+                name, repo_name, e
+            );
+            false
+        }
+    });
+}
+
+impl Repo {
+    /// Sync the repo, where `gentoo` are the other Gentoo repos an overlay's masters can be.
+    fn sync(
+        &self,
+        store: &Store,
+        gentoo: &[&gentoo::GentooRepo],
+    ) -> Result<PackageIndex, Box<dyn Error>> {
+        match self {
+            Repo::Alpine(repo) => repo.sync(),
+            Repo::Debian(repo) => repo.sync(),
+            Repo::Dummy(repo) => Ok(repo.sync()),
+            Repo::Gentoo(repo) => repo.sync(store, gentoo),
+        }
+    }
+
+    fn supports_variants(&self) -> bool {
+        match self {
+            Repo::Alpine(_) | Repo::Debian(_) | Repo::Dummy(_) => false,
+            Repo::Gentoo(_) => true,
+        }
+    }
+
+    fn verify(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Repo::Alpine(_) | Repo::Debian(_) | Repo::Dummy(_) => Ok(()),
+            Repo::Gentoo(repo) => repo.verify(),
+        }
+    }
+
+    /// How the repo orders its versions.
+    fn compare(&self) -> Compare {
+        match self {
+            Repo::Alpine(_) => alpine::version::compare,
+            Repo::Debian(_) => debian::version::compare,
+            Repo::Dummy(_) | Repo::Gentoo(_) => Version::compare,
+        }
+    }
+}
+
 static ADD_SUBCOMMANDS: phf::Map<&'static str, &'static crate::SubCommand<Repo>> = phf_map! {
+    "alpine" => &alpine::CMD,
+    "debian" => &debian::CMD,
     "dummy" => &dummy::CMD,
-    //"gentoo" => &gentoo::CMD,
+    "gentoo" => &gentoo::CMD,
 };
 
 // This deserializer parses a single string as an array with a single string.
@@ -63,6 +187,20 @@ where
     deserializer.deserialize_any(StringOrSeq(PhantomData))
 }
 
+#[derive(Debug)]
+pub struct Resolution {
+    pub repo: String,
+    /// The package's full name in `repo`, e.g. with the category a Gentoo spec left out.
+    pub name: String,
+    pub version: Version,
+    /// Default repos that were checked and passed over before `repo`, and why.
+    pub considered: Vec<(String, RepoError)>,
+    /// Lower-precedence default repos that weren't checked, since `repo` came first.
+    pub not_consulted: Vec<String>,
+    /// Newer versions in `repo` that matched but are masked, and why.
+    pub masked: Vec<(Version, String)>,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RepoConfig {
     #[serde(deserialize_with = "string_or_seq", rename = "default")]
@@ -88,7 +226,11 @@ impl RepoConfig {
     }
 
     fn add(&mut self, name: String, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+        check_name(&name)?;
         let repo = crate::run_subcommand(&ADD_SUBCOMMANDS, args)?;
+        if let Repo::Gentoo(repo) = &repo {
+            self.gentoo_masters(&name, repo)?;
+        }
 
         self.repos.insert(name, repo);
 
@@ -96,8 +238,8 @@ impl RepoConfig {
     }
 
     fn remove<T: Borrow<str>>(&mut self, name: T) -> Result<(), Box<dyn Error>> {
-        if let None = self.repos.remove(name.borrow()) {
-            return Err(Box::new(RepoError::NoSuchRepo));
+        if self.repos.remove(name.borrow()).is_none() {
+            return Err(Box::new(RepoError::NoSuchRepo(name.borrow().to_string())));
         }
 
         self.default_repos.retain(|r| r != name.borrow());
@@ -110,16 +252,17 @@ impl RepoConfig {
         old_name: O,
         new_name: N,
     ) -> Result<(), Box<dyn Error>> {
+        check_name(new_name.borrow())?;
         let repo = self
             .repos
             .remove(old_name.borrow())
-            .ok_or(RepoError::NoSuchRepo)?;
+            .ok_or_else(|| RepoError::NoSuchRepo(old_name.borrow().to_string()))?;
 
         // TODO: use unwrap_none when stable
-        if !self
+        if self
             .repos
             .insert(new_name.borrow().to_string(), repo)
-            .is_none()
+            .is_some()
         {
             panic!();
         }
@@ -140,7 +283,7 @@ impl RepoConfig {
         first: bool,
     ) -> Result<(), Box<dyn Error>> {
         if !self.repos.contains_key(&name) {
-            return Err(Box::new(RepoError::NoSuchRepo));
+            return Err(Box::new(RepoError::NoSuchRepo(name)));
         }
 
         self.default_repos.retain(|r| r != &name);
@@ -156,8 +299,330 @@ impl RepoConfig {
         Ok(())
     }
 
-    fn sync(&self) -> Result<(), Box<dyn Error>> {
-        unimplemented!()
+    /// Add the repos from a Portage repos.conf as default repos, higher priorities first.
+    fn import_portage(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut imported = gentoo::repos_conf::import(path)?;
+        imported.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let mut added = Vec::new();
+        for gentoo::repos_conf::ImportedRepo { name, repo, .. } in imported {
+            if self.repos.contains_key(&name) {
+                eprintln!("warning: not importing repo '{}': it already exists", name);
+                continue;
+            }
+            if let Err(e) = check_name(&name) {
+                eprintln!("warning: not importing repo '{}': {}", name, e);
+                continue;
+            }
+
+            println!("imported {}", name);
+            self.repos.insert(name.clone(), Repo::Gentoo(repo));
+            self.default_repos.push(name.clone());
+            added.push(name);
+        }
+
+        // masters are usually imported alongside their overlays, so only check once all are in
+        for name in added {
+            if let Some(Repo::Gentoo(repo)) = self.repos.get(&name) {
+                if let Err(e) = self.gentoo_masters(&name, repo) {
+                    eprintln!("warning: repo '{}' can't be used yet: {}", name, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The other Gentoo repos a Gentoo overlay uses eclasses and profiles from.
+    fn gentoo_masters(
+        &self,
+        name: &str,
+        repo: &gentoo::GentooRepo,
+    ) -> Result<Vec<&gentoo::GentooRepo>, Box<dyn Error>> {
+        repo.masters(self.other_gentoo(name))
+    }
+
+    /// Every Gentoo repo besides the named one.
+    fn other_gentoo(&self, name: &str) -> Vec<&gentoo::GentooRepo> {
+        self.repos
+            .iter()
+            .filter_map(|(other, r)| match r {
+                Repo::Gentoo(r) if other != name => Some(r),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.repos.contains_key(name)
+    }
+
+    /// How a repo orders its versions, for finding the newest.
+    pub fn compare(&self, name: &str) -> Compare {
+        self.repos
+            .get(name)
+            .map_or(Version::compare as Compare, Repo::compare)
+    }
+
+    /// Every repo name, default repos first (in order of precedence) and the rest sorted.
+    pub fn by_precedence(&self) -> Vec<&str> {
+        let mut others = self
+            .repos
+            .keys()
+            .filter(|r| !self.default_repos.contains(r))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        others.sort_unstable();
+
+        self.default_repos
+            .iter()
+            .map(String::as_str)
+            .chain(others)
+            .collect()
+    }
+
+    /// Load a repo's package index from its last sync.
+    pub fn index(&self, store: &Store, repo_name: &str) -> Result<PackageIndex, RepoError> {
+        PackageIndex::load(store.index_path(repo_name))
+            .map_err(|e| RepoError::IndexUnreadable(repo_name.to_string(), e))?
+            .ok_or_else(|| RepoError::NotSynced(repo_name.to_string()))
+    }
+
+    /// Why each version of a package in a repo is masked, for repos that can mask them.
+    fn masked(
+        &self,
+        repo_name: &str,
+        repo: &Repo,
+        name: &str,
+        package: &PackageSpec,
+        index: &PackageIndex,
+    ) -> Result<Vec<(Version, String)>, RepoError> {
+        match repo {
+            Repo::Alpine(_) | Repo::Debian(_) | Repo::Dummy(_) => Ok(Vec::new()),
+            Repo::Gentoo(gentoo) => self
+                .gentoo_masters(repo_name, gentoo)
+                .and_then(|masters| {
+                    gentoo.masked(&masters, name, index.get(name), package.variants())
+                })
+                .map_err(|e| RepoError::ProfileUnreadable(repo_name.to_string(), e)),
+        }
+    }
+
+    /// The name a repo's index has a package under. Gentoo packages can be named without
+    /// their category, as long as only one category has a package by that name.
+    fn full_name(
+        repo_name: &str,
+        repo: &Repo,
+        index: &PackageIndex,
+        name: &str,
+    ) -> Result<String, RepoError> {
+        if !matches!(repo, Repo::Gentoo(_)) || name.contains('/') {
+            return Ok(name.to_string());
+        }
+
+        let candidates = index
+            .names()
+            .filter(|full| full.rsplit('/').next() == Some(name))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        match candidates.len() {
+            0 => Err(RepoError::NotProvided(
+                repo_name.to_string(),
+                name.to_string(),
+            )),
+            1 => Ok(candidates.into_iter().next().unwrap()),
+            _ => Err(RepoError::AmbiguousName(
+                repo_name.to_string(),
+                name.to_string(),
+                candidates,
+            )),
+        }
+    }
+
+    /// Find a package's full name and newest usable version in a repo, along with the newer
+    /// versions that are masked. The other repos in the resolution are left empty.
+    fn resolve_in(
+        &self,
+        store: &Store,
+        repo_name: &str,
+        package: &PackageSpec,
+    ) -> Result<Resolution, RepoError> {
+        let repo = self
+            .repos
+            .get(repo_name)
+            .ok_or_else(|| RepoError::NoSuchRepo(repo_name.to_string()))?;
+
+        if !package.variants().is_empty() && !repo.supports_variants() {
+            return Err(RepoError::VariantsUnsupported(
+                repo_name.to_string(),
+                package.to_string(),
+            ));
+        }
+
+        let index = self.index(store, repo_name)?;
+        let name = Self::full_name(repo_name, repo, &index, package.name())?;
+        let versions = index.versions(&name);
+        if versions.is_empty() {
+            return Err(RepoError::NotProvided(
+                repo_name.to_string(),
+                package.name().to_string(),
+            ));
+        }
+        let compare = repo.compare();
+        if package.version().best_by(&versions, compare).is_none() {
+            return Err(RepoError::NoMatchingVersion(package.to_string(), versions));
+        }
+
+        let mut masked = self.masked(repo_name, repo, &name, package, &index)?;
+        masked.retain(|(version, _)| package.version().matches_by(version, compare));
+        let usable = versions
+            .iter()
+            .filter(|v| !masked.iter().any(|(masked, _)| masked == *v));
+        match package.version().best_by(usable, compare) {
+            Some(version) => {
+                masked.retain(|(masked, _)| masked > version);
+                masked.sort_by(|a, b| b.0.cmp(&a.0));
+                Ok(Resolution {
+                    repo: repo_name.to_string(),
+                    name,
+                    version: version.clone(),
+                    considered: Vec::new(),
+                    not_consulted: Vec::new(),
+                    masked,
+                })
+            }
+            None => {
+                masked.sort_by(|a, b| b.0.cmp(&a.0));
+                Err(RepoError::AllMasked(
+                    repo_name.to_string(),
+                    package.to_string(),
+                    masked,
+                ))
+            }
+        }
+    }
+
+    /// Find the repo and newest version that satisfy a package spec. Specs without a repo
+    /// go to the first default repo (in order of precedence) with a matching version.
+    pub fn resolve(
+        &self,
+        store: &Store,
+        package: &PackageSpec,
+    ) -> Result<Resolution, Box<dyn Error>> {
+        if let Some(repo) = package.repo() {
+            return Ok(self.resolve_in(store, repo, package)?);
+        }
+
+        if self.default_repos.is_empty() {
+            return Err(Box::new(RepoError::NoDefaultRepos(package.to_string())));
+        }
+
+        let mut considered = Vec::new();
+        for (i, repo) in self.default_repos.iter().enumerate() {
+            match self.resolve_in(store, repo, package) {
+                Ok(resolution) => {
+                    return Ok(Resolution {
+                        considered,
+                        not_consulted: self.default_repos[i + 1..].to_vec(),
+                        ..resolution
+                    })
+                }
+                Err(e) => considered.push((repo.clone(), e)),
+            }
+        }
+
+        Err(Box::new(RepoError::NotInDefaults(
+            package.to_string(),
+            considered,
+        )))
+    }
+
+    /// Have a record's repo build it into `dir`, in the given sandbox.
+    pub fn build(
+        &self,
+        store: &Store,
+        record: &Record,
+        sandbox: &SandboxConfig,
+        dir: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let repo = self
+            .repos
+            .get(&record.repo)
+            .ok_or_else(|| RepoError::NoSuchRepo(record.repo.clone()))?;
+        // downloaded packages are kept like in apt's and apk's caches, for rebuilding later
+        let archives = store.repo_dir(&record.repo).join("archives");
+        let not_provided = || RepoError::NotProvided(record.repo.clone(), record.to_string());
+        match repo {
+            Repo::Alpine(alpine) => {
+                let index = self.index(store, &record.repo)?;
+                let entry = index
+                    .entry(&record.name, &record.version)
+                    .ok_or_else(not_provided)?;
+                alpine.build(&index, &record.name, entry, &archives, dir)
+            }
+            Repo::Debian(debian) => {
+                let index = self.index(store, &record.repo)?;
+                let entry = index
+                    .entry(&record.name, &record.version)
+                    .ok_or_else(not_provided)?;
+                debian.build(&index, &record.name, entry, &archives, dir)
+            }
+            Repo::Dummy(_) => Ok(()),
+            Repo::Gentoo(gentoo) => gentoo.build(
+                &self.gentoo_masters(&record.repo, gentoo)?,
+                &record.name,
+                &record.version,
+                &record.variants,
+                sandbox,
+                dir,
+            ),
+        }
+    }
+
+    /// Sync the named repos (or all of them), refreshing their package indexes.
+    fn sync(&self, store: &Store, names: &[&str]) -> Result<(), Box<dyn Error>> {
+        let names = if names.is_empty() {
+            self.by_precedence()
+        } else {
+            names.to_vec()
+        };
+
+        let save = |name: &str, mut index: PackageIndex| -> Result<(), Box<dyn Error>> {
+            drop_invalid_names(name, &mut index);
+            index.save(store.index_path(name))?;
+            match &index.revision {
+                Some(revision) => {
+                    println!("synced {} at {} ({} packages)", name, revision, index.len())
+                }
+                None => println!("synced {} ({} packages)", name, index.len()),
+            }
+            Ok(())
+        };
+
+        // overlays can't be put in place before their masters, which may only be synced later
+        // on, so those are tried again at the end
+        let mut overlays = Vec::new();
+        for name in names {
+            let repo = self
+                .repos
+                .get(name)
+                .ok_or_else(|| RepoError::NoSuchRepo(name.to_string()))?;
+
+            match repo.sync(store, &self.other_gentoo(name)) {
+                Err(e) if gentoo::is_missing_master(&*e) => overlays.push((name, repo)),
+                index => save(name, index?)?,
+            }
+        }
+
+        for (name, repo) in overlays {
+            save(name, repo.sync(store, &self.other_gentoo(name))?)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -202,7 +667,7 @@ fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
                             .default_value("last"),
                     ),
                 |args, (name, subcommand)| {
-                    args.subcommand((subcommand.args)(SubCommand::with_name(*name)))
+                    args.subcommand((subcommand.args)(SubCommand::with_name(name)))
                 },
             ),
         )
@@ -248,6 +713,21 @@ fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
                 .about("Sync repositories")
                 .arg(Arg::with_name("repo").multiple(true).index(1)),
         )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check a synced repository's files against its checksums")
+                .arg(Arg::with_name("repo").required(true).index(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("import-portage")
+                .about("Add the repositories configured for Portage")
+                .arg(
+                    Arg::with_name("path")
+                        .index(1)
+                        .default_value("/etc/portage/repos.conf")
+                        .help("repos.conf file or directory to import"),
+                ),
+        )
 }
 
 fn list(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -271,7 +751,7 @@ fn add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
 
     config
         .repo
-        .add(args.value_of("name").unwrap().to_string(), &args)?;
+        .add(args.value_of("name").unwrap().to_string(), args)?;
 
     config.save()
 }
@@ -279,9 +759,19 @@ fn add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
 fn remove(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let mut config = Config::load()?;
 
-    config.repo.remove(args.value_of("repo").unwrap())?;
+    let name = args.value_of("repo").unwrap();
+    config.repo.remove(name)?;
+    config.save()?;
 
-    config.save()
+    // forget the repo's index too, so a new repo with the same name starts fresh (unless the
+    // name was edited into the config by hand, and isn't safe to use as a path)
+    if check_name(name).is_err() {
+        return Ok(());
+    }
+    match fs::remove_dir_all(Store::from_args(args)?.repo_dir(name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Box::new(e)),
+        _ => Ok(()),
+    }
 }
 
 fn rename(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -291,8 +781,13 @@ fn rename(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let new_name = args.value_of("new").unwrap();
 
     config.repo.rename(old_name, new_name)?;
+    config.save()?;
 
-    config.save()
+    let store = Store::from_args(args)?;
+    match fs::rename(store.repo_dir(old_name), store.repo_dir(new_name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Box::new(e)),
+        _ => Ok(()),
+    }
 }
 
 fn set_default(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -316,8 +811,40 @@ fn set_default(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     config.save()
 }
 
-fn sync(_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    Config::load()?.repo.sync()
+fn sync(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let store = Store::from_args(args)?;
+    let names = args
+        .values_of("repo")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    Config::load()?.repo.sync(&store, &names)
+}
+
+fn verify(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let store = Store::from_args(args)?;
+    let name = args.value_of("repo").unwrap();
+
+    let config = Config::load()?;
+    let repo = config
+        .repo
+        .repos
+        .get(name)
+        .ok_or_else(|| RepoError::NoSuchRepo(name.to_string()))?;
+    // a repo that was never synced has nothing to verify yet
+    config.repo.index(&store, name)?;
+    repo.verify()
+}
+
+fn import_portage(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut config = Config::load()?;
+
+    config
+        .repo
+        .import_portage(Path::new(args.value_of_os("path").unwrap()))?;
+
+    config.save()
 }
 
 static SUBCOMMANDS: phf::Map<&'static str, crate::SubCommandFn<()>> = phf_map! {
@@ -327,6 +854,8 @@ static SUBCOMMANDS: phf::Map<&'static str, crate::SubCommandFn<()>> = phf_map! {
     "rename" => rename,
     "set-default" => set_default,
     "sync" => sync,
+    "verify" => verify,
+    "import-portage" => import_portage,
 };
 
 fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -334,3 +863,53 @@ fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
 }
 
 pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexEntry;
+
+    fn index(names: &[&str]) -> PackageIndex {
+        let mut index = PackageIndex::default();
+        for name in names {
+            index.insert(name.to_string(), IndexEntry::new("1".parse().unwrap()));
+        }
+        index
+    }
+
+    #[test]
+    fn finds_gentoo_packages_without_categories() {
+        let gentoo = Repo::Gentoo(Default::default());
+        let index = index(&["media-video/ffmpeg", "app-misc/hello", "dev-util/hello"]);
+        let full_name = |name| RepoConfig::full_name("gentoo", &gentoo, &index, name);
+
+        assert_eq!(full_name("ffmpeg").unwrap(), "media-video/ffmpeg");
+        assert_eq!(full_name("app-misc/hello").unwrap(), "app-misc/hello");
+        assert!(matches!(
+            full_name("hello"),
+            Err(RepoError::AmbiguousName(_, _, candidates))
+                if candidates == ["app-misc/hello", "dev-util/hello"]
+        ));
+        assert!(matches!(full_name("sl"), Err(RepoError::NotProvided(..))));
+    }
+
+    #[test]
+    fn drops_packages_with_unsafe_names() {
+        let mut index = index(&["../../etc", "/etc", "app-misc/hello", "libc6", "a//b"]);
+        drop_invalid_names("test", &mut index);
+        assert_eq!(
+            index.names().collect::<Vec<_>>(),
+            ["app-misc/hello", "libc6"]
+        );
+    }
+
+    #[test]
+    fn leaves_other_names_alone() {
+        let dummy = Repo::Dummy(Default::default());
+        let index = index(&["games/sl"]);
+        assert_eq!(
+            RepoConfig::full_name("test", &dummy, &index, "sl").unwrap(),
+            "sl"
+        );
+    }
+}
@@ -0,0 +1,748 @@
+use super::{
+    fetch::{fetch, fetch_cached},
+    solver::{self, Bound, SolveError, Solver, Target},
+    Repo,
+};
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    local,
+    version::Version,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{App, Arg, ArgMatches};
+use flate2::bufread::GzDecoder;
+use quick_error::quick_error;
+use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tar::Archive;
+
+pub(super) mod version;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum AlpineError {
+        WrongScheme(uri: String) {
+            display("'{}' isn't an http://, https:// or file:// URI", uri)
+        }
+        UnknownArch {
+            display("no Alpine architecture is known for this machine; pass --arch")
+        }
+        Malformed(uri: String) {
+            display("'{}' isn't a valid apk archive", uri)
+        }
+        NoIndex(uri: String) {
+            display("'{}' has no APKINDEX file", uri)
+        }
+        Unsigned(uri: String) {
+            display("'{}' isn't signed", uri)
+        }
+        UnknownKey(uri: String, key: String, keys_dir: PathBuf) {
+            display("'{}' is signed with '{}', which isn't in {}", uri, key, keys_dir.display())
+        }
+        InvalidKey(path: PathBuf, err: String) {
+            display("couldn't read RSA key '{}': {}", path.display(), err)
+        }
+        BadSignature(uri: String, key: String) {
+            display("signature of '{}' couldn't be verified with '{}'", uri, key)
+        }
+        ChecksumMismatch(uri: String) {
+            display("'{}' doesn't match its checksum in the repo", uri)
+        }
+        NoFilename(package: String) {
+            display("the index doesn't say where to download '{}' from; try syncing again", package)
+        }
+    }
+}
+
+const DEFAULT_URI: &str = "https://dl-cdn.alpinelinux.org/alpine";
+
+/// Where apk itself looks for the keys packages and indexes are signed with.
+const DEFAULT_KEYS_DIR: &str = "/etc/apk/keys";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AlpineRepo {
+    /// The root of the mirror, which has a directory per branch in it.
+    uri: String,
+    branch: String,
+    repos: Vec<String>,
+    arch: String,
+    /// The RSA public keys packages and indexes can be signed with, named as apk names them.
+    keys_dir: PathBuf,
+}
+
+/// The Alpine name for the architecture storm was built for.
+fn host_arch() -> Option<&'static str> {
+    match env::consts::ARCH {
+        "x86_64" => Some("x86_64"),
+        "x86" => Some("x86"),
+        "aarch64" => Some("aarch64"),
+        "arm" => Some("armv7"),
+        "powerpc64" if cfg!(target_endian = "little") => Some("ppc64le"),
+        "riscv64" => Some("riscv64"),
+        "s390x" => Some("s390x"),
+        "loongarch64" => Some("loongarch64"),
+        _ => None,
+    }
+}
+
+/// Split an apk archive into its gzip streams: for a package, the signature, the control
+/// section and the data; for an index, the signature and the index itself.
+fn streams<'a>(uri: &str, mut data: &'a [u8]) -> Result<Vec<&'a [u8]>, AlpineError> {
+    let mut streams = Vec::new();
+    while !data.is_empty() {
+        let before = data;
+        // decoding a stream from a &[u8] leaves it just past the stream's end
+        io::copy(&mut GzDecoder::new(&mut data), &mut io::sink())
+            .map_err(|_| AlpineError::Malformed(uri.to_string()))?;
+        streams.push(&before[..before.len() - data.len()]);
+    }
+    Ok(streams)
+}
+
+/// Read every file in a gzipped tar stream, which apk may have cut off before the usual
+/// end-of-archive marker.
+fn files(uri: &str, stream: &[u8]) -> Result<Vec<(String, Vec<u8>)>, AlpineError> {
+    let malformed = |_| AlpineError::Malformed(uri.to_string());
+    let mut files = Vec::new();
+    for entry in Archive::new(GzDecoder::new(stream))
+        .entries()
+        .map_err(malformed)?
+    {
+        let mut entry = entry.map_err(malformed)?;
+        let path = entry.path().map_err(malformed)?;
+        let path = path.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).map_err(malformed)?;
+        files.push((path, contents));
+    }
+    Ok(files)
+}
+
+/// Parse a dependency or provide like `so:libc.musl-x86_64.so.1`, `cmd:sh` or `musl>=1.2`.
+fn parse_relation(s: &str) -> Result<Target, SolveError> {
+    let invalid = || SolveError::InvalidRelation(s.to_string());
+
+    let idx = s.find(['<', '>', '=', '~']).unwrap_or(s.len());
+    let (name, bound) = s.split_at(idx);
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    let bound = if bound.is_empty() {
+        None
+    } else if let Some(prefix) = bound.strip_prefix("~=").or(bound.strip_prefix('~')) {
+        Some(Bound::Prefix(prefix.parse().map_err(|_| invalid())?))
+    } else {
+        Some(Bound::Constraint(bound.parse().map_err(|_| invalid())?))
+    };
+
+    Ok(Target {
+        name: name.to_string(),
+        bound,
+    })
+}
+
+impl AlpineRepo {
+    fn repo_uri(&self, repo: &str) -> String {
+        format!("{}/{}/{}/{}", self.uri, self.branch, repo, self.arch)
+    }
+
+    /// Check that the first stream of an apk archive signs the second, with one of the keys
+    /// in the keys directory.
+    fn verify_signature(&self, uri: &str, streams: &[&[u8]]) -> Result<(), Box<dyn Error>> {
+        let (signature, signed) = match streams {
+            [signature, signed, ..] => (signature, signed),
+            _ => return Err(Box::new(AlpineError::Unsigned(uri.to_string()))),
+        };
+
+        for (name, signature) in files(uri, signature)? {
+            // SHA-1 signatures are still what most of Alpine's packages have
+            let (key, scheme, hashed) = if let Some(key) = name.strip_prefix(".SIGN.RSA256.") {
+                let hashed = Sha256::digest(signed).to_vec();
+                (key, Pkcs1v15Sign::new::<Sha256>(), hashed)
+            } else if let Some(key) = name.strip_prefix(".SIGN.RSA.") {
+                (
+                    key,
+                    Pkcs1v15Sign::new::<Sha1>(),
+                    Sha1::digest(signed).to_vec(),
+                )
+            } else {
+                continue;
+            };
+
+            // the signature names the key it was made with, which can't be outside the directory
+            let path = self.keys_dir.join(key);
+            if key.contains('/') || !path.is_file() {
+                return Err(Box::new(AlpineError::UnknownKey(
+                    uri.to_string(),
+                    key.to_string(),
+                    self.keys_dir.clone(),
+                )));
+            }
+            let public_key = RsaPublicKey::from_public_key_pem(&fs::read_to_string(&path)?)
+                .map_err(|e| AlpineError::InvalidKey(path.clone(), e.to_string()))?;
+            return public_key
+                .verify(scheme, &hashed, &signature)
+                .map_err(|_| AlpineError::BadSignature(uri.to_string(), key.to_string()).into());
+        }
+        Err(Box::new(AlpineError::Unsigned(uri.to_string())))
+    }
+
+    /// Fetch and verify a repo's APKINDEX, returning it along with the repo's description.
+    fn apkindex(&self, repo: &str) -> Result<(String, String), Box<dyn Error>> {
+        let uri = format!("{}/APKINDEX.tar.gz", self.repo_uri(repo));
+        let data = fetch(&uri)?;
+        let streams = streams(&uri, &data)?;
+        self.verify_signature(&uri, &streams)?;
+
+        let mut apkindex = None;
+        let mut description = String::new();
+        for (name, contents) in files(&uri, streams[1])? {
+            match name.as_str() {
+                "APKINDEX" => apkindex = Some(String::from_utf8_lossy(&contents).into_owned()),
+                "DESCRIPTION" => description = String::from_utf8_lossy(&contents).trim().into(),
+                _ => {}
+            }
+        }
+        Ok((apkindex.ok_or(AlpineError::NoIndex(uri))?, description))
+    }
+
+    pub(super) fn sync(&self) -> Result<PackageIndex, Box<dyn Error>> {
+        let mut index = PackageIndex::default();
+        let mut descriptions = Vec::new();
+        for repo in &self.repos {
+            let (apkindex, description) = self.apkindex(repo)?;
+            if !description.is_empty() && !descriptions.contains(&description) {
+                descriptions.push(description);
+            }
+
+            for paragraph in apkindex.split("\n\n") {
+                let field = |key: char| {
+                    paragraph
+                        .lines()
+                        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+                        .unwrap_or_default()
+                };
+                let (name, version) = match (field('P'), field('V')) {
+                    ("", _) | (_, "") => continue,
+                    fields => fields,
+                };
+                let parsed = match version.parse::<Version>() {
+                    Ok(version) => version,
+                    Err(e) => {
+                        eprintln!("warning: skipping {} {}: {}", name, version, e);
+                        continue;
+                    }
+                };
+
+                let mut entry = IndexEntry::new(parsed);
+                entry.summary = field('T').to_string();
+                entry.homepage = field('U').to_string();
+                entry.license = field('L').to_string();
+                // `!name` means the package conflicts with name
+                for dependency in field('D').split_whitespace() {
+                    match dependency.strip_prefix('!') {
+                        Some(conflict) => entry.conflicts.push(conflict.to_string()),
+                        None => entry.depends.push(dependency.to_string()),
+                    }
+                }
+                entry.provides = field('p').split_whitespace().map(str::to_string).collect();
+                // the checksum is of the package's control section, as `Q1` and base64 SHA-1
+                let checksum = field('C')
+                    .strip_prefix("Q1")
+                    .and_then(|sha1| BASE64.decode(sha1).ok())
+                    .map(|sha1| {
+                        let hex = sha1
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .collect::<String>();
+                        format!("sha1:{}", hex)
+                    })
+                    .unwrap_or_default();
+                let filename = format!("{}/{}/{}-{}.apk", repo, self.arch, name, version);
+                entry.checksums.insert(filename, checksum);
+                index.insert(name.to_string(), entry);
+            }
+        }
+
+        index.revision = Some(descriptions.join(", ")).filter(|r| !r.is_empty());
+        Ok(index)
+    }
+
+    /// Check a downloaded .apk: its signature, that it's the build the index lists, and that
+    /// its files are the ones its signed control section vouches for.
+    fn verify_package(&self, uri: &str, data: &[u8], checksum: &str) -> Result<(), Box<dyn Error>> {
+        let streams = streams(uri, data)?;
+        if streams.len() != 3 {
+            return Err(Box::new(AlpineError::Malformed(uri.to_string())));
+        }
+        self.verify_signature(uri, &streams)?;
+        let (control, files_stream) = (streams[1], streams[2]);
+
+        let mismatch = || Box::new(AlpineError::ChecksumMismatch(uri.to_string()));
+        if format!("sha1:{:x}", Sha1::digest(control)) != checksum {
+            return Err(mismatch());
+        }
+        let pkginfo = files(uri, control)?
+            .into_iter()
+            .find(|(name, _)| name == ".PKGINFO")
+            .map(|(_, contents)| local::parse_fields(&String::from_utf8_lossy(&contents), '='))
+            .ok_or_else(|| AlpineError::Malformed(uri.to_string()))?;
+        if pkginfo.get("datahash") != Some(&format!("{:x}", Sha256::digest(files_stream))) {
+            return Err(mismatch());
+        }
+        Ok(())
+    }
+
+    /// Download a package's .apk into `archives` (unless it's there from before), and verify
+    /// it.
+    fn archive(
+        &self,
+        name: &str,
+        entry: &IndexEntry,
+        archives: &Path,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let (filename, checksum) = entry
+            .checksums
+            .iter()
+            .next()
+            .ok_or_else(|| AlpineError::NoFilename(name.to_string()))?;
+        let path = archives.join(filename.rsplit('/').next().unwrap_or(filename));
+        let uri = format!("{}/{}/{}", self.uri, self.branch, filename);
+        fetch_cached(&uri, &path, |data| {
+            self.verify_package(&uri, data, checksum)
+        })?;
+        Ok(path)
+    }
+
+    /// Unpack a package, along with everything it depends on, into `dir`. Each package gets
+    /// its own root, so its dependencies are solved on their own.
+    pub(super) fn build(
+        &self,
+        index: &PackageIndex,
+        name: &str,
+        entry: &IndexEntry,
+        archives: &Path,
+        dir: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let packages = Solver::new(index, parse_relation, version::compare).solve(name, entry)?;
+        if packages.len() > 1 {
+            let needs = solver::describe(&packages[1..]);
+            println!("{} {} needs: {}", name, entry.version, needs);
+        }
+
+        let paths = packages
+            .iter()
+            .map(|(name, entry)| self.archive(name, entry, archives))
+            .collect::<Result<Vec<_>, _>>()?;
+        for path in paths {
+            local::unpack(&path, dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Add an Alpine (apk) repository")
+        .arg(
+            Arg::with_name("uri")
+                .long("uri")
+                .short("u")
+                .takes_value(true)
+                .value_name("URI")
+                .default_value(DEFAULT_URI)
+                .help("Root of the mirror, the directory with a directory per branch in it"),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .long("branch")
+                .short("b")
+                .takes_value(true)
+                .value_name("BRANCH")
+                .default_value("latest-stable")
+                .help("Branch to use, e.g. v3.20 or edge"),
+        )
+        .arg(
+            Arg::with_name("repos")
+                .long("repos")
+                .short("r")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .value_name("REPO,...")
+                .default_value("main")
+                .help("Repositories of the branch to use, e.g. main,community"),
+        )
+        .arg(
+            Arg::with_name("arch")
+                .long("arch")
+                .short("a")
+                .takes_value(true)
+                .value_name("ARCH")
+                .help("Architecture of the packages to use [default: this machine's]"),
+        )
+        .arg(
+            Arg::with_name("keys-dir")
+                .long("keys-dir")
+                .short("k")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value(DEFAULT_KEYS_DIR)
+                .help("Directory of RSA public keys packages must be signed with"),
+        )
+}
+
+fn run(args: &ArgMatches) -> Result<Repo, Box<dyn Error>> {
+    let uri = args.value_of("uri").unwrap().trim_end_matches('/');
+    if !["http://", "https://", "file://"]
+        .iter()
+        .any(|scheme| uri.starts_with(scheme))
+    {
+        return Err(Box::new(AlpineError::WrongScheme(uri.to_string())));
+    }
+
+    let arch = match args.value_of("arch") {
+        Some(arch) => arch,
+        None => host_arch().ok_or(AlpineError::UnknownArch)?,
+    };
+
+    Ok(Repo::Alpine(AlpineRepo {
+        uri: uri.to_string(),
+        branch: args.value_of("branch").unwrap().to_string(),
+        repos: args
+            .values_of("repos")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        arch: arch.to_string(),
+        keys_dir: PathBuf::from(args.value_of_os("keys-dir").unwrap()),
+    }))
+}
+
+pub(super) static CMD: crate::SubCommand<Repo> = crate::SubCommand { args, run };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use lazy_static::lazy_static;
+    use rsa::{
+        pkcs8::{EncodePublicKey, LineEnding},
+        RsaPrivateKey,
+    };
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const KEY_NAME: &str = "test.rsa.pub";
+
+    lazy_static! {
+        // small keys, since generating them in a debug build is slow
+        static ref KEY: RsaPrivateKey = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        static ref OTHER_KEY: RsaPrivateKey =
+            RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    }
+
+    /// A gzipped tar stream of `files`, which like apk's signature and control sections can
+    /// be cut off before the end-of-archive marker.
+    fn tar_gz(files: &[(&str, &[u8])], cut: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        let mut tar = builder.into_inner().unwrap();
+        if cut {
+            tar.truncate(tar.len() - 1024);
+        }
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+        gz.finish().unwrap()
+    }
+
+    /// A signature stream for `signed`, as `.SIGN.RSA256.<key>` or (SHA-1) `.SIGN.RSA.<key>`.
+    fn sign(key: &RsaPrivateKey, file: &str, signed: &[u8]) -> Vec<u8> {
+        let signature = if file.starts_with(".SIGN.RSA256.") {
+            key.sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(signed))
+        } else {
+            key.sign(Pkcs1v15Sign::new::<Sha1>(), &Sha1::digest(signed))
+        };
+        tar_gz(&[(file, &signature.unwrap())], true)
+    }
+
+    fn signed_by_key(signed: &[u8]) -> Vec<u8> {
+        sign(&KEY, &format!(".SIGN.RSA256.{}", KEY_NAME), signed)
+    }
+
+    /// The sections of an .apk holding one file.
+    struct Apk {
+        signature: Vec<u8>,
+        control: Vec<u8>,
+        data: Vec<u8>,
+    }
+
+    impl Apk {
+        fn new(name: &str, version: &str) -> Self {
+            let path = format!("usr/share/{}/version", name);
+            let data = tar_gz(&[(&path, version.as_bytes())], false);
+            let control = Self::control(name, version, &data);
+            Self {
+                signature: signed_by_key(&control),
+                control,
+                data,
+            }
+        }
+
+        fn control(name: &str, version: &str, data: &[u8]) -> Vec<u8> {
+            let pkginfo = format!(
+                "pkgname = {}\npkgver = {}\ndatahash = {:x}\n",
+                name,
+                version,
+                Sha256::digest(data)
+            );
+            tar_gz(&[(".PKGINFO", pkginfo.as_bytes())], true)
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            [&self.signature[..], &self.control, &self.data].concat()
+        }
+    }
+
+    /// A mirror in `<tmp>/mirror` with `v3.20/main/x86_64` in it, whose packages must be
+    /// signed with `KEY`.
+    fn mirror() -> (TempDir, AlpineRepo) {
+        let tmp = TempDir::new().unwrap();
+        let keys_dir = tmp.path().join("keys");
+        fs::create_dir(&keys_dir).unwrap();
+        let pem = KEY.to_public_key().to_public_key_pem(LineEnding::LF);
+        fs::write(keys_dir.join(KEY_NAME), pem.unwrap()).unwrap();
+        fs::create_dir_all(tmp.path().join("mirror/v3.20/main/x86_64")).unwrap();
+
+        let repo = AlpineRepo {
+            uri: format!("file://{}/mirror", tmp.path().display()),
+            branch: "v3.20".to_string(),
+            repos: vec!["main".to_string()],
+            arch: "x86_64".to_string(),
+            keys_dir,
+        };
+        (tmp, repo)
+    }
+
+    fn repo_dir(tmp: &TempDir) -> PathBuf {
+        tmp.path().join("mirror/v3.20/main/x86_64")
+    }
+
+    /// The index stream of an APKINDEX.tar.gz.
+    fn index_stream(apkindex: &str) -> Vec<u8> {
+        let description = b"v3.20-test\n";
+        tar_gz(
+            &[
+                ("DESCRIPTION", description),
+                ("APKINDEX", apkindex.as_bytes()),
+            ],
+            false,
+        )
+    }
+
+    /// Publish each `(name, version, extra index fields)` as a signed package, and an index
+    /// of them signed with `KEY`.
+    fn publish(tmp: &TempDir, packages: &[(&str, &str, &str)]) {
+        let mut apkindex = String::new();
+        for (name, version, fields) in packages {
+            let apk = Apk::new(name, version);
+            let filename = format!("{}-{}.apk", name, version);
+            fs::write(repo_dir(tmp).join(filename), apk.bytes()).unwrap();
+            apkindex.push_str(&format!(
+                "C:Q1{}\nP:{}\nV:{}\nT:the {} package\n{}\n",
+                BASE64.encode(Sha1::digest(&apk.control)),
+                name,
+                version,
+                name,
+                fields
+            ));
+        }
+        let index = index_stream(&apkindex);
+        write_index(tmp, &[&signed_by_key(&index), &index]);
+    }
+
+    fn write_index(tmp: &TempDir, streams: &[&[u8]]) {
+        fs::write(repo_dir(tmp).join("APKINDEX.tar.gz"), streams.concat()).unwrap();
+    }
+
+    fn error(err: Box<dyn Error>) -> AlpineError {
+        match err.downcast::<AlpineError>() {
+            Ok(err) => *err,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn syncs_signed_indexes() {
+        let (tmp, repo) = mirror();
+        publish(
+            &tmp,
+            &[
+                (
+                    "app",
+                    "1.0-r0",
+                    "D:so:libfoo.so.1 cmd:sh !oldapp\np:cmd:app=1.0-r0\n",
+                ),
+                ("libfoo", "1.0-r3", "p:so:libfoo.so.1=1.0\n"),
+            ],
+        );
+
+        let index = repo.sync().unwrap();
+        assert_eq!(index.revision.as_deref(), Some("v3.20-test"));
+        let app = &index.get("app")[0];
+        assert_eq!(app.summary, "the app package");
+        assert_eq!(app.depends, ["so:libfoo.so.1", "cmd:sh"]);
+        assert_eq!(app.conflicts, ["oldapp"]);
+        assert_eq!(app.provides, ["cmd:app=1.0-r0"]);
+
+        let control = Apk::new("app", "1.0-r0").control;
+        let (filename, checksum) = app.checksums.iter().next().unwrap();
+        assert_eq!(filename, "main/x86_64/app-1.0-r0.apk");
+        assert_eq!(checksum, &format!("sha1:{:x}", Sha1::digest(control)));
+
+        // SHA-1 signatures are accepted too
+        let index = fs::read(repo_dir(&tmp).join("APKINDEX.tar.gz")).unwrap();
+        let index = streams("APKINDEX", &index).unwrap()[1].to_vec();
+        let signature = sign(&KEY, &format!(".SIGN.RSA.{}", KEY_NAME), &index);
+        write_index(&tmp, &[&signature, &index]);
+        assert_eq!(repo.sync().unwrap().get("libfoo").len(), 1);
+    }
+
+    #[test]
+    fn refuses_indexes_not_signed_with_a_known_key() {
+        let (tmp, repo) = mirror();
+        let index = index_stream("P:app\nV:1.0-r0\n");
+
+        let unknown = sign(&OTHER_KEY, ".SIGN.RSA256.other.rsa.pub", &index);
+        write_index(&tmp, &[&unknown, &index]);
+        let err = error(repo.sync().unwrap_err());
+        assert!(matches!(err, AlpineError::UnknownKey(_, ref key, _) if key == "other.rsa.pub"));
+
+        // the key has to be in the keys directory itself
+        let outside = format!(".SIGN.RSA256.../keys/{}", KEY_NAME);
+        write_index(&tmp, &[&sign(&KEY, &outside, &index), &index]);
+        let err = error(repo.sync().unwrap_err());
+        assert!(matches!(err, AlpineError::UnknownKey(..)));
+
+        let forged = sign(&OTHER_KEY, &format!(".SIGN.RSA256.{}", KEY_NAME), &index);
+        write_index(&tmp, &[&forged, &index]);
+        let err = error(repo.sync().unwrap_err());
+        assert!(matches!(err, AlpineError::BadSignature(..)));
+
+        write_index(&tmp, &[&index]);
+        let err = error(repo.sync().unwrap_err());
+        assert!(matches!(err, AlpineError::Unsigned(_)));
+    }
+
+    #[test]
+    fn refuses_tampered_packages() {
+        let (tmp, repo) = mirror();
+        publish(&tmp, &[("app", "1.0-r0", "")]);
+        let index = repo.sync().unwrap();
+        let (_, checksum) = index.get("app")[0].checksums.iter().next().unwrap();
+        let verify = |apk: &Apk| repo.verify_package("app.apk", &apk.bytes(), checksum);
+
+        let apk = Apk::new("app", "1.0-r0");
+        verify(&apk).unwrap();
+
+        // a control section the signature isn't of
+        let tampered_data = tar_gz(&[("usr/bin/app", b"evil")], false);
+        let tampered = Apk {
+            control: Apk::control("app", "1.0-r0", &tampered_data),
+            ..Apk::new("app", "1.0-r0")
+        };
+        let err = error(verify(&tampered).unwrap_err());
+        assert!(matches!(err, AlpineError::BadSignature(..)));
+
+        // signed, but not the build the index lists
+        let tampered = Apk {
+            signature: signed_by_key(&tampered.control),
+            data: tampered_data.clone(),
+            ..tampered
+        };
+        let err = error(verify(&tampered).unwrap_err());
+        assert!(matches!(err, AlpineError::ChecksumMismatch(_)));
+
+        // the build the index lists, but with different files
+        let tampered = Apk {
+            data: tampered_data,
+            ..Apk::new("app", "1.0-r0")
+        };
+        let err = error(verify(&tampered).unwrap_err());
+        assert!(matches!(err, AlpineError::ChecksumMismatch(_)));
+
+        let truncated = Apk {
+            data: Vec::new(),
+            ..Apk::new("app", "1.0-r0")
+        };
+        let err = error(verify(&truncated).unwrap_err());
+        assert!(matches!(err, AlpineError::Malformed(_)));
+    }
+
+    #[test]
+    fn builds_with_dependencies_on_libraries_and_commands() {
+        let (tmp, repo) = mirror();
+        publish(
+            &tmp,
+            &[
+                ("app", "1.0-r0", "D:so:libfoo.so.1 cmd:sh libbar~1.2\n"),
+                // apk orders a patch release after any revision of the release
+                ("libfoo", "1.0-r3", "p:so:libfoo.so.1=1.0\n"),
+                ("libfoo", "1.0_p1-r0", "p:so:libfoo.so.1=1.0\n"),
+                ("busybox", "1.36.1-r29", "p:cmd:sh=1.36.1-r29\n"),
+                ("libbar", "1.20-r0", ""),
+                ("libbar", "1.2.3-r0", ""),
+            ],
+        );
+        let index = repo.sync().unwrap();
+        let entry = &index.get("app")[0];
+
+        let (archives, dir) = (tmp.path().join("archives"), tmp.path().join("root"));
+        fs::create_dir_all(&archives).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        repo.build(&index, "app", entry, &archives, &dir).unwrap();
+
+        let installed = |name: &str| {
+            fs::read_to_string(dir.join("usr/share").join(name).join("version")).unwrap()
+        };
+        assert_eq!(installed("app"), "1.0-r0");
+        assert_eq!(installed("libfoo"), "1.0_p1-r0");
+        assert_eq!(installed("busybox"), "1.36.1-r29");
+        assert_eq!(installed("libbar"), "1.2.3-r0");
+        assert!(archives.join("libfoo-1.0_p1-r0.apk").is_file());
+    }
+
+    #[test]
+    fn parses_relations() {
+        let target = parse_relation("so:libc.musl-x86_64.so.1").unwrap();
+        assert_eq!(target.name, "so:libc.musl-x86_64.so.1");
+        assert!(target.bound.is_none());
+
+        let target = parse_relation("musl>=1.2").unwrap();
+        assert_eq!(target.name, "musl");
+        assert!(matches!(target.bound, Some(Bound::Constraint(_))));
+
+        for fuzzy in ["libbar~1.2", "libbar~=1.2"] {
+            let target = parse_relation(fuzzy).unwrap();
+            assert_eq!(target.name, "libbar");
+            assert!(matches!(target.bound, Some(Bound::Prefix(ref p)) if p.to_string() == "1.2"));
+        }
+
+        assert!(parse_relation(">=1.2").is_err());
+        assert!(parse_relation("musl>=").is_err());
+    }
+}
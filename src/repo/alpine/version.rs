@@ -0,0 +1,162 @@
+use crate::version::Version;
+use std::cmp::Ordering;
+
+/// Suffixes for releases before the version they're on, oldest first.
+const PRE_RELEASE: &[&str] = &["alpha", "beta", "pre", "rc"];
+/// Suffixes for snapshots and patches after the version they're on, oldest first.
+const POST_RELEASE: &[&str] = &["cvs", "svn", "git", "hg", "p"];
+
+/// The kinds of part an apk version is made of, like `1`, `.2`, `a`, `_p`, `3` and `-r4` in
+/// `1.2a_p3-r4`. Where two versions have different kinds of part, the one earlier in this
+/// list is newer: `1.2.1` is newer than `1.2a`, which is newer than `1.2_p1`, `1.2-r1` and
+/// `1.2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Digits,
+    Letter,
+    Suffix,
+    SuffixNumber,
+    Revision,
+    End,
+}
+
+/// Split a version into its parts and their values, or `None` if apk wouldn't accept it.
+/// Pre-release suffixes have negative values, and everything else non-negative ones.
+fn tokens(version: &str) -> Option<Vec<(Kind, i64)>> {
+    let mut tokens = Vec::new();
+    let mut rest = version;
+    let mut kind = Kind::Digits;
+    while kind != Kind::End {
+        let value = match kind {
+            Kind::Letter => {
+                let letter = rest.as_bytes()[0];
+                rest = &rest[1..];
+                i64::from(letter)
+            }
+            Kind::Suffix => {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_lowercase())
+                    .unwrap_or(rest.len());
+                let suffix = &rest[..len];
+                rest = &rest[len..];
+                match PRE_RELEASE.iter().position(|&s| s == suffix) {
+                    Some(i) => i as i64 - PRE_RELEASE.len() as i64,
+                    None => POST_RELEASE.iter().position(|&s| s == suffix)? as i64,
+                }
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let digits = &rest[..len];
+                rest = &rest[len..];
+                digits.parse().ok()?
+            }
+        };
+        tokens.push((kind, value));
+
+        // a letter can only follow digits, and nothing but the end can follow a revision
+        let next = if rest.is_empty() {
+            Kind::End
+        } else if kind == Kind::Digits && rest.starts_with('.') {
+            rest = &rest[1..];
+            Kind::Digits
+        } else if kind == Kind::Digits && rest.starts_with(|c: char| c.is_ascii_lowercase()) {
+            Kind::Letter
+        } else if kind == Kind::Suffix && rest.starts_with(|c: char| c.is_ascii_digit()) {
+            Kind::SuffixNumber
+        } else if kind < Kind::Revision && rest.starts_with('_') {
+            rest = &rest[1..];
+            Kind::Suffix
+        } else if kind < Kind::Revision && rest.starts_with("-r") {
+            rest = &rest[2..];
+            Kind::Revision
+        } else {
+            return None;
+        };
+        kind = next;
+    }
+    tokens.push((Kind::End, 0));
+    Some(tokens)
+}
+
+/// Compare Alpine versions the way apk does, so `-rN` revisions, letters and `_p` or `_git`
+/// suffixes all sort after the release they're on, and `_rc` and the like before it.
+/// Versions apk wouldn't accept fall back to storm's own ordering.
+pub fn compare(a: &Version, b: &Version) -> Ordering {
+    let (a_tokens, b_tokens) = match (tokens(&a.to_string()), tokens(&b.to_string())) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return a.compare(b),
+    };
+
+    for (&(a_kind, a_value), &(b_kind, b_value)) in a_tokens.iter().zip(&b_tokens) {
+        if a_kind != b_kind {
+            return match (a_kind, b_kind) {
+                (Kind::Suffix, _) if a_value < 0 => Ordering::Less,
+                (_, Kind::Suffix) if b_value < 0 => Ordering::Greater,
+                _ => b_kind.cmp(&a_kind),
+            };
+        }
+        let ordering = a_value.cmp(&b_value);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> Ordering {
+        compare(&a.parse().unwrap(), &b.parse().unwrap())
+    }
+
+    #[test]
+    fn orders_like_apk() {
+        let ordered = [
+            "1.0_alpha",
+            "1.0_alpha2",
+            "1.0_beta",
+            "1.0_rc1",
+            "1.0",
+            "1.0-r1",
+            "1.0-r2",
+            "1.0-r10",
+            "1.0_cvs",
+            "1.0_svn20240101",
+            "1.0_git20231231",
+            "1.0_p1",
+            "1.0_p1-r1",
+            "1.0_p2",
+            "1.0a",
+            "1.0b",
+            "1.0.1",
+            "1.2",
+            "1.10",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(
+                cmp(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(cmp(pair[1], pair[0]), Ordering::Greater);
+        }
+        assert_eq!(cmp("1.2.3-r0", "1.2.3-r0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn rejects_what_apk_would() {
+        assert!(tokens("1.2_p1-r3").is_some());
+        assert!(tokens("1.2ab").is_none());
+        assert!(tokens("1.2-r1_p1").is_none());
+        assert!(tokens("1.2_foo").is_none());
+        assert!(tokens("1.2-1").is_none());
+        // which storm still orders its own way
+        assert_eq!(cmp("1.2-1", "1.2-2"), Ordering::Less);
+    }
+}
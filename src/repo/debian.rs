@@ -0,0 +1,869 @@
+use super::{
+    fetch::{fetch, fetch_cached},
+    solver::{self, Solver},
+    Repo,
+};
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    local,
+    version::Version,
+};
+use clap::{App, Arg, ArgMatches};
+use keyring::Keyring;
+use quick_error::quick_error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod keyring;
+mod relation;
+pub(super) mod version;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum DebianError {
+        WrongScheme(uri: String) {
+            display("'{}' isn't an http://, https:// or file:// URI", uri)
+        }
+        UnknownArch {
+            display("no Debian architecture is known for this machine; pass --arch")
+        }
+        MissingClient(program: &'static str) {
+            display("'{}' isn't installed (or isn't in $PATH)", program)
+        }
+        NoRelease(uri: String) {
+            display("'{}' has no InRelease or Release file; check the URI and suite", uri)
+        }
+        NotInRelease(what: &'static str, value: String, available: String) {
+            display("the repo has no {} '{}' (it has: {})", what, value, available)
+        }
+        NoPackages(uri: String) {
+            display("'{}' has no Packages index", uri)
+        }
+        NoKeyring(path: PathBuf) {
+            display("OpenPGP keyring '{}' doesn't exist; add the repo with --keyring (or --allow-insecure)", path.display())
+        }
+        InvalidKeyring(path: PathBuf, err: String) {
+            display("couldn't read OpenPGP keyring '{}': {}", path.display(), err)
+        }
+        Unsigned(uri: String) {
+            display("'{}' isn't signed; add the repo with --allow-insecure to use it anyway", uri)
+        }
+        BadSignature(file: String, err: String) {
+            display("signature of '{}' couldn't be verified with the repo's keyring: {}", file, err)
+        }
+        ChecksumMismatch(uri: String) {
+            display("'{}' doesn't match its SHA256 sum in the repo", uri)
+        }
+        NoChecksum(package: String) {
+            display("the index has no SHA256 sum for '{}'; add the repo with --allow-insecure to use it anyway", package)
+        }
+        NoFilename(package: String) {
+            display("the index doesn't say where to download '{}' from; try syncing again", package)
+        }
+        Expired(uri: String, valid_until: String) {
+            display("'{}' was only valid until {}; the mirror may be out of date, or replaying an old Release file", uri, valid_until)
+        }
+        InvalidDate(field: &'static str, date: String) {
+            display("the Release file's {} '{}' isn't a valid date", field, date)
+        }
+    }
+}
+
+const DEFAULT_URI: &str = "https://deb.debian.org/debian";
+
+/// Where the debian-archive-keyring package installs Debian's archive keys.
+const DEFAULT_KEYRING: &str = "/usr/share/keyrings/debian-archive-keyring.gpg";
+
+/// Compressions tried for `Packages` indexes, most preferred first.
+const PACKAGES_FILES: &[&str] = &["Packages.xz", "Packages.gz", "Packages"];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DebianRepo {
+    /// The root of the archive, which has `dists/` and `pool/` in it.
+    uri: String,
+    suite: String,
+    components: Vec<String>,
+    arch: String,
+    /// The keys the Release file must be signed with, instead of Debian's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyring: Option<PathBuf>,
+    /// Use the repo even if it isn't signed, has expired, or has no checksums.
+    #[serde(default)]
+    allow_insecure: bool,
+}
+
+/// The Debian name for the architecture storm was built for.
+fn host_arch() -> Option<&'static str> {
+    match env::consts::ARCH {
+        "x86_64" => Some("amd64"),
+        "x86" => Some("i386"),
+        "aarch64" => Some("arm64"),
+        "arm" => Some("armhf"),
+        "powerpc64" if cfg!(target_endian = "little") => Some("ppc64el"),
+        "riscv64" => Some("riscv64"),
+        "s390x" => Some("s390x"),
+        "mips64" if cfg!(target_endian = "little") => Some("mips64el"),
+        _ => None,
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// The files listed in a Release file's SHA256 section, with their sums.
+fn release_sums(release: &str) -> HashMap<String, String> {
+    let mut sums = HashMap::new();
+    let mut in_sha256 = false;
+    for line in release.lines() {
+        // the section is a multiline field, with a `sum size path` line per file
+        if !line.starts_with(' ') {
+            in_sha256 = line.trim_end() == "SHA256:";
+        } else if in_sha256 {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if let [sum, _size, path] = fields[..] {
+                sums.insert(path.to_string(), sum.to_lowercase());
+            }
+        }
+    }
+    sums
+}
+
+/// Seconds since the epoch of a Release file date, like `Sat, 17 Oct 2026 00:00:00 UTC`.
+fn parse_date(date: &str) -> Option<i64> {
+    // the day of the week is optional, and says nothing the rest doesn't
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let fields = date.split_whitespace().collect::<Vec<_>>();
+    let (day, month, year, time, zone) = match fields[..] {
+        [day, month, year, time, zone] => (day, month, year, time, zone),
+        _ => return None,
+    };
+
+    let day = day.parse::<i64>().ok()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|m| *m == month)? as i64
+        + 1;
+    let year = year.parse::<i64>().ok()?;
+    let seconds = match time.split(':').collect::<Vec<_>>()[..] {
+        [h, m, s] => {
+            h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60 + s.parse::<i64>().ok()?
+        }
+        _ => return None,
+    };
+    let offset = match zone {
+        "UTC" | "GMT" | "Z" => 0,
+        _ => {
+            let sign = match zone.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = (zone.get(1..3)?, zone.get(3..5)?);
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+
+    // days since the epoch, per howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86_400 + seconds - offset)
+}
+
+/// Split a control file (a Release file or Packages index) into its paragraphs.
+fn paragraphs(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+}
+
+/// The signed text of a clearsigned file, or the whole file if it isn't signed.
+fn strip_signature(contents: &str) -> String {
+    if !contents.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return contents.to_string();
+    }
+
+    // the armor headers (Hash: ...) end at the first blank line
+    let mut lines = contents.lines().skip_while(|line| !line.is_empty()).skip(1);
+    let mut text = String::new();
+    for line in lines.by_ref() {
+        if line == "-----BEGIN PGP SIGNATURE-----" {
+            break;
+        }
+        text.push_str(line.strip_prefix("- ").unwrap_or(line));
+        text.push('\n');
+    }
+    text
+}
+
+/// Split a relationship field like `libc6 (>= 2.34), libfoo | libbar` into its clauses.
+fn relations(field: Option<&String>) -> Vec<String> {
+    field
+        .map(|f| {
+            f.split(',')
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl DebianRepo {
+    fn dists_uri(&self) -> String {
+        format!("{}/dists/{}", self.uri, self.suite)
+    }
+
+    /// Fetch the suite's Release file, preferring the clearsigned InRelease, and check its
+    /// signature unless the repo is insecure.
+    fn release(&self) -> Result<String, Box<dyn Error>> {
+        let dists = self.dists_uri();
+        let (name, contents, signature) = match fetch(&format!("{}/InRelease", dists)) {
+            Ok(contents) => ("InRelease", contents, None),
+            Err(_) => {
+                let contents = fetch(&format!("{}/Release", dists))
+                    .map_err(|_| DebianError::NoRelease(dists.clone()))?;
+                let signature = fetch(&format!("{}/Release.gpg", dists)).ok();
+                ("Release", contents, signature)
+            }
+        };
+
+        if self.allow_insecure {
+            eprintln!("warning: not checking the signature of {}/{}", dists, name);
+            return Ok(strip_signature(&String::from_utf8_lossy(&contents)));
+        }
+
+        let keyring = Keyring::open(
+            self.keyring
+                .as_deref()
+                .unwrap_or_else(|| Path::new(DEFAULT_KEYRING)),
+        )?;
+        match signature {
+            None if name == "InRelease" => keyring.verify_clearsigned(name, &contents),
+            Some(signature) => {
+                keyring.verify_detached(name, &contents, &signature)?;
+                Ok(String::from_utf8_lossy(&contents).into_owned())
+            }
+            None => Err(Box::new(DebianError::Unsigned(dists))),
+        }
+    }
+
+    /// Check that the Release file lists the repo's architecture and components.
+    fn check_release(&self, release: &HashMap<String, String>) -> Result<(), DebianError> {
+        let mut checks = vec![("architecture", &self.arch, "architectures")];
+        checks.extend(
+            self.components
+                .iter()
+                .map(|component| ("component", component, "components")),
+        );
+        for (what, value, field) in checks {
+            // older Release files may not say, in which case the index fetch will tell
+            if let Some(available) = release.get(field) {
+                let listed = available.split_whitespace().any(|a| {
+                    // e.g. "updates/main" in security suites
+                    a == value || a.rsplit('/').next() == Some(value)
+                });
+                if !listed {
+                    return Err(DebianError::NotInRelease(
+                        what,
+                        value.clone(),
+                        available.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the Release file hasn't expired, so an old (but validly signed) one can't
+    /// be replayed to hide updates.
+    fn check_valid_until(&self, release: &HashMap<String, String>) -> Result<(), DebianError> {
+        let valid_until = match release.get("valid-until") {
+            Some(valid_until) => valid_until,
+            None => return Ok(()),
+        };
+        let expires = parse_date(valid_until)
+            .ok_or_else(|| DebianError::InvalidDate("Valid-Until", valid_until.clone()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        if now <= expires {
+            return Ok(());
+        }
+
+        let err = DebianError::Expired(self.dists_uri(), valid_until.clone());
+        if self.allow_insecure {
+            eprintln!("warning: {}", err);
+            return Ok(());
+        }
+        Err(err)
+    }
+
+    /// Fetch and decompress a component's `Packages` index, checking it against its sum in
+    /// the Release file.
+    fn packages(
+        &self,
+        component: &str,
+        sums: &HashMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        let dir = format!("{}/binary-{}", component, self.arch);
+        let mut found = None;
+        for name in PACKAGES_FILES {
+            let path = format!("{}/{}", dir, name);
+            let sum = sums.get(&path);
+            // an index the Release file doesn't vouch for can't be trusted
+            if sum.is_none() && !self.allow_insecure {
+                continue;
+            }
+
+            let uri = format!("{}/{}", self.dists_uri(), path);
+            if let Ok(compressed) = fetch(&uri) {
+                if sum.is_some_and(|sum| sha256(&compressed) != *sum) {
+                    return Err(Box::new(DebianError::ChecksumMismatch(uri)));
+                }
+                found = Some((name, compressed));
+                break;
+            }
+        }
+        let (name, compressed) = found.ok_or_else(|| {
+            DebianError::NoPackages(format!("{}/{}/Packages", self.dists_uri(), dir))
+        })?;
+
+        let mut contents = String::new();
+        local::decompress(Path::new(name), BufReader::new(&compressed[..]))?
+            .read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    pub(super) fn sync(&self) -> Result<PackageIndex, Box<dyn Error>> {
+        let release = self.release()?;
+        let sums = release_sums(&release);
+        let release = local::parse_fields(&release, ':');
+        self.check_release(&release)?;
+        self.check_valid_until(&release)?;
+
+        let mut index = PackageIndex::default();
+        for component in &self.components {
+            for paragraph in paragraphs(&self.packages(component, &sums)?) {
+                let fields = local::parse_fields(paragraph, ':');
+                let (name, version) = match (fields.get("package"), fields.get("version")) {
+                    (Some(name), Some(version)) => (name, version),
+                    _ => continue,
+                };
+                let version = match version.parse::<Version>() {
+                    Ok(version) => version,
+                    Err(e) => {
+                        eprintln!("warning: skipping {} {}: {}", name, version, e);
+                        continue;
+                    }
+                };
+
+                let mut entry = IndexEntry::new(version);
+                entry.summary = fields.get("description").cloned().unwrap_or_default();
+                entry.homepage = fields.get("homepage").cloned().unwrap_or_default();
+                // Pre-Depends have to be unpacked first, but storm unpacks everything at once
+                entry.depends = relations(fields.get("pre-depends"));
+                entry.depends.extend(relations(fields.get("depends")));
+                entry.provides = relations(fields.get("provides"));
+                // nothing gets configured or upgraded in place, so breaking a package is as
+                // bad as conflicting with it
+                entry.conflicts = relations(fields.get("conflicts"));
+                entry.conflicts.extend(relations(fields.get("breaks")));
+                if let Some(filename) = fields.get("filename") {
+                    let checksum = fields
+                        .get("sha256")
+                        .map(|sha256| format!("sha256:{}", sha256))
+                        .unwrap_or_default();
+                    entry.checksums.insert(filename.clone(), checksum);
+                }
+                index.insert(name.clone(), entry);
+            }
+        }
+
+        index.revision = release.get("date").cloned();
+        Ok(index)
+    }
+
+    /// Download a package's .deb into `archives` (unless it's there from before), checking
+    /// it against its sum in the index.
+    fn archive(
+        &self,
+        name: &str,
+        entry: &IndexEntry,
+        archives: &Path,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let (filename, checksum) = entry
+            .checksums
+            .iter()
+            .next()
+            .ok_or_else(|| DebianError::NoFilename(name.to_string()))?;
+        let sum = checksum.strip_prefix("sha256:");
+        if sum.is_none() && !self.allow_insecure {
+            return Err(Box::new(DebianError::NoChecksum(name.to_string())));
+        }
+
+        let path = archives.join(filename.rsplit('/').next().unwrap_or(filename));
+        let uri = format!("{}/{}", self.uri, filename);
+        fetch_cached(&uri, &path, |data| match sum {
+            Some(sum) if sha256(data) != sum => {
+                Err(Box::new(DebianError::ChecksumMismatch(uri.clone())))
+            }
+            _ => Ok(()),
+        })?;
+        Ok(path)
+    }
+
+    /// Unpack a package, along with everything it depends on, into `dir`. Each package gets
+    /// its own root, so its dependencies are solved on their own.
+    pub(super) fn build(
+        &self,
+        index: &PackageIndex,
+        name: &str,
+        entry: &IndexEntry,
+        archives: &Path,
+        dir: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let packages = Solver::new(index, relation::parse, version::compare).solve(name, entry)?;
+        if packages.len() > 1 {
+            let needs = solver::describe(&packages[1..]);
+            println!("{} {} needs: {}", name, entry.version, needs);
+        }
+
+        let paths = packages
+            .iter()
+            .map(|(name, entry)| self.archive(name, entry, archives))
+            .collect::<Result<Vec<_>, _>>()?;
+        for path in paths {
+            local::unpack(&path, dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Add a Debian (APT) repository")
+        .arg(
+            Arg::with_name("uri")
+                .long("uri")
+                .short("u")
+                .takes_value(true)
+                .value_name("URI")
+                .default_value(DEFAULT_URI)
+                .help("Root of the archive, the directory with dists/ and pool/ in it"),
+        )
+        .arg(
+            Arg::with_name("suite")
+                .long("suite")
+                .short("S")
+                .takes_value(true)
+                .value_name("SUITE")
+                .default_value("stable")
+                .help("Suite or codename to use, e.g. bookworm or noble-updates"),
+        )
+        .arg(
+            Arg::with_name("components")
+                .long("components")
+                .short("c")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .value_name("COMPONENT,...")
+                .default_value("main")
+                .help("Components of the suite to use"),
+        )
+        .arg(
+            Arg::with_name("arch")
+                .long("arch")
+                .short("a")
+                .takes_value(true)
+                .value_name("ARCH")
+                .help("Architecture of the packages to use [default: this machine's]"),
+        )
+        .arg(
+            Arg::with_name("keyring")
+                .long("keyring")
+                .short("k")
+                .takes_value(true)
+                .value_name("PATH")
+                .help(
+                    "OpenPGP keyring the repo is signed with [default: Debian's archive keyring]",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-insecure")
+                .long("allow-insecure")
+                .conflicts_with("keyring")
+                .help("Use the repo without checking its signature or expiry (or checksums it doesn't have)"),
+        )
+}
+
+fn run(args: &ArgMatches) -> Result<Repo, Box<dyn Error>> {
+    let uri = args.value_of("uri").unwrap().trim_end_matches('/');
+    if !["http://", "https://", "file://"]
+        .iter()
+        .any(|scheme| uri.starts_with(scheme))
+    {
+        return Err(Box::new(DebianError::WrongScheme(uri.to_string())));
+    }
+
+    let arch = match args.value_of("arch") {
+        Some(arch) => arch,
+        None => host_arch().ok_or(DebianError::UnknownArch)?,
+    };
+
+    Ok(Repo::Debian(DebianRepo {
+        uri: uri.to_string(),
+        suite: args.value_of("suite").unwrap().to_string(),
+        components: args
+            .values_of("components")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        arch: arch.to_string(),
+        keyring: args.value_of_os("keyring").map(PathBuf::from),
+        allow_insecure: args.is_present("allow-insecure"),
+    }))
+}
+
+pub(super) static CMD: crate::SubCommand<Repo> = crate::SubCommand { args, run };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::solver::SolveError;
+    use std::{
+        ffi::OsStr,
+        fs,
+        os::unix::fs::PermissionsExt,
+        process::{Command, Stdio},
+    };
+    use tempfile::TempDir;
+
+    fn run(program: &str, args: &[&OsStr]) {
+        let status = Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} failed", program);
+    }
+
+    /// Build a .deb for each `(name, version, extra control fields)` and publish them as the
+    /// `test` suite of a mirror in `<tmp>/mirror`, with `fields` added to its Release file.
+    /// With `sign`, the Release file is clearsigned as InRelease with a fresh key exported to
+    /// `<tmp>/key.asc`.
+    fn publish(
+        tmp: &Path,
+        packages: &[(&str, &str, &str)],
+        fields: &str,
+        sign: bool,
+    ) -> DebianRepo {
+        let mirror = tmp.join("mirror");
+        let (pool, binary) = (
+            mirror.join("pool/main"),
+            mirror.join("dists/test/main/binary-amd64"),
+        );
+        fs::create_dir_all(&pool).unwrap();
+        fs::create_dir_all(&binary).unwrap();
+
+        let mut index = String::new();
+        for (name, version, fields) in packages {
+            let root = tmp.join("src").join(name);
+            fs::create_dir_all(root.join("DEBIAN")).unwrap();
+            fs::create_dir_all(root.join("usr/share").join(name)).unwrap();
+            fs::write(root.join("usr/share").join(name).join("README"), name).unwrap();
+            let control = format!(
+                "Package: {}\nVersion: {}\nArchitecture: amd64\nMaintainer: storm\n\
+                 Description: {}\n{}",
+                name, version, name, fields
+            );
+            fs::write(root.join("DEBIAN/control"), &control).unwrap();
+
+            let filename = format!("pool/main/{}_{}_amd64.deb", name, version);
+            let deb = mirror.join(&filename);
+            run(
+                "dpkg-deb",
+                &[
+                    "--root-owner-group".as_ref(),
+                    "--build".as_ref(),
+                    root.as_os_str(),
+                    deb.as_os_str(),
+                ],
+            );
+            let data = fs::read(&deb).unwrap();
+            index.push_str(&format!(
+                "{}Filename: {}\nSize: {}\nSHA256: {}\n\n",
+                control,
+                filename,
+                data.len(),
+                sha256(&data)
+            ));
+        }
+        let packages = binary.join("Packages");
+        fs::write(&packages, index).unwrap();
+        run("xz", &["--keep".as_ref(), packages.as_os_str()]);
+
+        let mut release = format!(
+            "Suite: test\nDate: Sat, 17 Oct 2026 00:00:00 UTC\n{}\
+             Architectures: amd64\nComponents: main\nSHA256:\n",
+            fields
+        );
+        for name in ["Packages", "Packages.xz"] {
+            let data = fs::read(binary.join(name)).unwrap();
+            release.push_str(&format!(
+                " {} {} main/binary-amd64/{}\n",
+                sha256(&data),
+                data.len(),
+                name
+            ));
+        }
+        let release_path = mirror.join("dists/test/Release");
+        fs::write(&release_path, release).unwrap();
+
+        let mut keyring = None;
+        if sign {
+            let gnupg = tmp.join("gnupg");
+            fs::create_dir(&gnupg).unwrap();
+            fs::set_permissions(&gnupg, fs::Permissions::from_mode(0o700)).unwrap();
+            let gpg = |args: &[&OsStr]| {
+                let mut all = vec![
+                    "--homedir".as_ref(),
+                    gnupg.as_os_str(),
+                    "--batch".as_ref(),
+                    "--quiet".as_ref(),
+                    "--passphrase=".as_ref(),
+                ];
+                all.extend(args);
+                run("gpg", &all);
+            };
+            gpg(&[
+                "--quick-gen-key".as_ref(),
+                "storm <storm@localhost>".as_ref(),
+                "ed25519".as_ref(),
+                "sign".as_ref(),
+                "never".as_ref(),
+            ]);
+            let key = tmp.join("key.asc");
+            gpg(&[
+                "--armor".as_ref(),
+                "--output".as_ref(),
+                key.as_os_str(),
+                "--export".as_ref(),
+            ]);
+            let in_release = mirror.join("dists/test/InRelease");
+            gpg(&[
+                "--output".as_ref(),
+                in_release.as_os_str(),
+                "--clearsign".as_ref(),
+                release_path.as_os_str(),
+            ]);
+            let _ = Command::new("gpgconf")
+                .arg("--homedir")
+                .arg(&gnupg)
+                .args(["--kill", "gpg-agent"])
+                .status();
+            keyring = Some(key);
+        }
+
+        DebianRepo {
+            uri: format!("file://{}", mirror.display()),
+            suite: "test".to_string(),
+            components: vec!["main".to_string()],
+            arch: "amd64".to_string(),
+            allow_insecure: keyring.is_none(),
+            keyring,
+        }
+    }
+
+    #[test]
+    fn syncs_and_unpacks_from_a_local_mirror() {
+        let tmp = TempDir::new().unwrap();
+        let repo = publish(
+            tmp.path(),
+            &[
+                ("libfoo1", "1.2-1", ""),
+                (
+                    "foo",
+                    "1.0-1",
+                    "Depends: libfoo1 (>= 1.2), missing | libfoo1\n",
+                ),
+            ],
+            "Valid-Until: Fri, 01 Jan 2100 00:00:00 UTC\n",
+            true,
+        );
+
+        let index = repo.sync().unwrap();
+        assert_eq!(index.names().collect::<Vec<_>>(), ["foo", "libfoo1"]);
+        let entry = &index.get("foo")[0];
+        assert_eq!(entry.version.to_string(), "1.0-1");
+        assert_eq!(entry.depends, ["libfoo1 (>= 1.2)", "missing | libfoo1"]);
+        assert_eq!(
+            index.revision.as_deref(),
+            Some("Sat, 17 Oct 2026 00:00:00 UTC")
+        );
+
+        let (archives, dir) = (tmp.path().join("archives"), tmp.path().join("root"));
+        repo.build(&index, "foo", entry, &archives, &dir).unwrap();
+        assert!(dir.join("usr/share/foo/README").exists());
+        assert!(dir.join("usr/share/libfoo1/README").exists());
+        assert!(archives.join("libfoo1_1.2-1_amd64.deb").exists());
+    }
+
+    #[test]
+    fn rejects_indexes_and_packages_that_dont_match_their_sums() {
+        let tmp = TempDir::new().unwrap();
+        let repo = publish(tmp.path(), &[("foo", "1.0", "")], "", false);
+        let mirror = tmp.path().join("mirror");
+
+        let index = repo.sync().unwrap();
+        let deb = mirror.join("pool/main/foo_1.0_amd64.deb");
+        let mut data = fs::read(&deb).unwrap();
+        data.push(0);
+        fs::write(&deb, data).unwrap();
+        let (archives, dir) = (tmp.path().join("archives"), tmp.path().join("root"));
+        let err = repo
+            .build(&index, "foo", &index.get("foo")[0], &archives, &dir)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(DebianError::ChecksumMismatch(_))
+        ));
+
+        let packages = mirror.join("dists/test/main/binary-amd64/Packages.xz");
+        let mut data = fs::read(&packages).unwrap();
+        data.push(0);
+        fs::write(&packages, data).unwrap();
+        let err = repo.sync().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(DebianError::ChecksumMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_expired_releases() {
+        let tmp = TempDir::new().unwrap();
+        let expired = "Valid-Until: Sat, 01 Jan 2000 00:00:00 UTC\n";
+        let mut repo = publish(tmp.path(), &[("foo", "1.0", "")], expired, true);
+
+        let err = repo.sync().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DebianError::Expired(..))));
+        repo.allow_insecure = true;
+        assert_eq!(repo.sync().unwrap().names().collect::<Vec<_>>(), ["foo"]);
+    }
+
+    #[test]
+    fn parses_release_dates() {
+        assert_eq!(
+            parse_date("Sat, 17 Oct 2026 00:00:00 UTC"),
+            Some(1_792_195_200)
+        );
+        assert_eq!(parse_date("29 Feb 2000 12:30:15 +0100"), Some(951_823_815));
+        assert_eq!(parse_date("Sat, 17 Oct 2026 00:00 UTC"), None);
+        assert_eq!(parse_date("Sat, 17 Okt 2026 00:00:00 UTC"), None);
+    }
+
+    /// An index of `(name, version, depends, provides, conflicts)` packages, with each field
+    /// written as in a Packages file.
+    fn index(packages: &[(&str, &str, &str, &str, &str)]) -> PackageIndex {
+        let mut index = PackageIndex::default();
+        for (name, version, depends, provides, conflicts) in packages {
+            let mut entry = IndexEntry::new(version.parse().unwrap());
+            entry.depends = relations(Some(&depends.to_string()));
+            entry.provides = relations(Some(&provides.to_string()));
+            entry.conflicts = relations(Some(&conflicts.to_string()));
+            index.insert(name.to_string(), entry);
+        }
+        index
+    }
+
+    fn solve(index: &PackageIndex, name: &str) -> Result<Vec<String>, SolveError> {
+        let entry = &index.get(name)[0];
+        let packages = Solver::new(index, relation::parse, version::compare).solve(name, entry)?;
+        Ok(packages
+            .iter()
+            .map(|(name, entry)| format!("{} {}", name, entry.version))
+            .collect())
+    }
+
+    #[test]
+    fn solves_versioned_relations_in_dpkg_order() {
+        let index = index(&[
+            ("app", "1", "libfoo (>= 1.2.1)", "", ""),
+            ("old", "1", "libfoo (<< 1.2.1)", "", ""),
+            ("any", "1", "libfoo", "", ""),
+            ("libfoo", "1.2.1-1", "", "", ""),
+            ("libfoo", "1.2-10", "", "", ""),
+        ]);
+        assert_eq!(solve(&index, "app").unwrap(), ["app 1", "libfoo 1.2.1-1"]);
+        assert_eq!(solve(&index, "old").unwrap(), ["old 1", "libfoo 1.2-10"]);
+        assert_eq!(solve(&index, "any").unwrap(), ["any 1", "libfoo 1.2.1-1"]);
+    }
+
+    #[test]
+    fn tries_alternatives_in_order_then_providers() {
+        let index = index(&[
+            (
+                "app",
+                "1",
+                "missing | mail-transport-agent, sed | busybox",
+                "",
+                "",
+            ),
+            ("exim4", "4.96", "", "mail-transport-agent", ""),
+            ("busybox", "1.36", "", "", ""),
+            ("sed", "4.9", "", "", ""),
+        ]);
+        assert_eq!(
+            solve(&index, "app").unwrap(),
+            ["app 1", "exim4 4.96", "sed 4.9"]
+        );
+    }
+
+    #[test]
+    fn backtracks_around_conflicts() {
+        let index = index(&[
+            ("app", "1", "libfoo, libbar", "", ""),
+            ("libfoo", "2", "", "", "libbar (<< 2)"),
+            ("libfoo", "1", "", "", ""),
+            ("libbar", "1", "", "", ""),
+        ]);
+        assert_eq!(
+            solve(&index, "app").unwrap(),
+            ["app 1", "libfoo 1", "libbar 1"]
+        );
+    }
+
+    #[test]
+    fn explains_unsatisfiable_relations() {
+        let index = index(&[
+            ("app", "1", "libfoo (>= 1.2.1)", "", ""),
+            ("libfoo", "1.2-10", "", "", ""),
+            ("other", "1", "nothing", "", ""),
+        ]);
+        assert_eq!(
+            solve(&index, "app").unwrap_err().to_string(),
+            "app 1 needs 'libfoo (>= 1.2.1)', but the repo only has libfoo 1.2-10"
+        );
+        assert_eq!(
+            solve(&index, "other").unwrap_err().to_string(),
+            "other 1 needs 'nothing', but the repo has no package nothing"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use super::DebianError;
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fs, io,
+    path::Path,
+    process::{Command, Stdio},
+};
+use tempfile::TempDir;
+
+/// A throwaway gpg home holding only a repo's keyring, so no other key can vouch for it.
+pub(super) struct Keyring {
+    home: TempDir,
+}
+
+impl Keyring {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.is_file() {
+            return Err(Box::new(DebianError::NoKeyring(path.to_path_buf())));
+        }
+
+        // a fresh directory (only we can use) that no one could have planted keys in beforehand
+        let home = tempfile::Builder::new().prefix("storm-gpg-").tempdir()?;
+        let keyring = Self { home };
+        keyring
+            .gpg(&["--import".as_ref(), path.as_os_str()])
+            .map_err(|err| DebianError::InvalidKeyring(path.to_path_buf(), err))?;
+        Ok(keyring)
+    }
+
+    /// Run gpg, returning what it had to say if it failed.
+    fn gpg(&self, args: &[&OsStr]) -> Result<(), String> {
+        // the keyring was configured explicitly, so it's trusted without needing certification
+        let output = Command::new("gpg")
+            .arg("--homedir")
+            .arg(self.home.path())
+            .args(["--batch", "--quiet", "--trust-model=always"])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => DebianError::MissingClient("gpg").to_string(),
+                _ => e.to_string(),
+            })?;
+
+        if !output.status.success() {
+            // the last line is the verdict, after details like which key made the signature
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let verdict = stderr.trim().lines().last().unwrap_or_default();
+            return Err(verdict.trim_start_matches("gpg: ").to_string());
+        }
+        Ok(())
+    }
+
+    /// Check a clearsigned file like InRelease, returning only the text that was signed.
+    pub fn verify_clearsigned(
+        &self,
+        name: &str,
+        contents: &[u8],
+    ) -> Result<String, Box<dyn Error>> {
+        let home = self.home.path();
+        let (path, text) = (home.join(name), home.join("signed-text"));
+        fs::write(&path, contents)?;
+        // --decrypt writes out what the signature covers, and nothing around it
+        self.gpg(&[
+            "--yes".as_ref(),
+            "--output".as_ref(),
+            text.as_os_str(),
+            "--decrypt".as_ref(),
+            path.as_os_str(),
+        ])
+        .map_err(|err| DebianError::BadSignature(name.to_string(), err))?;
+        Ok(fs::read_to_string(text)?)
+    }
+
+    /// Check a file against a detached signature, like Release and Release.gpg.
+    pub fn verify_detached(
+        &self,
+        name: &str,
+        contents: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let path = self.home.path().join(name);
+        let signature_path = path.with_extension("sig");
+        fs::write(&path, contents)?;
+        fs::write(&signature_path, signature)?;
+        self.gpg(&[
+            "--verify".as_ref(),
+            signature_path.as_os_str(),
+            path.as_os_str(),
+        ])
+        .map_err(|err| DebianError::BadSignature(name.to_string(), err))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{
+    repo::solver::{Bound, SolveError, Target},
+    version::{Constraint, Op},
+};
+
+/// Parse a relation like `libc6:any (>= 2.34)`, as used in Depends, Provides and Conflicts.
+pub(super) fn parse(s: &str) -> Result<Target, SolveError> {
+    let invalid = || SolveError::InvalidRelation(s.to_string());
+
+    let s = s.trim();
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '(' || c == '[')
+        .unwrap_or(s.len());
+    // `:any` and `:native` only matter with multiarch, and storm installs a single arch
+    let name = s[..end].split(':').next().unwrap();
+    if name.is_empty() {
+        return Err(invalid());
+    }
+
+    // anything after the version (architecture lists, build profiles) is for source
+    // packages, which storm doesn't use
+    let bound = match s[end..].trim_start().strip_prefix('(') {
+        Some(rest) => {
+            let inner = rest[..rest.find(')').ok_or_else(invalid)?].trim();
+            let (op, version) = [
+                ("<<", Op::Lt),
+                ("<=", Op::Le),
+                (">>", Op::Gt),
+                (">=", Op::Ge),
+                ("=", Op::Eq),
+                // obsolete spellings, which dpkg reads as <= and >=
+                ("<", Op::Le),
+                (">", Op::Ge),
+            ]
+            .iter()
+            .find(|(prefix, _)| inner.starts_with(prefix))
+            .map(|(prefix, op)| (*op, inner[prefix.len()..].trim()))
+            .ok_or_else(invalid)?;
+            let version = version.parse().map_err(|_| invalid())?;
+            Some(Bound::Constraint(Constraint { op, version }))
+        }
+        None => None,
+    };
+
+    Ok(Target {
+        name: name.to_string(),
+        bound,
+    })
+}
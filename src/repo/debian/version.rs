@@ -0,0 +1,115 @@
+use crate::version::Version;
+use std::cmp::Ordering;
+
+/// Split a version into its epoch, upstream version and revision, as in `1:2.36-9`.
+fn split(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+        None => (0, version),
+    };
+    // upstream versions can have hyphens in them, but revisions can't
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+/// Where a character sorts: `~` before anything (even the end), then letters, then
+/// everything else.
+fn order(c: Option<&u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(b'~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(&c) if c.is_ascii_alphabetic() => c as i32,
+        Some(&c) => c as i32 + 256,
+    }
+}
+
+/// Compare upstream versions or revisions the way dpkg does, alternating between runs of
+/// non-digits (compared character by character) and runs of digits (compared numerically).
+fn compare_part(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    while !a.is_empty() || !b.is_empty() {
+        let is_text = |s: &[u8]| s.first().is_some_and(|c| !c.is_ascii_digit());
+        while is_text(a) || is_text(b) {
+            let ordering = order(a.first()).cmp(&order(b.first()));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            a = a.get(1..).unwrap_or_default();
+            b = b.get(1..).unwrap_or_default();
+        }
+
+        let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+        let (a_len, b_len) = (digits(a), digits(b));
+        let trim = |s: &[u8]| {
+            let zeros = s.iter().take_while(|&&c| c == b'0').count();
+            s[zeros..].to_vec()
+        };
+        let (a_num, b_num) = (trim(&a[..a_len]), trim(&b[..b_len]));
+        let ordering = a_num
+            .len()
+            .cmp(&b_num.len())
+            .then_with(|| a_num.cmp(&b_num));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+    Ordering::Equal
+}
+
+/// Compare Debian versions the way dpkg does: by epoch, then upstream version, then
+/// revision. Unlike storm's own ordering, separators count, so `1.2-10` is older than
+/// `1.2.1-1`.
+pub fn compare(a: &Version, b: &Version) -> Ordering {
+    let (a, b) = (a.to_string(), b.to_string());
+    let (a_epoch, a_upstream, a_revision) = split(&a);
+    let (b_epoch, b_upstream, b_revision) = split(&b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_part(a_upstream.as_bytes(), b_upstream.as_bytes()))
+        .then_with(|| compare_part(a_revision.as_bytes(), b_revision.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> Ordering {
+        compare(&a.parse().unwrap(), &b.parse().unwrap())
+    }
+
+    #[test]
+    fn orders_like_dpkg() {
+        let ordered = [
+            "1.0~~", "1.0~~a", "1.0~", "1.0", "1.0-1", "1.0-2", "1.0-10", "1.0a", "1.0+b1",
+            "1.0.1", "1.2-10", "1.2.1-1", "1.10", "9:0.1", "10:0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(
+                cmp(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(cmp(pair[1], pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn ignores_leading_zeros_and_zero_epochs() {
+        assert_eq!(cmp("1.00", "1.0"), Ordering::Equal);
+        assert_eq!(cmp("0:1.0-1", "1.0-1"), Ordering::Equal);
+        assert_eq!(cmp("1.0-0", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn splits_revisions_at_the_last_hyphen() {
+        assert_eq!(split("1:2.3-rc1-4"), (1, "2.3-rc1", "4"));
+        assert_eq!(split("2.3"), (0, "2.3", ""));
+        assert_eq!(cmp("2.3-rc1-4", "2.3-rc2-1"), Ordering::Less);
+    }
+}
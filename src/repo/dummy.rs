@@ -1,17 +1,64 @@
 use super::Repo;
-use clap::{App, ArgMatches};
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    version::Version,
+};
+use clap::{App, Arg, ArgMatches};
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, error::Error};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub struct DummyRepo;
+pub struct DummyRepo {
+    // lets the dummy repo stand in for a real one when testing
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    packages: BTreeMap<String, Vec<Version>>,
+}
+
+impl DummyRepo {
+    pub(super) fn sync(&self) -> PackageIndex {
+        let mut index = PackageIndex::default();
+        let mut digest = Sha256::new();
+        for (name, versions) in &self.packages {
+            for version in versions {
+                index.insert(name.clone(), IndexEntry::new(version.clone()));
+                digest.update(format!("{}@{}\n", name, version));
+            }
+        }
+        // the packages are all there is to a dummy repo, so they make up its revision
+        index.revision = Some(format!("{:x}", digest.finalize())[..12].to_string());
+        index
+    }
+}
 
 fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
-    app.about("Add a dummy repository")
+    app.about("Add a dummy repository").arg(
+        Arg::with_name("package")
+            .long("package")
+            .short("P")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME@VERSION")
+            .help("Pretend the repository provides a package"),
+    )
 }
 
-fn run(_args: &ArgMatches) -> Result<Repo, Box<dyn Error>> {
-    Ok(Repo::Dummy(DummyRepo::default()))
+fn run(args: &ArgMatches) -> Result<Repo, Box<dyn Error>> {
+    let mut repo = DummyRepo::default();
+
+    for package in args.values_of("package").into_iter().flatten() {
+        let (name, version) = match package.find('@') {
+            Some(idx) => (&package[..idx], package[idx + 1..].parse()?),
+            None => (package, "0".parse()?),
+        };
+        repo.packages
+            .entry(name.to_string())
+            .or_default()
+            .push(version);
+    }
+
+    Ok(Repo::Dummy(repo))
 }
 
 pub(super) static CMD: crate::SubCommand<Repo> = crate::SubCommand { args, run };
@@ -0,0 +1,67 @@
+use quick_error::quick_error;
+use std::{
+    error::Error,
+    fs, io,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum FetchError {
+        MissingCurl {
+            display("'curl' isn't installed (or isn't in $PATH)")
+        }
+        Failed(uri: String, err: String) {
+            display("couldn't download '{}': {}", uri, err)
+        }
+    }
+}
+
+/// Download a file (over HTTP or from a `file://` URI) into memory.
+pub(super) fn fetch(uri: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", uri])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| -> Box<dyn Error> {
+            if e.kind() == io::ErrorKind::NotFound {
+                Box::new(FetchError::MissingCurl)
+            } else {
+                Box::new(e)
+            }
+        })?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(Box::new(FetchError::Failed(
+            uri.to_string(),
+            err.trim().trim_start_matches("curl: ").to_string(),
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Download a package file to `path`, unless a copy that passes `verify` is already there
+/// (the repo may have rebuilt the package since it was downloaded).
+pub(super) fn fetch_cached<F>(uri: &str, path: &Path, verify: F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(&[u8]) -> Result<(), Box<dyn Error>>,
+{
+    if fs::read(path).is_ok_and(|data| verify(&data).is_ok()) {
+        return Ok(());
+    }
+
+    println!("fetching {}", uri);
+    let data = fetch(uri)?;
+    verify(&data)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // write the whole file in one go so an interrupted download is never reused
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
@@ -1,26 +1,711 @@
+use super::Repo;
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    package::Variants,
+    sandbox::SandboxConfig,
+    store::Store,
+    version::Version,
+};
+use clap::{App, Arg, ArgMatches};
+use layout::Layout;
+use profile::Profile;
+use quick_error::quick_error;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+mod atom;
+mod cvs;
+mod ebuild;
+mod git;
+mod layout;
+mod manifest;
+mod md5_cache;
+mod profile;
+pub(super) mod repos_conf;
+mod rsync;
+mod svn;
+mod webrsync;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum GentooError {
+        RelativeLocation(location: PathBuf) {
+            display("repo location '{}' must be an absolute path", location.display())
+        }
+        UnknownSyncType(sync_type: String) {
+            display("unknown sync type '{}'", sync_type)
+        }
+        NoCvsModule {
+            display("cvs repos need the module to check out; pass --sync-cvs-repo")
+        }
+        NoDefaultUri(sync_type: SyncType) {
+            display("{} repos have no default sync URI; pass --sync-uri", sync_type)
+        }
+        WrongScheme(uri: String, sync_type: SyncType) {
+            display("'{}' can't be synced with {} (expected {})", uri, sync_type, sync_type.schemes().join(", "))
+        }
+        MissingClient(program: String) {
+            display("'{}' isn't installed (or isn't in $PATH)", program)
+        }
+        CommandFailed(command: String, status: std::process::ExitStatus) {
+            display("'{}' failed ({})", command, status)
+        }
+        InterruptedSwap(location: PathBuf, old: PathBuf, err: Box<dyn Error>) {
+            display("an earlier sync of {} was interrupted and its old tree couldn't be restored \
+                     from {} ({}); move it back by hand", location.display(), old.display(), err)
+        }
+        NoSnapshot(uri: String) {
+            display("couldn't find a recent snapshot at '{}'", uri)
+        }
+        ChecksumMismatch(file: String) {
+            display("checksum of '{}' doesn't match; it may be corrupt or tampered with", file)
+        }
+        NoKey(path: PathBuf) {
+            display("OpenPGP key '{}' doesn't exist; pass --sync-openpgp-key-path", path.display())
+        }
+        BadSignature(file: String) {
+            display("signature of '{}' couldn't be verified with the repo's OpenPGP key", file)
+        }
+        InvalidLayout(key: String, value: String) {
+            display("metadata/layout.conf: '{}' must be true or false, not '{}'", key, value)
+        }
+        InvalidAtom(atom: String) {
+            display("invalid atom '{}'", atom)
+        }
+        NoProfile(path: PathBuf) {
+            display("profile '{}' doesn't exist", path.display())
+        }
+        ProfileCycle(path: PathBuf) {
+            display("profile '{}' is its own parent", path.display())
+        }
+        UnknownProfileRepo(name: String) {
+            display("profile refers to repo '{}', which isn't the repo or one of its masters", name)
+        }
+        InvalidManifest(path: PathBuf, line: usize) {
+            display("{}:{}: invalid Manifest entry", path.display(), line)
+        }
+        NoManifest(dir: PathBuf) {
+            display("'{}' has no Manifest", dir.display())
+        }
+        NotInManifest(path: PathBuf) {
+            display("'{}' isn't listed in its Manifest", path.display())
+        }
+        MissingFromTree(path: PathBuf) {
+            display("'{}' is listed in its Manifest but doesn't exist", path.display())
+        }
+        ManifestMismatch(path: PathBuf, what: &'static str) {
+            display("{} of '{}' doesn't match its Manifest; it may be corrupt or tampered with",
+                    what, path.display())
+        }
+        NoSupportedHash(path: PathBuf) {
+            display("the Manifest entry for '{}' has no BLAKE2B or SHA512 hash", path.display())
+        }
+        VerifyFailed(failed: usize) {
+            display("{} packages or directories failed verification", failed)
+        }
+        MissingMaster(location: PathBuf, master: String) {
+            display("{} needs master repo '{}', but no synced repo is named that; add it and sync it first",
+                    location.display(), master)
+        }
+        NoMetadata(path: PathBuf) {
+            display("'{}' doesn't exist; the repo may need to be synced again", path.display())
+        }
+        UnsupportedEapi(package: String, eapi: String) {
+            display("{} uses EAPI {}, which storm can't build", package, eapi)
+        }
+        FetchRestricted(file: String, distdir: PathBuf) {
+            display("'{}' can't be downloaded automatically (RESTRICT=fetch); put it in {} by hand",
+                    file, distdir.display())
+        }
+        FetchFailed(file: String) {
+            display("couldn't download '{}' from any of its sources", file)
+        }
+        Unverified(path: PathBuf) {
+            display("'{}' can't be verified, since the tree has no tree-wide Manifest; add the repo \
+                     with --allow-unverified to build from it anyway", path.display())
+        }
+        BuildFailed(package: String, dir: PathBuf, err: Box<dyn Error>) {
+            display("building {} failed: {} (its build directory was kept at {})",
+                    package, err, dir.display())
+        }
+    }
+}
+
+/// Whether a sync failed only because the tree needs a master that isn't there (yet).
+pub(super) fn is_missing_master(err: &(dyn Error + 'static)) -> bool {
+    matches!(err.downcast_ref(), Some(GentooError::MissingMaster(..)))
+}
+
+const DEFAULT_LOCATION: &str = "/var/db/repos/gentoo";
+
+/// Directories in a tree that belong to this machine rather than the repo, which syncing
+/// must never replace or delete.
+const LOCAL_DIRS: &[&str] = &["distfiles", "local", "packages"];
+
+/// Where a tree keeps its eclasses.
+const ECLASS_DIR: &str = "eclass";
+
+/// Directories at the top of a tree that hold something other than categories of packages.
+const NON_CATEGORY_DIRS: &[&str] = &["eclass", "licenses", "metadata", "profiles", "scripts"];
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct GentooRepo {
     location: PathBuf,
-    sync_type: SyncType,
-    sync_uri: String,
+    /// How to sync the repo, or `None` for a local repo that isn't synced from anywhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sync_type: Option<SyncType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sync_uri: Option<String>,
+    /// How many commits of history to fetch when syncing with git, or all of them if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sync_depth: Option<u32>,
+    /// The public key snapshots must be signed with when syncing with webrsync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sync_openpgp_key_path: Option<PathBuf>,
+    /// The module to check out when syncing with CVS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sync_cvs_repo: Option<String>,
+    /// The profile to use, relative to the repo's `profiles` directory or (as
+    /// `repo:path`) a master's. Only repo-wide masks apply without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    /// Keywords to accept on top of the profile's `ACCEPT_KEYWORDS`, like in make.conf.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accept_keywords: Option<String>,
+    /// Build from the tree even if it has no tree-wide Manifest to check ebuilds, eclasses
+    /// and the metadata cache against.
+    #[serde(default)]
+    allow_unverified: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-enum SyncType {
+pub enum SyncType {
     Cvs,
     Git,
+    #[default]
     Rsync,
     Svn,
     WebRsync,
 }
 
-impl Default for SyncType {
-    fn default() -> Self {
-        Self::Rsync
+impl SyncType {
+    /// Where the main Gentoo tree can be synced from, if it's published this way.
+    fn default_uri(self) -> Option<&'static str> {
+        match self {
+            SyncType::Git => Some("https://anongit.gentoo.org/git/repo/sync/gentoo.git"),
+            SyncType::Rsync => Some("rsync://rsync.gentoo.org/gentoo-portage"),
+            SyncType::WebRsync => Some("https://distfiles.gentoo.org/snapshots"),
+            SyncType::Cvs | SyncType::Svn => None,
+        }
+    }
+
+    /// URI prefixes the sync type's client understands. `/` is a path on this machine.
+    fn schemes(self) -> &'static [&'static str] {
+        match self {
+            SyncType::Cvs => &[":pserver:", ":ext:", ":local:", "/"],
+            SyncType::Git => &["https://", "http://", "git://", "ssh://", "file://", "/"],
+            SyncType::Rsync => &["rsync://", "ssh://", "/"],
+            SyncType::Svn => &["svn://", "svn+ssh://", "https://", "http://", "file://"],
+            SyncType::WebRsync => &["https://", "http://", "file://"],
+        }
+    }
+
+    fn check_uri(self, uri: &str) -> Result<(), GentooError> {
+        if self.schemes().iter().any(|scheme| uri.starts_with(scheme)) {
+            Ok(())
+        } else {
+            Err(GentooError::WrongScheme(uri.to_string(), self))
+        }
+    }
+}
+
+impl Display for SyncType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SyncType::Cvs => "cvs",
+            SyncType::Git => "git",
+            SyncType::Rsync => "rsync",
+            SyncType::Svn => "svn",
+            SyncType::WebRsync => "webrsync",
+        })
+    }
+}
+
+impl FromStr for SyncType {
+    type Err = GentooError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cvs" => Ok(SyncType::Cvs),
+            "git" => Ok(SyncType::Git),
+            "rsync" => Ok(SyncType::Rsync),
+            "svn" => Ok(SyncType::Svn),
+            "webrsync" => Ok(SyncType::WebRsync),
+            _ => Err(GentooError::UnknownSyncType(s.to_string())),
+        }
+    }
+}
+
+/// Run a sync client, passing its progress output through, and return what it printed to
+/// stdout with surrounding whitespace trimmed.
+fn run_client<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    dir: Option<&Path>,
+) -> Result<String, Box<dyn Error>> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+
+    let output = command.output().map_err(|e| -> Box<dyn Error> {
+        if e.kind() == io::ErrorKind::NotFound {
+            Box::new(GentooError::MissingClient(program.to_string()))
+        } else {
+            Box::new(e)
+        }
+    })?;
+
+    if !output.status.success() {
+        let command = args.iter().fold(program.to_string(), |command, arg| {
+            format!("{} {}", command, arg.as_ref().to_string_lossy())
+        });
+        return Err(Box::new(GentooError::CommandFailed(command, output.status)));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Convert days since the epoch to a (year, month, day) date, per
+/// howardhinnant.github.io/date_algorithms.html.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Move this machine's own directories from an old tree into the one replacing it. This
+/// fails rather than replacing a non-empty directory the new tree already has.
+fn carry_over(old: &Path, location: &Path) -> Result<(), Box<dyn Error>> {
+    for dir in LOCAL_DIRS {
+        match fs::rename(old.join(dir), location.join(dir)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Box::new(e)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl GentooRepo {
+    /// A scratch path next to the location, for syncs to download into before anything
+    /// in the tree is changed.
+    fn staging_path(&self) -> PathBuf {
+        let mut name = OsString::from(".");
+        name.push(self.location.file_name().unwrap_or_default());
+        name.push(".storm-sync");
+        self.location.with_file_name(name)
+    }
+
+    /// Remove whatever an earlier sync left in the staging path. A swap that was interrupted
+    /// leaves the old tree (with this machine's distfiles and such) in `<staging>/old`, so
+    /// that's finished or undone first: if the location is gone the old tree goes back, and
+    /// otherwise the new tree is already in place and only the local directories are left
+    /// to carry over.
+    fn clear_staging(&self) -> Result<(), Box<dyn Error>> {
+        let staging = self.staging_path();
+        let old = staging.join("old");
+        if old.is_dir() {
+            let restored = if self.location.exists() {
+                carry_over(&old, &self.location)
+            } else {
+                fs::rename(&old, &self.location).map_err(Into::into)
+            };
+            if let Err(err) = restored {
+                return Err(Box::new(GentooError::InterruptedSwap(
+                    self.location.clone(),
+                    old,
+                    err,
+                )));
+            }
+        }
+
+        match fs::remove_dir_all(&staging) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Box::new(e)),
+            _ => Ok(()),
+        }
     }
+
+    /// Check the layout of a synced tree before it replaces the one in the location, so an
+    /// update that needs a master that isn't among `others` can't leave the repo unusable.
+    fn check_incoming(
+        &self,
+        layout: &Layout,
+        others: &[&GentooRepo],
+    ) -> Result<(), Box<dyn Error>> {
+        self.masters_in(layout, others.iter().copied()).map(drop)
+    }
+
+    /// Make the first checkout of a repo into the staging path and move it into place, so a
+    /// failed checkout leaves nothing behind.
+    fn check_out<F>(&self, others: &[&GentooRepo], check_out: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce(&Path) -> Result<(), Box<dyn Error>>,
+    {
+        let staging = self.staging_path();
+        self.clear_staging()?;
+        if let Some(parent) = self.location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let checked_out = check_out(&staging)
+            .and_then(|_| self.check_incoming(&Layout::read(&staging)?, others))
+            .and_then(|_| fs::rename(&staging, &self.location).map_err(Into::into));
+        if checked_out.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        checked_out
+    }
+
+    /// Check the settings `repo add` and `repo import-portage` can't rule out up front.
+    fn validate(&self) -> Result<(), GentooError> {
+        if !self.location.is_absolute() {
+            return Err(GentooError::RelativeLocation(self.location.clone()));
+        }
+
+        if let Some(sync_type) = self.sync_type {
+            match &self.sync_uri {
+                Some(uri) => sync_type.check_uri(uri)?,
+                None => return Err(GentooError::NoDefaultUri(sync_type)),
+            }
+            if sync_type == SyncType::Cvs && self.sync_cvs_repo.is_none() {
+                return Err(GentooError::NoCvsModule);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sync the tree and index it. `others` are the other Gentoo repos, which the masters the
+    /// synced tree needs must be among before it's put in place.
+    pub(super) fn sync(
+        &self,
+        _store: &Store,
+        others: &[&GentooRepo],
+    ) -> Result<PackageIndex, Box<dyn Error>> {
+        let uri = self.sync_uri.as_deref().unwrap_or_default();
+        let revision = match self.sync_type {
+            Some(SyncType::Git) => Some(git::sync(self, uri, others)?),
+            Some(SyncType::Rsync) => rsync::sync(self, uri, others)?,
+            Some(SyncType::WebRsync) => Some(webrsync::sync(self, uri, others)?),
+            Some(SyncType::Cvs) => Some(cvs::sync(self, uri, others)?),
+            Some(SyncType::Svn) => Some(svn::sync(self, uri, others)?),
+            None => None,
+        };
+
+        Layout::read(&self.location)?.warn_unsupported(&self.location);
+        let mut index = md5_cache::index(&self.location)?.unwrap_or_else(|| {
+            eprintln!(
+                "warning: {} has no metadata cache, so its packages can't be indexed \
+                 (egencache can generate one)",
+                self.location.display()
+            );
+            PackageIndex::default()
+        });
+        index.revision = revision;
+        Ok(index)
+    }
+
+    /// Find the repos this one's layout names as masters among `others`, by their repo names.
+    pub(super) fn masters<'a, I>(&self, others: I) -> Result<Vec<&'a GentooRepo>, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = &'a GentooRepo>,
+    {
+        self.masters_in(&Layout::read(&self.location)?, others)
+    }
+
+    fn masters_in<'a, I>(
+        &self,
+        layout: &Layout,
+        others: I,
+    ) -> Result<Vec<&'a GentooRepo>, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = &'a GentooRepo>,
+    {
+        if layout.masters.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut named = Vec::new();
+        for repo in others {
+            if let Some(name) = Layout::read(&repo.location)?.repo_name {
+                named.push((name, repo));
+            }
+        }
+
+        layout
+            .masters
+            .iter()
+            .map(|master| {
+                named
+                    .iter()
+                    .find(|(name, _)| name == master)
+                    .map(|&(_, repo)| repo)
+                    .ok_or_else(|| {
+                        GentooError::MissingMaster(self.location.clone(), master.clone()).into()
+                    })
+            })
+            .collect()
+    }
+
+    /// Why each version of a package that can't be used with the requested variants (under
+    /// the repo's profile) can't be, by version.
+    pub(super) fn masked(
+        &self,
+        masters: &[&GentooRepo],
+        name: &str,
+        entries: &[IndexEntry],
+        variants: &Variants,
+    ) -> Result<Vec<(Version, String)>, Box<dyn Error>> {
+        let profile = Profile::load(self, masters)?;
+        let repo_name = Layout::read(&self.location)?.repo_name;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| {
+                profile
+                    .mask_reason(repo_name.as_deref(), name, entry, variants)
+                    .map(|reason| (entry.version.clone(), reason))
+            })
+            .collect())
+    }
+
+    /// Where source tarballs are downloaded to, which syncing leaves alone.
+    fn distdir(&self) -> PathBuf {
+        self.location.join("distfiles")
+    }
+
+    /// Check every package in the tree against its Manifest.
+    pub(super) fn verify(&self) -> Result<(), Box<dyn Error>> {
+        let thin = Layout::read(&self.location)?.thin_manifests;
+        let tree = manifest::has_tree_manifest(&self.location);
+        let (mut verified, mut failed) = (0, 0);
+        for (category, category_path) in md5_cache::sorted_dir(&self.location)? {
+            let skip = NON_CATEGORY_DIRS.contains(&category.as_str())
+                || LOCAL_DIRS.contains(&category.as_str());
+            if skip || !category_path.is_dir() {
+                continue;
+            }
+
+            for (package, path) in md5_cache::sorted_dir(&category_path)? {
+                let has_ebuilds = path.is_dir()
+                    && md5_cache::sorted_dir(&path)?
+                        .iter()
+                        .any(|(name, _)| name.ends_with(".ebuild"));
+                if !has_ebuilds {
+                    continue;
+                }
+
+                let name = format!("{}/{}", category, package);
+                let checked = manifest::verify_package(&path, &self.distdir(), thin || tree)
+                    .and_then(|_| {
+                        if tree {
+                            manifest::verify_tree(&self.location, Path::new(&name))
+                        } else {
+                            Ok(())
+                        }
+                    });
+                match checked {
+                    Ok(()) => verified += 1,
+                    Err(e) => {
+                        eprintln!("error: {}: {}", name, e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+
+        if tree {
+            for dir in NON_CATEGORY_DIRS {
+                if !self.location.join(dir).exists() {
+                    continue;
+                }
+                if let Err(e) = manifest::verify_tree(&self.location, Path::new(dir)) {
+                    eprintln!("error: {}: {}", dir, e);
+                    failed += 1;
+                }
+            }
+        } else {
+            eprintln!(
+                "warning: {} has no tree-wide Manifest, so its eclasses, profiles and metadata \
+                 cache couldn't be verified",
+                self.location.display()
+            );
+        }
+
+        if failed != 0 {
+            return Err(Box::new(GentooError::VerifyFailed(failed)));
+        }
+        println!(
+            "verified {} packages in {}",
+            verified,
+            self.location.display()
+        );
+        Ok(())
+    }
+
+    /// Check part of the tree against its tree-wide Manifest, or refuse to use it without
+    /// one unless the repo allows that.
+    fn verify_path(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if manifest::has_tree_manifest(&self.location) {
+            manifest::verify_tree(&self.location, path)
+        } else if self.allow_unverified {
+            Ok(())
+        } else {
+            Err(Box::new(GentooError::Unverified(self.location.join(path))))
+        }
+    }
+
+    /// Build a version of a package into `dir`, with variants as USE flags (e.g. [x264,-vaapi]
+    /// is USE="x264 -vaapi").
+    pub(super) fn build(
+        &self,
+        masters: &[&GentooRepo],
+        name: &str,
+        version: &Version,
+        variants: &Variants,
+        sandbox: &SandboxConfig,
+        dir: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        // nothing from the trees gets near the sandbox unless it matches their Manifests
+        let thin = Layout::read(&self.location)?.thin_manifests;
+        let only_dist = thin || manifest::has_tree_manifest(&self.location);
+        manifest::verify_package(&self.location.join(name), &self.distdir(), only_dist)?;
+        self.verify_path(Path::new(name))?;
+        self.verify_path(&md5_cache::path(Path::new(""), name, version))?;
+        for tree in masters.iter().copied().chain([self]) {
+            tree.verify_path(Path::new(ECLASS_DIR))?;
+        }
+
+        ebuild::build(self, masters, name, version, variants, sandbox, dir)
+    }
+}
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Add a Gentoo (Portage) repository")
+        .arg(
+            Arg::with_name("location")
+                .long("location")
+                .short("l")
+                .takes_value(true)
+                .value_name("PATH")
+                .default_value(DEFAULT_LOCATION)
+                .help("Absolute path the repository is synced to"),
+        )
+        .arg(
+            Arg::with_name("sync-type")
+                .long("sync-type")
+                .short("t")
+                .takes_value(true)
+                .possible_values(&["cvs", "git", "rsync", "svn", "webrsync"])
+                .default_value("rsync")
+                .help("How to sync the repository"),
+        )
+        .arg(
+            Arg::with_name("sync-uri")
+                .long("sync-uri")
+                .short("u")
+                .takes_value(true)
+                .value_name("URI")
+                .help("Where to sync the repository from [default: the main Gentoo tree]"),
+        )
+        .arg(
+            Arg::with_name("sync-depth")
+                .long("sync-depth")
+                .takes_value(true)
+                .value_name("COMMITS")
+                .help("Only fetch this many commits of history (git only)"),
+        )
+        .arg(
+            Arg::with_name("sync-openpgp-key-path")
+                .long("sync-openpgp-key-path")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Public key snapshots must be signed with (webrsync only)"),
+        )
+        .arg(
+            Arg::with_name("sync-cvs-repo")
+                .long("sync-cvs-repo")
+                .takes_value(true)
+                .value_name("MODULE")
+                .help("Module to check out (cvs only)"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("PROFILE")
+                .help(
+                    "Profile to resolve packages with, e.g. default/linux/amd64/23.0 \
+                       (or REPO:PROFILE for a master's)",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-unverified")
+                .long("allow-unverified")
+                .help("Build from the repository even without a tree-wide Manifest to verify it"),
+        )
+        .arg(
+            Arg::with_name("accept-keywords")
+                .long("accept-keywords")
+                .takes_value(true)
+                .value_name("KEYWORDS")
+                .help("Keywords to accept on top of the profile's, e.g. \"~amd64\""),
+        )
 }
+
+fn run(args: &ArgMatches) -> Result<Repo, Box<dyn Error>> {
+    let sync_type = args.value_of("sync-type").unwrap().parse::<SyncType>()?;
+    let repo = GentooRepo {
+        location: PathBuf::from(args.value_of_os("location").unwrap()),
+        sync_type: Some(sync_type),
+        sync_uri: args
+            .value_of("sync-uri")
+            .or_else(|| sync_type.default_uri())
+            .map(str::to_string),
+        sync_depth: args
+            .value_of("sync-depth")
+            .map(str::parse)
+            .transpose()?
+            .filter(|&depth| depth != 0),
+        sync_openpgp_key_path: args.value_of_os("sync-openpgp-key-path").map(PathBuf::from),
+        sync_cvs_repo: args.value_of("sync-cvs-repo").map(str::to_string),
+        profile: args.value_of("profile").map(str::to_string),
+        accept_keywords: args.value_of("accept-keywords").map(str::to_string),
+        allow_unverified: args.is_present("allow-unverified"),
+    };
+    repo.validate()?;
+
+    Ok(Repo::Gentoo(repo))
+}
+
+pub(super) static CMD: crate::SubCommand<Repo> = crate::SubCommand { args, run };
@@ -0,0 +1,136 @@
+use super::{md5_cache::split_version, GentooError};
+use crate::{index::IndexEntry, version::Version};
+use std::{cmp::Ordering, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AtomOp {
+    Lt,
+    Le,
+    Eq,
+    /// `=1.2*`, any version starting with `1.2`.
+    Glob,
+    /// `~1.2`, any revision of `1.2`.
+    AnyRevision,
+    Ge,
+    Gt,
+}
+
+/// A package atom such as `>=media-video/ffmpeg-4.3:0::gentoo`, as used in profiles.
+/// USE dependencies (`[x264]`) are accepted but ignored, since they don't affect masking.
+#[derive(Debug, Clone)]
+pub(super) struct Atom {
+    name: String,
+    version: Option<(AtomOp, Version)>,
+    slot: Option<String>,
+    repo: Option<String>,
+}
+
+/// Strip an `-rN` revision, which `~` atoms ignore.
+pub(super) fn without_revision(version: &str) -> &str {
+    match version.rfind("-r") {
+        Some(idx) if version[idx + 2..].bytes().all(|c| c.is_ascii_digit()) => &version[..idx],
+        _ => version,
+    }
+}
+
+impl Atom {
+    /// Whether a version of a package from the repo named `repo` matches.
+    pub fn matches(&self, repo: Option<&str>, name: &str, entry: &IndexEntry) -> bool {
+        if self.name != name {
+            return false;
+        }
+        if self.repo.is_some() && self.repo.as_deref() != repo {
+            return false;
+        }
+
+        if let Some(slot) = &self.slot {
+            // `:0` matches any subslot of slot 0, `:0/1` only that one
+            let matches_slot = if slot.contains('/') {
+                *slot == entry.slot
+            } else {
+                entry.slot.split('/').next() == Some(slot)
+            };
+            if !matches_slot {
+                return false;
+            }
+        }
+
+        let (op, version) = match &self.version {
+            Some(version) => version,
+            None => return true,
+        };
+        let ordering = entry.version.compare(version);
+        match op {
+            AtomOp::Lt => ordering == Ordering::Less,
+            AtomOp::Le => ordering != Ordering::Greater,
+            AtomOp::Eq => ordering == Ordering::Equal,
+            AtomOp::Glob => entry.version.to_string().starts_with(&version.to_string()),
+            AtomOp::AnyRevision => {
+                without_revision(&entry.version.to_string()) == version.to_string()
+            }
+            AtomOp::Ge => ordering != Ordering::Less,
+            AtomOp::Gt => ordering == Ordering::Greater,
+        }
+    }
+}
+
+impl FromStr for Atom {
+    type Err = GentooError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || GentooError::InvalidAtom(s.to_string());
+
+        let mut rest = match s.find('[') {
+            Some(idx) => &s[..idx],
+            None => s,
+        };
+        let mut repo = None;
+        if let Some(idx) = rest.find("::") {
+            repo = Some(rest[idx + 2..].to_string());
+            rest = &rest[..idx];
+        }
+        let mut slot = None;
+        if let Some(idx) = rest.find(':') {
+            slot = Some(rest[idx + 1..].to_string());
+            rest = &rest[..idx];
+        }
+
+        let op = [
+            ("<=", AtomOp::Le),
+            (">=", AtomOp::Ge),
+            ("<", AtomOp::Lt),
+            (">", AtomOp::Gt),
+            ("=", AtomOp::Eq),
+            ("~", AtomOp::AnyRevision),
+        ]
+        .iter()
+        .find(|(prefix, _)| rest.starts_with(prefix));
+
+        let (name, version) = match op {
+            Some(&(prefix, mut op)) => {
+                rest = &rest[prefix.len()..];
+                if op == AtomOp::Eq {
+                    if let Some(stripped) = rest.strip_suffix('*') {
+                        rest = stripped;
+                        op = AtomOp::Glob;
+                    }
+                }
+                let (name, version) = split_version(rest).ok_or_else(invalid)?;
+                let version = version.parse::<Version>().map_err(|_| invalid())?;
+                (name, Some((op, version)))
+            }
+            None => (rest, None),
+        };
+
+        if name.split('/').count() != 2 || name.split('/').any(str::is_empty) {
+            return Err(invalid());
+        }
+
+        Ok(Atom {
+            name: name.to_string(),
+            version,
+            slot: slot.filter(|slot| !slot.is_empty()),
+            repo: repo.filter(|repo| !repo.is_empty()),
+        })
+    }
+}
@@ -0,0 +1,210 @@
+use super::{
+    civil_date,
+    layout::{Layout, LAYOUT_FILE, REPO_NAME_FILE},
+    run_client, GentooRepo,
+};
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Check out the repo's module on the first sync and update it afterwards. CVS has no
+/// repo-wide revisions, so the time of the sync is recorded instead; `cvs update -D` with
+/// it gets the same tree back.
+pub(super) fn sync(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+) -> Result<String, Box<dyn Error>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date(now / 86400);
+    let (hour, minute, second) = (now % 86400 / 3600, now % 3600 / 60, now % 60);
+    let date = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    );
+
+    let module = repo.sync_cvs_repo.as_deref().unwrap_or_default();
+    // pin the update to the recorded time, in case something is committed while it runs
+    if repo.location.join("CVS").exists() {
+        // `checkout -p` prints files rather than writing them. A missing file can't be told
+        // apart from a failed checkout, but the update would fail too.
+        let print = |path: &str| {
+            let file = format!("{}/{}", module, path);
+            run_client(
+                "cvs",
+                &["-q", "-z0", "-d", uri, "checkout", "-p", "-D", &date, &file],
+                None,
+            )
+            .ok()
+        };
+        let layout = Layout::parse(
+            print(LAYOUT_FILE).as_deref(),
+            print(REPO_NAME_FILE).as_deref(),
+        )?;
+        repo.check_incoming(&layout, others)?;
+
+        run_client(
+            "cvs",
+            &["-q", "-z0", "-d", uri, "update", "-d", "-P", "-D", &date],
+            Some(&repo.location),
+        )?;
+    } else {
+        repo.check_out(others, |staging| {
+            // cvs can only check out into a directory name relative to where it's run
+            let (parent, name) = (staging.parent().unwrap(), staging.file_name().unwrap());
+            run_client(
+                "cvs",
+                &[
+                    "-q".as_ref(),
+                    "-z0".as_ref(),
+                    "-d".as_ref(),
+                    uri.as_ref(),
+                    "checkout".as_ref(),
+                    "-P".as_ref(),
+                    "-D".as_ref(),
+                    date.as_ref(),
+                    "-d".as_ref(),
+                    name,
+                    module.as_ref(),
+                ],
+                Some(parent),
+            )
+            .map(drop)
+        })?;
+    }
+
+    Ok(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+    use tempfile::TempDir;
+
+    fn have_cvs() -> bool {
+        let found = Command::new("cvs").arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: cvs isn't installed");
+        }
+        found
+    }
+
+    fn cvs(dir: &Path, root: &str, args: &[&str]) -> String {
+        let mut all = vec!["-q", "-d", root];
+        all.extend(args);
+        run_client("cvs", &all, Some(dir)).unwrap()
+    }
+
+    /// Write a file into a working copy and commit it.
+    fn commit(work: &Path, root: &str, file: &str, contents: &str) {
+        let path = work.join(file);
+        let new = !path.exists();
+        if let Some((dir, _)) = file.rsplit_once('/') {
+            if !work.join(dir).exists() {
+                fs::create_dir_all(work.join(dir)).unwrap();
+                cvs(work, root, &["add", dir]);
+            }
+        }
+        fs::write(&path, contents).unwrap();
+        if new {
+            cvs(work, root, &["add", file]);
+        }
+        cvs(work, root, &["commit", "-m", file]);
+    }
+
+    /// An upstream repo with one file in its `tree` module, and a working copy of it to
+    /// commit from.
+    fn repo(tmp: &TempDir) -> (GentooRepo, String, PathBuf) {
+        let root = tmp.path().join("cvsroot").to_str().unwrap().to_string();
+        cvs(tmp.path(), &root, &["init"]);
+        let import = tmp.path().join("import");
+        fs::create_dir(&import).unwrap();
+        fs::write(import.join("a"), "1").unwrap();
+        cvs(
+            &import,
+            &root,
+            &["import", "-m", "import", "tree", "storm", "start"],
+        );
+        cvs(tmp.path(), &root, &["checkout", "-d", "work", "tree"]);
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            sync_cvs_repo: Some("tree".to_string()),
+            ..GentooRepo::default()
+        };
+        (repo, root, tmp.path().join("work"))
+    }
+
+    #[test]
+    fn checks_out_then_updates() {
+        if !have_cvs() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let (repo, root, work) = repo(&tmp);
+
+        assert!(sync(&repo, &root, &[]).unwrap().ends_with(" UTC"));
+        assert_eq!(fs::read_to_string(repo.location.join("a")).unwrap(), "1");
+        assert!(repo.location.join("CVS").exists());
+        assert!(!repo.staging_path().exists());
+
+        commit(&work, &root, "b", "2");
+        commit(&work, &root, "a", "3");
+        sync(&repo, &root, &[]).unwrap();
+        assert_eq!(fs::read_to_string(repo.location.join("b")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(repo.location.join("a")).unwrap(), "3");
+    }
+
+    #[test]
+    fn failed_checkout_leaves_nothing_behind() {
+        if !have_cvs() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let (mut repo, root, _) = repo(&tmp);
+        repo.sync_cvs_repo = Some("missing".to_string());
+
+        assert!(sync(&repo, &root, &[]).is_err());
+        assert!(!repo.location.exists());
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn refuses_updates_needing_missing_masters() {
+        if !have_cvs() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let (repo, root, work) = repo(&tmp);
+        commit(&work, &root, LAYOUT_FILE, "masters =\n");
+        sync(&repo, &root, &[]).unwrap();
+
+        commit(&work, &root, LAYOUT_FILE, "masters = gentoo\n");
+        let err = sync(&repo, &root, &[]).unwrap_err();
+        assert!(crate::repo::gentoo::is_missing_master(&*err));
+        assert_eq!(
+            fs::read_to_string(repo.location.join(LAYOUT_FILE)).unwrap(),
+            "masters =\n"
+        );
+
+        let master = GentooRepo {
+            location: tmp.path().join("gentoo"),
+            ..GentooRepo::default()
+        };
+        fs::create_dir_all(master.location.join("profiles")).unwrap();
+        fs::write(master.location.join(REPO_NAME_FILE), "gentoo\n").unwrap();
+        sync(&repo, &root, &[&master]).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.location.join(LAYOUT_FILE)).unwrap(),
+            "masters = gentoo\n"
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use super::{
+    atom::without_revision,
+    manifest, md5_cache,
+    profile::{Profile, PROFILES_DIR},
+    run_client, GentooError, GentooRepo, ECLASS_DIR,
+};
+use crate::{
+    package::Variants,
+    sandbox::{Mount, SandboxConfig, SandboxError},
+    version::Version,
+};
+use blake2::{Blake2b512, Digest};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    ffi::OsStr,
+    fs, io,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// The package manager side of PMS: the helpers ebuilds and eclasses call, and what runs
+/// their phases.
+const EBUILD_SH: &str = include_str!("ebuild.sh");
+
+/// EAPIs whose helpers `ebuild.sh` provides.
+const SUPPORTED_EAPIS: &[&str] = &["6", "7", "8"];
+
+const DEFAULT_GENTOO_MIRRORS: &str = "https://distfiles.gentoo.org";
+
+const PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Where things are in the sandbox. The build directory is laid out like Portage's.
+const BUILD_DIR: &str = "/build";
+const IMAGE_DIR: &str = "/build/image";
+const DISTFILES_DIR: &str = "/distfiles";
+const REPOS_DIR: &str = "/repos";
+
+/// The files a SRC_URI (with its USE conditionals evaluated) downloads, and the URIs each
+/// can be fetched from. Plain file names are only fetched from mirrors.
+fn src_uri(src_uri: &str, use_flags: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut files: Vec<(String, Vec<String>)> = Vec::new();
+    // whether each group the tokens are in applies
+    let mut groups = Vec::new();
+    let mut condition = None;
+    let mut tokens = src_uri.split_whitespace().peekable();
+    while let Some(token) = tokens.next() {
+        if token == "(" {
+            groups.push(condition.take().unwrap_or(true));
+            continue;
+        } else if token == ")" {
+            groups.pop();
+            continue;
+        } else if let Some(flag) = token.strip_suffix('?') {
+            let (flag, negated) = match flag.strip_prefix('!') {
+                Some(flag) => (flag, true),
+                None => (flag, false),
+            };
+            condition = Some(use_flags.iter().any(|f| f == flag) != negated);
+            continue;
+        }
+
+        // `uri -> name` saves a download under another name
+        let name = match tokens.next_if_eq(&"->") {
+            Some(_) => tokens.next().unwrap_or(token),
+            None => token.rsplit('/').next().unwrap_or(token),
+        };
+        if !groups.iter().all(|&applies| applies) {
+            continue;
+        }
+
+        let uri = Some(token.to_string()).filter(|uri| uri.contains("://"));
+        match files.iter_mut().find(|(n, _)| n == name) {
+            Some((_, uris)) => uris.extend(uri),
+            None => files.push((name.to_string(), uri.into_iter().collect())),
+        }
+    }
+    files
+}
+
+/// Downloads distfiles from their upstream URIs and from Gentoo's mirrors.
+struct Fetcher {
+    gentoo_mirrors: Vec<String>,
+    /// The mirrors `mirror://name/` URIs can use, from the trees' `thirdpartymirrors`.
+    third_party: BTreeMap<String, Vec<String>>,
+    restrict: Vec<String>,
+}
+
+impl Fetcher {
+    fn new(trees: &[&GentooRepo], profile: &Profile, restrict: &str) -> io::Result<Self> {
+        let mut third_party = BTreeMap::new();
+        for tree in trees {
+            let path = tree.location.join(PROFILES_DIR).join("thirdpartymirrors");
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in contents.lines().filter(|l| !l.starts_with('#')) {
+                let mut words = line.split_whitespace();
+                if let Some(name) = words.next() {
+                    third_party.insert(name.to_string(), words.map(str::to_string).collect());
+                }
+            }
+        }
+
+        Ok(Self {
+            gentoo_mirrors: profile
+                .vars()
+                .get("GENTOO_MIRRORS")
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_GENTOO_MIRRORS)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            third_party,
+            restrict: restrict.split_whitespace().map(str::to_string).collect(),
+        })
+    }
+
+    fn restricts(&self, what: &str) -> bool {
+        self.restrict.iter().any(|r| r == what)
+    }
+
+    /// Where a distfile can be downloaded from, in the order to try them in.
+    fn sources(&self, name: &str, uris: &[String]) -> Vec<String> {
+        // Gentoo's mirrors spread distfiles over directories named by their BLAKE2B hash
+        let hash = Blake2b512::digest(name.as_bytes());
+        let mirrored = self
+            .gentoo_mirrors
+            .iter()
+            .map(|mirror| format!("{}/distfiles/{:02x}/{}", mirror, hash[0], name));
+
+        let mut upstream = Vec::new();
+        for uri in uris {
+            match uri.strip_prefix("mirror://") {
+                Some(rest) => {
+                    let (mirror, path) = rest.split_once('/').unwrap_or((rest, ""));
+                    match self.third_party.get(mirror) {
+                        Some(mirrors) => upstream.extend(
+                            mirrors
+                                .iter()
+                                .map(|m| format!("{}/{}", m.trim_end_matches('/'), path)),
+                        ),
+                        None => eprintln!("warning: unknown mirror in '{}'", uri),
+                    }
+                }
+                None => upstream.push(uri.clone()),
+            }
+        }
+
+        if self.restricts("mirror") {
+            upstream
+        } else if self.restricts("primaryuri") {
+            upstream.into_iter().chain(mirrored).collect()
+        } else {
+            mirrored.chain(upstream).collect()
+        }
+    }
+
+    /// Download a distfile into `distdir`, trying each source until one matches the Manifest.
+    fn fetch(
+        &self,
+        package_dir: &Path,
+        distdir: &Path,
+        name: &str,
+        uris: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let path = distdir.join(name);
+        if self.restricts("fetch") {
+            return Err(Box::new(GentooError::FetchRestricted(
+                name.to_string(),
+                distdir.to_path_buf(),
+            )));
+        }
+
+        fs::create_dir_all(distdir)?;
+        for uri in self.sources(name, uris) {
+            println!("fetching {}", uri);
+            let fetched = run_client(
+                "curl",
+                &[
+                    OsStr::new("--fail"),
+                    OsStr::new("--location"),
+                    OsStr::new("--output"),
+                    path.as_os_str(),
+                    OsStr::new(&uri),
+                ],
+                None,
+            )
+            .and_then(|_| manifest::verify_distfile(package_dir, distdir, name));
+            match fetched {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    eprintln!("warning: {}", e);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        Err(Box::new(GentooError::FetchFailed(name.to_string())))
+    }
+}
+
+/// Build a version of a package from a repo (whose masters are `masters`) by running its
+/// ebuild's phases in the sandbox, installing it into `image`.
+pub(super) fn build(
+    repo: &GentooRepo,
+    masters: &[&GentooRepo],
+    name: &str,
+    version: &Version,
+    variants: &Variants,
+    sandbox: &SandboxConfig,
+    image: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let (category, pn) = name.split_once('/').unwrap_or(("", name));
+    let pvr = version.to_string();
+    let pv = without_revision(&pvr);
+    let pf = format!("{}-{}", pn, pvr);
+    let package_dir = repo.location.join(name);
+
+    let metadata_path = md5_cache::path(&repo.location, name, version);
+    let mut metadata = md5_cache::read_metadata(&metadata_path).map_err(|e| -> Box<dyn Error> {
+        if e.kind() == io::ErrorKind::NotFound {
+            Box::new(GentooError::NoMetadata(metadata_path.clone()))
+        } else {
+            Box::new(e)
+        }
+    })?;
+    let mut field = |key: &str| metadata.remove(key).unwrap_or_default();
+    let eapi = Some(field("EAPI"))
+        .filter(|eapi| !eapi.is_empty())
+        .unwrap_or_else(|| "0".to_string());
+    if !SUPPORTED_EAPIS.contains(&eapi.as_str()) {
+        return Err(Box::new(GentooError::UnsupportedEapi(pf, eapi)));
+    }
+
+    let profile = Profile::load(repo, masters)?;
+    let iuse = field("IUSE")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let (use_flags, iuse_effective) = profile.use_flags(&iuse, variants);
+    for (flag, _) in variants.iter() {
+        if !iuse_effective.iter().any(|f| f == flag) {
+            eprintln!("warning: {} has no USE flag '{}'; ignoring it", pf, flag);
+        }
+    }
+
+    // every tree the ebuild can use eclasses from, the repo itself last so its own win
+    let trees = masters.iter().copied().chain([repo]).collect::<Vec<_>>();
+    let distfiles = src_uri(&field("SRC_URI"), &use_flags);
+    let listed = manifest::distfiles(&package_dir)?;
+    let fetcher = Fetcher::new(&trees, &profile, &field("RESTRICT"))?;
+    let distdir = repo.distdir();
+    for (distfile, uris) in &distfiles {
+        let path = distdir.join(distfile);
+        if !listed.contains(distfile) {
+            return Err(Box::new(GentooError::NotInManifest(path)));
+        } else if !path.exists() {
+            fetcher.fetch(&package_dir, &distdir, distfile, uris)?;
+        }
+    }
+
+    // the sandbox's root, next to the image and hidden like a sync's staging path
+    let root = image.with_file_name(format!(
+        ".{}.work",
+        image.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    match fs::remove_dir_all(&root) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Box::new(e)),
+        _ => {}
+    }
+    let build_dir = root.join(BUILD_DIR.trim_start_matches('/'));
+    for dir in &["work", "temp", "homedir"] {
+        fs::create_dir_all(build_dir.join(dir))?;
+    }
+    fs::write(build_dir.join("ebuild.sh"), EBUILD_SH)?;
+
+    let tree_dirs = (0..trees.len())
+        .map(|i| format!("{}/{}", REPOS_DIR, i))
+        .collect::<Vec<_>>();
+    let ebuild_dir = format!("{}/{}", tree_dirs.last().unwrap(), name);
+    let mut mounts = vec![
+        Mount {
+            source: image,
+            target: IMAGE_DIR,
+            writable: true,
+        },
+        Mount {
+            source: &distdir,
+            target: DISTFILES_DIR,
+            writable: false,
+        },
+    ];
+    for (tree, dir) in trees.iter().zip(&tree_dirs) {
+        mounts.push(Mount {
+            source: &tree.location,
+            target: dir,
+            writable: false,
+        });
+    }
+
+    let makeopts = profile.vars().get("MAKEOPTS").cloned().unwrap_or_else(|| {
+        let jobs = thread::available_parallelism().map_or(1, |n| n.get());
+        format!("-j{}", jobs)
+    });
+    let pr = pvr[pv.len()..].trim_start_matches('-');
+    let env = [
+        ("EAPI", eapi.clone()),
+        ("CATEGORY", category.to_string()),
+        ("PN", pn.to_string()),
+        ("PV", pv.to_string()),
+        ("PR", if pr.is_empty() { "r0" } else { pr }.to_string()),
+        ("PVR", pvr.clone()),
+        ("P", format!("{}-{}", pn, pv)),
+        ("PF", pf.clone()),
+        ("SLOT", field("SLOT")),
+        (
+            "A",
+            distfiles
+                .iter()
+                .map(|(n, _)| n.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        ("USE", use_flags.join(" ")),
+        ("IUSE_EFFECTIVE", iuse_effective.join(" ")),
+        ("EBUILD", format!("{}/{}.ebuild", ebuild_dir, pf)),
+        ("FILESDIR", format!("{}/files", ebuild_dir)),
+        (
+            "ECLASSDIRS",
+            tree_dirs
+                .iter()
+                .map(|dir| format!("{}/{}", dir, ECLASS_DIR))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        ("DISTDIR", DISTFILES_DIR.to_string()),
+        ("PORTAGE_BUILDDIR", BUILD_DIR.to_string()),
+        ("WORKDIR", format!("{}/work", BUILD_DIR)),
+        ("S", format!("{}/work/{}-{}", BUILD_DIR, pn, pv)),
+        ("T", format!("{}/temp", BUILD_DIR)),
+        ("TMPDIR", format!("{}/temp", BUILD_DIR)),
+        ("HOME", format!("{}/homedir", BUILD_DIR)),
+        ("D", format!("{}/", IMAGE_DIR)),
+        ("ED", format!("{}/", IMAGE_DIR)),
+        ("EPREFIX", String::new()),
+        ("MAKEOPTS", makeopts),
+        ("PATH", PATH.to_string()),
+    ];
+
+    let mut command = Command::new("/bin/bash");
+    command
+        .arg(format!("{}/ebuild.sh", BUILD_DIR))
+        .env_clear()
+        // the profile's settings (CHOST, CFLAGS, ...) apply unless storm sets them itself
+        .envs(profile.vars())
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null());
+
+    println!("building {} with USE=\"{}\"", pf, use_flags.join(" "));
+    match sandbox.run(&mut command, &root, &mounts) {
+        Ok(()) => Ok(fs::remove_dir_all(&root)?),
+        // keep everything around for working out what went wrong
+        Err(e) if matches!(e.downcast_ref(), Some(SandboxError::Failed(_))) => {
+            Err(Box::new(GentooError::BuildFailed(pf, root, e)))
+        }
+        Err(e) => {
+            let _ = fs::remove_dir_all(&root);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,693 @@
+# Runs an ebuild's phases, providing the functions PMS says the package manager defines.
+# storm sets up the environment (P, WORKDIR, USE, ECLASSDIRS, ...) before running this in
+# the sandbox; see ebuild.rs.
+
+shopt -s extglob
+
+# die has to stop everything, even when it's called in a subshell
+__storm_pid=${BASHPID}
+trap 'exit 1' TERM
+
+die() {
+	local i
+	echo " * ERROR: ${CATEGORY}/${PF} failed (${EBUILD_PHASE_FUNC:-global scope}):" >&2
+	echo " *   ${*:-(no error message)}" >&2
+	for (( i = 1; i < ${#FUNCNAME[@]}; i++ )); do
+		echo " *   called from ${FUNCNAME[i]} (${BASH_SOURCE[i]##*/}:${BASH_LINENO[i-1]})" >&2
+	done
+	kill -s TERM "${__storm_pid}"
+	exit 1
+}
+
+assert() {
+	local status
+	for status in "${PIPESTATUS[@]}"; do
+		[[ ${status} -eq 0 ]] || die "$@"
+	done
+}
+
+# helpers return an error instead of dying when called through nonfatal
+nonfatal() {
+	__storm_nonfatal=1 "$@"
+}
+
+__storm_helper_die() {
+	if [[ -n ${__storm_nonfatal} ]]; then
+		echo " * $*" >&2
+		return 1
+	fi
+	die "$@"
+}
+
+einfo() { echo " * $*" >&2; }
+einfon() { echo -n " * $*" >&2; }
+elog() { einfo "$@"; }
+ewarn() { echo " * WARNING: $*" >&2; }
+eqawarn() { echo " * QA Notice: $*" >&2; }
+eerror() { echo " * ERROR: $*" >&2; }
+ebegin() { echo " * $* ..." >&2; }
+
+eend() {
+	local status=${1:-0}
+	shift
+	[[ ${status} -eq 0 ]] || eerror "${*:-failed}"
+	return "${status}"
+}
+
+# eclasses log to these, which storm has nowhere to send
+debug-print() { :; }
+debug-print-function() { :; }
+debug-print-section() { :; }
+
+has() {
+	local needle=$1 x
+	shift
+	for x; do
+		[[ ${x} == "${needle}" ]] && return 0
+	done
+	return 1
+}
+
+hasv() { has "$@" && echo "$1"; }
+hasq() { has "$@"; }
+
+in_iuse() {
+	has "$1" ${IUSE_EFFECTIVE}
+}
+
+use() {
+	local flag=${1#!}
+	in_iuse "${flag}" || eqawarn "USE flag '${flag}' isn't in IUSE of ${CATEGORY}/${PF}"
+	if [[ $1 == !* ]]; then
+		! has "${flag}" ${USE}
+	else
+		has "${flag}" ${USE}
+	fi
+}
+
+useq() { use "$@"; }
+usev() { use "$1" && echo "${2:-${1#!}}"; }
+
+usex() {
+	if use "$1"; then
+		echo "${2-yes}$4"
+	else
+		echo "${3-no}$5"
+	fi
+}
+
+use_with() {
+	local option=${2:-${1#!}} value=${3+=$3}
+	if use "$1"; then
+		echo "--with-${option}${value}"
+	else
+		echo "--without-${option}${value}"
+	fi
+}
+
+use_enable() {
+	local option=${2:-${1#!}} value=${3+=$3}
+	if use "$1"; then
+		echo "--enable-${option}${value}"
+	else
+		echo "--disable-${option}${value}"
+	fi
+}
+
+# storm doesn't know what's installed inside the sandbox
+has_version() { return 1; }
+best_version() { return 1; }
+
+get_libdir() {
+	local libdir=LIBDIR_${ABI:-${DEFAULT_ABI}}
+	echo "${!libdir:-lib}"
+}
+
+# Split a version into alternating separators and components, e.g. 1.2b is
+# ("" 1 . 2 "" b).
+__storm_ver_split() {
+	local v=$1 sep comp
+	__storm_ver=()
+	while [[ -n ${v} ]]; do
+		sep=${v%%[a-zA-Z0-9]*}
+		v=${v:${#sep}}
+		if [[ ${v} == [0-9]* ]]; then
+			comp=${v%%[^0-9]*}
+		else
+			comp=${v%%[^a-zA-Z]*}
+		fi
+		v=${v:${#comp}}
+		__storm_ver+=("${sep}" "${comp}")
+	done
+}
+
+# Parse a ver_cut or ver_rs range (N, N- or N-M) into start and end.
+__storm_ver_range() {
+	local range=$1 max=$2
+	case ${range} in
+		+([0-9])) start=${range} end=${range} ;;
+		+([0-9])-) start=${range%-} end=${max} ;;
+		+([0-9])-+([0-9])) start=${range%-*} end=${range#*-} ;;
+		*) die "invalid version range '${range}'" ;;
+	esac
+	(( end > max )) && end=${max}
+	return 0
+}
+
+ver_cut() {
+	local start end i out=
+	__storm_ver_split "${2-${PV}}"
+	__storm_ver_range "$1" $(( ${#__storm_ver[@]} / 2 ))
+	for (( i = start * 2 - 1; i <= end * 2 - 1; i++ )); do
+		(( i >= 0 )) && out+=${__storm_ver[i]}
+	done
+	echo "${out}"
+}
+
+ver_rs() {
+	local v=${PV} start end i
+	if (( $# % 2 )); then
+		v=${!#}
+		set -- "${@:1:$#-1}"
+	fi
+	__storm_ver_split "${v}"
+	while (( $# > 1 )); do
+		__storm_ver_range "$1" $(( ${#__storm_ver[@]} / 2 ))
+		for (( i = start * 2; i <= end * 2 && i < ${#__storm_ver[@]}; i += 2 )); do
+			# a missing separator before the first component isn't replaced
+			[[ ${i} -eq 0 && -z ${__storm_ver[0]} ]] && continue
+			__storm_ver[i]=$2
+		done
+		shift 2
+	done
+	local IFS=
+	echo "${__storm_ver[*]}"
+}
+
+# Compare two non-negative integers of any length, setting __storm_cmp to -1, 0 or 1.
+__storm_int_cmp() {
+	local a=${1##+(0)} b=${2##+(0)}
+	if (( ${#a} != ${#b} )); then
+		(( ${#a} < ${#b} )) && __storm_cmp=-1 || __storm_cmp=1
+	elif [[ ${a} == "${b}" ]]; then
+		__storm_cmp=0
+	else
+		[[ ${a} < ${b} ]] && __storm_cmp=-1 || __storm_cmp=1
+	fi
+}
+
+__storm_suffix_rank() {
+	case $1 in
+		alpha) echo 0 ;;
+		beta) echo 1 ;;
+		pre) echo 2 ;;
+		rc) echo 3 ;;
+		'') echo 4 ;;
+		p) echo 5 ;;
+	esac
+}
+
+# Compare two versions per PMS section 3.3, setting __storm_cmp to -1, 0 or 1.
+__storm_ver_cmp() {
+	local re='^([0-9]+(\.[0-9]+)*)([a-z]?)((_(alpha|beta|pre|rc|p)[0-9]*)*)(-r([0-9]+))?$'
+	[[ $1 =~ ${re} ]] || die "invalid version '$1'"
+	local -a a_nums=(${BASH_REMATCH[1]//./ }) a_suffixes=(${BASH_REMATCH[4]//_/ })
+	local a_letter=${BASH_REMATCH[3]} a_rev=${BASH_REMATCH[8]:-0}
+	[[ $2 =~ ${re} ]] || die "invalid version '$2'"
+	local -a b_nums=(${BASH_REMATCH[1]//./ }) b_suffixes=(${BASH_REMATCH[4]//_/ })
+	local b_letter=${BASH_REMATCH[3]} b_rev=${BASH_REMATCH[8]:-0}
+	local i a b
+
+	__storm_int_cmp "${a_nums[0]}" "${b_nums[0]}"
+	[[ ${__storm_cmp} -ne 0 ]] && return
+	for (( i = 1; i < ${#a_nums[@]} && i < ${#b_nums[@]}; i++ )); do
+		a=${a_nums[i]} b=${b_nums[i]}
+		if [[ ${a} == 0* || ${b} == 0* ]]; then
+			# components with leading zeros compare as decimals
+			a=${a%%+(0)} b=${b%%+(0)}
+			if [[ ${a} == "${b}" ]]; then
+				__storm_cmp=0
+			else
+				[[ ${a} < ${b} ]] && __storm_cmp=-1 || __storm_cmp=1
+			fi
+		else
+			__storm_int_cmp "${a}" "${b}"
+		fi
+		[[ ${__storm_cmp} -ne 0 ]] && return
+	done
+	if (( ${#a_nums[@]} != ${#b_nums[@]} )); then
+		(( ${#a_nums[@]} < ${#b_nums[@]} )) && __storm_cmp=-1 || __storm_cmp=1
+		return
+	fi
+
+	if [[ ${a_letter} != "${b_letter}" ]]; then
+		[[ ${a_letter} < ${b_letter} ]] && __storm_cmp=-1 || __storm_cmp=1
+		return
+	fi
+
+	for (( i = 0; i < ${#a_suffixes[@]} || i < ${#b_suffixes[@]}; i++ )); do
+		a=${a_suffixes[i]} b=${b_suffixes[i]}
+		local a_rank b_rank
+		a_rank=$(__storm_suffix_rank "${a%%[0-9]*}")
+		b_rank=$(__storm_suffix_rank "${b%%[0-9]*}")
+		if (( a_rank != b_rank )); then
+			(( a_rank < b_rank )) && __storm_cmp=-1 || __storm_cmp=1
+			return
+		fi
+		__storm_int_cmp "${a##+([a-z])}" "${b##+([a-z])}"
+		[[ ${__storm_cmp} -ne 0 ]] && return
+	done
+
+	__storm_int_cmp "${a_rev}" "${b_rev}"
+}
+
+ver_test() {
+	local a=${PVR} op b
+	if [[ $# -eq 3 ]]; then
+		a=$1
+		shift
+	fi
+	op=$1 b=$2
+	__storm_ver_cmp "${a}" "${b}"
+	case ${op} in
+		-eq) (( __storm_cmp == 0 )) ;;
+		-ne) (( __storm_cmp != 0 )) ;;
+		-lt) (( __storm_cmp < 0 )) ;;
+		-le) (( __storm_cmp <= 0 )) ;;
+		-gt) (( __storm_cmp > 0 )) ;;
+		-ge) (( __storm_cmp >= 0 )) ;;
+		*) die "ver_test: invalid operator '${op}'" ;;
+	esac
+}
+
+inherit() {
+	local outer=${ECLASS} eclass dir path
+	for eclass; do
+		path=
+		# later trees (the repo itself, after its masters) override earlier ones
+		for dir in ${ECLASSDIRS}; do
+			[[ -f ${dir}/${eclass}.eclass ]] && path=${dir}/${eclass}.eclass
+		done
+		[[ -n ${path} ]] || die "inherit: eclass '${eclass}' doesn't exist"
+
+		ECLASS=${eclass}
+		has "${eclass}" ${INHERITED} || INHERITED+=" ${eclass}"
+		source "${path}" || die "inherit: sourcing ${eclass}.eclass failed"
+	done
+	ECLASS=${outer}
+}
+
+EXPORT_FUNCTIONS() {
+	[[ -n ${ECLASS} ]] || die "EXPORT_FUNCTIONS called outside of an eclass"
+	local func
+	for func; do
+		eval "${func}() { ${ECLASS}_${func} \"\$@\"; }"
+	done
+}
+
+unpack() {
+	[[ $# -gt 0 ]] || die "unpack: no files given"
+	local f path name
+	for f; do
+		if [[ ${f} == */* ]]; then
+			path=${f}
+		else
+			path=${DISTDIR}/${f}
+		fi
+		[[ -f ${path} ]] || __storm_helper_die "unpack: ${path} doesn't exist" || return
+		name=${f##*/}
+
+		echo ">>> Unpacking ${name} to ${PWD}"
+		case ${name,,} in
+			*.tar|*.tar.*|*.tgz|*.tbz|*.tbz2|*.txz)
+				tar --no-same-owner -xof "${path}" ;;
+			*.zip|*.jar)
+				unzip -qo "${path}" ;;
+			*.gz|*.z)
+				gzip -dc "${path}" > "${name%.*}" ;;
+			*.bz2)
+				bzip2 -dc "${path}" > "${name%.*}" ;;
+			*.xz)
+				xz -dc "${path}" > "${name%.*}" ;;
+			*.lz)
+				lzip -dc "${path}" > "${name%.*}" ;;
+			*.zst)
+				zstd -dc "${path}" > "${name%.*}" ;;
+			*.7z)
+				7z x -y "${path}" > /dev/null ;;
+			*)
+				echo "unpack: skipping ${name}, which isn't an archive"
+				continue ;;
+		esac || __storm_helper_die "unpack: unpacking ${name} failed" || return
+	done
+}
+
+econf() {
+	local configure=${ECONF_SOURCE:-.}/configure
+	[[ -x ${configure} ]] || __storm_helper_die "econf: ${configure} doesn't exist" || return
+
+	local help args=(
+		--prefix="${EPREFIX}"/usr
+		--mandir="${EPREFIX}"/usr/share/man
+		--infodir="${EPREFIX}"/usr/share/info
+		--datadir="${EPREFIX}"/usr/share
+		--sysconfdir="${EPREFIX}"/etc
+		--localstatedir="${EPREFIX}"/var/lib
+	)
+	[[ -n ${CHOST} ]] && args+=(--build="${CBUILD:-${CHOST}}" --host="${CHOST}")
+	[[ -n ${CTARGET} ]] && args+=(--target="${CTARGET}")
+
+	# the rest are only passed to configure scripts that understand them
+	help=$("${configure}" --help 2> /dev/null)
+	[[ ${help} == *--libdir* ]] && args+=(--libdir="${EPREFIX}/usr/$(get_libdir)")
+	[[ ${help} == *--disable-dependency-tracking* ]] && args+=(--disable-dependency-tracking)
+	[[ ${help} == *--disable-silent-rules* ]] && args+=(--disable-silent-rules)
+	[[ ${help} == *--docdir* ]] && args+=(--docdir="${EPREFIX}/usr/share/doc/${PF}")
+	[[ ${help} == *--htmldir* ]] && args+=(--htmldir="${EPREFIX}/usr/share/doc/${PF}/html")
+	if [[ ${EAPI} == 8 ]]; then
+		[[ ${help} == *--datarootdir* ]] && args+=(--datarootdir="${EPREFIX}/usr/share")
+		[[ ${help} == *--disable-static* ]] && args+=(--disable-static)
+	fi
+
+	echo "${configure}" "${args[@]}" "$@"
+	"${configure}" "${args[@]}" "$@" || __storm_helper_die "econf failed"
+}
+
+emake() {
+	echo ${MAKE:-make} ${MAKEOPTS} "$@"
+	${MAKE:-make} ${MAKEOPTS} "$@" || __storm_helper_die "emake failed"
+}
+
+eapply() {
+	local options=() files=() f patch patches
+	while [[ $# -gt 0 ]]; do
+		case $1 in
+			--) shift; files+=("$@"); break ;;
+			-*) options+=("$1") ;;
+			*) files+=("$1") ;;
+		esac
+		shift
+	done
+	[[ ${#files[@]} -gt 0 ]] || die "eapply: no patches given"
+
+	for f in "${files[@]}"; do
+		patches=()
+		if [[ -d ${f} ]]; then
+			for patch in "${f}"/*; do
+				[[ ${patch} == *.@(diff|patch) ]] && patches+=("${patch}")
+			done
+			[[ ${#patches[@]} -gt 0 ]] || die "eapply: ${f} has no patches in it"
+		else
+			patches=("${f}")
+		fi
+
+		for patch in "${patches[@]}"; do
+			ebegin "Applying ${patch##*/}"
+			patch -p1 -f -s -g0 --no-backup-if-mismatch "${options[@]}" < "${patch}"
+			eend $? "${patch##*/} didn't apply" || __storm_helper_die "eapply failed" || return
+		done
+	done
+}
+
+# there are no user patches in storm
+eapply_user() { :; }
+
+# Install docs from an array or a list, for DOCS and HTML_DOCS.
+__storm_install_docs() {
+	local var=$1
+	if [[ $(declare -p "${var}" 2> /dev/null) == "declare -a"* ]]; then
+		local -n docs=${var}
+		[[ ${#docs[@]} -eq 0 ]] || dodoc -r "${docs[@]}"
+	else
+		[[ -z ${!var} ]] || dodoc -r ${!var}
+	fi
+}
+
+einstalldocs() {
+	(
+		if [[ -n ${DOCS+set} ]]; then
+			__storm_install_docs DOCS
+		else
+			local doc
+			for doc in README* ChangeLog AUTHORS NEWS TODO CHANGES THANKS BUGS FAQ CREDITS CHANGELOG; do
+				[[ -s ${doc} ]] && dodoc "${doc}"
+			done
+		fi
+		docinto html
+		__storm_install_docs HTML_DOCS
+	) || return
+	return 0
+}
+
+__storm_desttree=/usr
+__storm_insdesttree=/
+__storm_exedesttree=/
+__storm_docdesttree=
+__storm_insopts=(-m0644)
+__storm_exeopts=(-m0755)
+__storm_diropts=(-m0755)
+__storm_libopts=(-m0644)
+
+into() { __storm_desttree=${1%/}; }
+insinto() { __storm_insdesttree=$1; }
+exeinto() { __storm_exedesttree=$1; }
+docinto() { __storm_docdesttree=$1; }
+insopts() { __storm_insopts=("$@"); }
+exeopts() { __storm_exeopts=("$@"); }
+diropts() { __storm_diropts=("$@"); }
+libopts() { __storm_libopts=("$@"); }
+
+# storm leaves binaries and documentation as they're installed
+dostrip() { :; }
+docompress() { :; }
+
+# Where a path in the installed system is in the image.
+__storm_image() {
+	echo "${ED%/}/${1#/}"
+}
+
+dodir() {
+	local dir
+	for dir; do
+		install -d "${__storm_diropts[@]}" "$(__storm_image "${dir}")" ||
+			__storm_helper_die "dodir: creating ${dir} failed" || return
+	done
+}
+
+keepdir() {
+	local dir
+	dodir "$@" || return
+	for dir; do
+		touch "$(__storm_image "${dir}")/.keep_${CATEGORY}_${PN}-${SLOT%/*}" ||
+			__storm_helper_die "keepdir: ${dir} failed" || return
+	done
+}
+
+# Install files into a directory of the image, with the given install options. -r copies
+# directories recursively.
+__storm_install() {
+	local helper=$1 dir=$2 recursive= f
+	shift 2
+	local -a options=()
+	while [[ $1 != -- ]]; do
+		options+=("$1")
+		shift
+	done
+	shift
+	if [[ $1 == -r ]]; then
+		recursive=1
+		shift
+	fi
+	[[ $# -gt 0 ]] || die "${helper}: no files given"
+
+	local dest
+	dest=$(__storm_image "${dir}")
+	install -d "${__storm_diropts[@]}" "${dest}" ||
+		__storm_helper_die "${helper}: creating ${dir} failed" || return
+	for f; do
+		f=${f%/}
+		if [[ -d ${f} && ! -L ${f} ]]; then
+			[[ -n ${recursive} ]] || __storm_helper_die "${helper}: ${f} is a directory" || return
+			(
+				shopt -s dotglob nullglob
+				local children=("${f}"/*)
+				install -d "${__storm_diropts[@]}" "${dest}/${f##*/}" || exit 1
+				[[ ${#children[@]} -eq 0 ]] ||
+					__storm_install "${helper}" "${dir}/${f##*/}" "${options[@]}" -- -r "${children[@]}"
+			) || __storm_helper_die "${helper}: installing ${f} failed" || return
+		elif [[ -L ${f} ]]; then
+			cp -P "${f}" "${dest}/" || __storm_helper_die "${helper}: installing ${f} failed" || return
+		elif [[ -e ${f} ]]; then
+			install "${options[@]}" "${f}" "${dest}/" ||
+				__storm_helper_die "${helper}: installing ${f} failed" || return
+		else
+			__storm_helper_die "${helper}: ${f} doesn't exist" || return
+		fi
+	done
+}
+
+doins() { __storm_install doins "${__storm_insdesttree}" "${__storm_insopts[@]}" -- "$@"; }
+doexe() { __storm_install doexe "${__storm_exedesttree}" "${__storm_exeopts[@]}" -- "$@"; }
+dobin() { __storm_install dobin "${__storm_desttree}/bin" -m0755 -- "$@"; }
+dosbin() { __storm_install dosbin "${__storm_desttree}/sbin" -m0755 -- "$@"; }
+doheader() { __storm_install doheader /usr/include "${__storm_insopts[@]}" -- "$@"; }
+doinfo() { __storm_install doinfo /usr/share/info -m0644 -- "$@"; }
+doinitd() { __storm_install doinitd /etc/init.d -m0755 -- "$@"; }
+doconfd() { __storm_install doconfd /etc/conf.d -m0644 -- "$@"; }
+doenvd() { __storm_install doenvd /etc/env.d -m0644 -- "$@"; }
+dolib.so() { __storm_install dolib.so "${__storm_desttree}/$(get_libdir)" -m0755 -- "$@"; }
+dolib.a() { __storm_install dolib.a "${__storm_desttree}/$(get_libdir)" -m0644 -- "$@"; }
+
+dodoc() {
+	__storm_install dodoc "/usr/share/doc/${PF}/${__storm_docdesttree}" -m0644 -- "$@"
+}
+
+doman() {
+	local f name section i18n=
+	for f; do
+		if [[ ${f} == -i18n=* ]]; then
+			i18n=${f#-i18n=}
+			continue
+		fi
+		name=${f##*/}
+		section=${name##*.}
+		[[ ${section} == [0-9n]* ]] || __storm_helper_die "doman: can't tell which section ${f} is in" || return
+		__storm_install doman "/usr/share/man/${i18n:+${i18n}/}man${section:0:1}" -m0644 -- "${f}" || return
+	done
+}
+
+# newins and friends install a file (or stdin, with -) under another name.
+__storm_new() {
+	local helper=$1 src=$2 name=$3 dir
+	[[ -n ${name} ]] || die "new${helper#do}: no name given"
+	dir=$(mktemp -d "${T}/new.XXXXXX") || die
+	if [[ ${src} == - ]]; then
+		cat > "${dir}/${name}"
+	else
+		cp -P "${src}" "${dir}/${name}"
+	fi || __storm_helper_die "new${helper#do}: ${src} doesn't exist" || return
+	"${helper}" "${dir}/${name}"
+}
+
+newins() { __storm_new doins "$@"; }
+newexe() { __storm_new doexe "$@"; }
+newbin() { __storm_new dobin "$@"; }
+newsbin() { __storm_new dosbin "$@"; }
+newheader() { __storm_new doheader "$@"; }
+newinitd() { __storm_new doinitd "$@"; }
+newconfd() { __storm_new doconfd "$@"; }
+newenvd() { __storm_new doenvd "$@"; }
+newlib.so() { __storm_new dolib.so "$@"; }
+newlib.a() { __storm_new dolib.a "$@"; }
+newdoc() { __storm_new dodoc "$@"; }
+newman() { __storm_new doman "$@"; }
+
+dosym() {
+	local relative= target link
+	if [[ $1 == -r ]]; then
+		relative=1
+		shift
+	fi
+	target=$1
+	link=$(__storm_image "$2")
+	[[ ${link} == */ ]] && link+=${target##*/}
+	mkdir -p "${link%/*}" || __storm_helper_die "dosym: creating ${2%/*} failed" || return
+	if [[ -n ${relative} ]]; then
+		target=$(realpath -m --relative-to="$(dirname "/${2#/}")" "${target}")
+	fi
+	ln -snf "${target}" "${link}" || __storm_helper_die "dosym: linking $2 failed"
+}
+
+fperms() {
+	local mode=$1 path
+	shift
+	for path; do
+		chmod "${mode}" "$(__storm_image "${path}")" || __storm_helper_die "fperms: ${path} failed" || return
+	done
+}
+
+fowners() {
+	local owner=$1 path
+	shift
+	for path; do
+		chown "${owner}" "$(__storm_image "${path}")" || __storm_helper_die "fowners: ${path} failed" || return
+	done
+}
+
+__storm_has_makefile() {
+	[[ -f Makefile || -f GNUmakefile || -f makefile ]]
+}
+
+default_pkg_setup() { :; }
+
+default_src_unpack() {
+	[[ -z ${A} ]] || unpack ${A}
+}
+
+default_src_prepare() {
+	if [[ $(declare -p PATCHES 2> /dev/null) == "declare -a"* ]]; then
+		[[ ${#PATCHES[@]} -eq 0 ]] || eapply "${PATCHES[@]}"
+	elif [[ -n ${PATCHES} ]]; then
+		eapply ${PATCHES}
+	fi
+	eapply_user
+}
+
+default_src_configure() {
+	[[ -x ${ECONF_SOURCE:-.}/configure ]] && econf
+	return 0
+}
+
+default_src_compile() {
+	__storm_has_makefile && emake
+	return 0
+}
+
+default_src_test() {
+	__storm_has_makefile || return 0
+	local target
+	for target in check test; do
+		if ${MAKE:-make} -n "${target}" &> /dev/null; then
+			emake -j1 "${target}"
+			return
+		fi
+	done
+}
+
+default_src_install() {
+	__storm_has_makefile && emake DESTDIR="${D}" install
+	einstalldocs
+}
+
+default() {
+	"default_${EBUILD_PHASE_FUNC}"
+}
+
+__storm_run_phase() {
+	local phase=$1
+	EBUILD_PHASE=${phase#*_}
+	EBUILD_PHASE_FUNC=${phase}
+	if [[ ${phase} == @(pkg_setup|src_unpack) || ! -d ${S} ]]; then
+		cd "${WORKDIR}" || die
+	else
+		cd "${S}" || die
+	fi
+
+	# like in Portage, only a helper failing (or die) fails the phase, not its return status
+	echo ">>> Running ${phase}"
+	if declare -F "${phase}" > /dev/null; then
+		"${phase}"
+	else
+		"default_${phase}"
+	fi
+}
+
+INHERITED=
+source "${EBUILD}" || die "sourcing ${EBUILD} failed"
+
+for __storm_phase in pkg_setup src_unpack src_prepare src_configure src_compile src_test src_install; do
+	if [[ ${__storm_phase} == src_test ]] && { ! has test ${FEATURES} || has test ${RESTRICT}; }; then
+		continue
+	fi
+	__storm_run_phase "${__storm_phase}"
+done
@@ -0,0 +1,165 @@
+use super::{
+    layout::{Layout, LAYOUT_FILE, REPO_NAME_FILE},
+    run_client, GentooRepo,
+};
+use std::{error::Error, ffi::OsString, path::Path};
+
+/// A file in a commit, or `None` if the commit doesn't have it.
+fn show(dir: &Path, commit: &str, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+    // ls-tree prints nothing for paths the commit doesn't have, where show would fail
+    if run_client(
+        "git",
+        &["ls-tree", "--name-only", commit, "--", path],
+        Some(dir),
+    )?
+    .is_empty()
+    {
+        return Ok(None);
+    }
+    run_client("git", &["show", &format!("{}:{}", commit, path)], Some(dir)).map(Some)
+}
+
+/// Clone the repo on the first sync and fast-forward it afterwards, returning the synced commit.
+pub(super) fn sync(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+) -> Result<String, Box<dyn Error>> {
+    let depth = repo.sync_depth.map(|depth| format!("--depth={}", depth));
+
+    if repo.location.join(".git").exists() {
+        // everything that can fail halfway happens in the fetch, which doesn't touch the tree
+        let mut fetch = vec!["fetch"];
+        fetch.extend(depth.as_deref());
+        fetch.extend(&["--", uri, "HEAD"]);
+        run_client("git", &fetch, Some(&repo.location))?;
+
+        let layout = Layout::parse(
+            show(&repo.location, "FETCH_HEAD", LAYOUT_FILE)?.as_deref(),
+            show(&repo.location, "FETCH_HEAD", REPO_NAME_FILE)?.as_deref(),
+        )?;
+        repo.check_incoming(&layout, others)?;
+
+        // a shallow clone doesn't have the history to fast-forward through
+        let update: &[&str] = if depth.is_some() {
+            &["reset", "--merge", "FETCH_HEAD"]
+        } else {
+            &["merge", "--ff-only", "FETCH_HEAD"]
+        };
+        run_client("git", update, Some(&repo.location))?;
+    } else {
+        repo.check_out(others, |staging| {
+            let mut clone = vec![OsString::from("clone")];
+            clone.extend(depth.map(OsString::from));
+            clone.extend(vec!["--".into(), uri.into(), staging.into()]);
+            run_client("git", &clone, None).map(drop)
+        })?;
+    }
+
+    run_client("git", &["rev-parse", "HEAD"], Some(&repo.location))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let mut all = vec!["-c", "user.name=storm", "-c", "user.email=storm@localhost"];
+        all.extend(args);
+        run_client("git", &all, Some(dir)).unwrap()
+    }
+
+    fn commit(dir: &Path, file: &str, contents: &str) -> String {
+        fs::write(dir.join(file), contents).unwrap();
+        git(dir, &["add", "--all"]);
+        git(dir, &["commit", "--quiet", "--message", file]);
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    fn repo(tmp: &TempDir, sync_depth: Option<u32>) -> (GentooRepo, PathBuf) {
+        let upstream = tmp.path().join("upstream");
+        fs::create_dir(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet"]);
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            sync_depth,
+            ..GentooRepo::default()
+        };
+        (repo, upstream)
+    }
+
+    #[test]
+    fn clones_then_fast_forwards() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, upstream) = repo(&tmp, None);
+        let uri = upstream.to_str().unwrap();
+
+        let first = commit(&upstream, "a", "1");
+        assert_eq!(sync(&repo, uri, &[]).unwrap(), first);
+        assert_eq!(fs::read_to_string(repo.location.join("a")).unwrap(), "1");
+        assert!(!repo.staging_path().exists());
+
+        let second = commit(&upstream, "b", "2");
+        assert_eq!(sync(&repo, uri, &[]).unwrap(), second);
+        assert_eq!(fs::read_to_string(repo.location.join("b")).unwrap(), "2");
+    }
+
+    #[test]
+    fn shallow_syncs_follow_rewritten_history() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, upstream) = repo(&tmp, Some(1));
+        // git ignores --depth for plain paths
+        let uri = format!("file://{}", upstream.display());
+
+        commit(&upstream, "a", "1");
+        commit(&upstream, "b", "2");
+        sync(&repo, &uri, &[]).unwrap();
+        assert_eq!(git(&repo.location, &["rev-list", "--count", "HEAD"]), "1");
+
+        git(
+            &upstream,
+            &["commit", "--quiet", "--amend", "--message", "amended"],
+        );
+        let amended = git(&upstream, &["rev-parse", "HEAD"]);
+        assert_eq!(sync(&repo, &uri, &[]).unwrap(), amended);
+    }
+
+    #[test]
+    fn failed_clone_leaves_nothing_behind() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, _) = repo(&tmp, None);
+        let missing = tmp.path().join("missing");
+
+        assert!(sync(&repo, missing.to_str().unwrap(), &[]).is_err());
+        assert!(!repo.location.exists());
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn refuses_updates_needing_missing_masters() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, upstream) = repo(&tmp, None);
+        let uri = upstream.to_str().unwrap();
+        fs::create_dir(upstream.join("metadata")).unwrap();
+        let synced = commit(&upstream, "metadata/layout.conf", "masters =\n");
+        sync(&repo, uri, &[]).unwrap();
+
+        commit(&upstream, "metadata/layout.conf", "masters = gentoo\n");
+        let err = sync(&repo, uri, &[]).unwrap_err();
+        assert!(crate::repo::gentoo::is_missing_master(&*err));
+        assert_eq!(git(&repo.location, &["rev-parse", "HEAD"]), synced);
+
+        let master = GentooRepo {
+            location: tmp.path().join("gentoo"),
+            ..GentooRepo::default()
+        };
+        fs::create_dir_all(master.location.join("profiles")).unwrap();
+        fs::write(master.location.join("profiles/repo_name"), "gentoo\n").unwrap();
+        sync(&repo, uri, &[&master]).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use super::GentooError;
+use std::{error::Error, fs, io, path::Path};
+
+pub(super) const LAYOUT_FILE: &str = "metadata/layout.conf";
+pub(super) const REPO_NAME_FILE: &str = "profiles/repo_name";
+
+/// Profile formats storm understands; trees can use others, which are ignored with a warning.
+const SUPPORTED_PROFILE_FORMATS: &[&str] = &["pms", "portage-1", "portage-2"];
+
+/// What a tree says about itself in `metadata/layout.conf`.
+#[derive(Debug)]
+pub(super) struct Layout {
+    /// The name other trees refer to this one by, which needn't match its storm repo name.
+    pub repo_name: Option<String>,
+    /// The repo names of the trees this one uses eclasses and profiles from, in order.
+    pub masters: Vec<String>,
+    /// Thin Manifests only list distfiles, leaving ebuilds to be checked by the sync.
+    pub thin_manifests: bool,
+    pub sign_manifests: bool,
+    pub profile_formats: Vec<String>,
+}
+
+impl Default for Layout {
+    // Portage's defaults for a tree without a layout.conf, except that Manifests are only
+    // taken to be signed when the tree says so
+    fn default() -> Self {
+        Self {
+            repo_name: None,
+            masters: Vec::new(),
+            thin_manifests: false,
+            sign_manifests: false,
+            profile_formats: vec!["pms".to_string()],
+        }
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, GentooError> {
+    match value.to_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(GentooError::InvalidLayout(
+            key.to_string(),
+            value.to_string(),
+        )),
+    }
+}
+
+impl Layout {
+    /// Read a tree's layout, which is all defaults if it hasn't been synced yet.
+    pub fn read(location: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Layout::parse(
+            read_optional(&location.join(LAYOUT_FILE))?.as_deref(),
+            read_optional(&location.join(REPO_NAME_FILE))?.as_deref(),
+        )?)
+    }
+
+    /// Parse the contents of a tree's layout.conf and repo_name, if it has them.
+    pub fn parse(conf: Option<&str>, repo_name: Option<&str>) -> Result<Self, GentooError> {
+        let mut layout = Layout::default();
+        for line in conf.unwrap_or_default().lines() {
+            let line = line.trim();
+            let idx = match line.find('=') {
+                Some(idx) if !line.starts_with('#') => idx,
+                _ => continue,
+            };
+            let (key, value) = (line[..idx].trim(), line[idx + 1..].trim());
+            let words = || value.split_whitespace().map(str::to_string).collect();
+
+            match key {
+                "repo-name" => layout.repo_name = Some(value.to_string()),
+                "masters" => layout.masters = words(),
+                "thin-manifests" => layout.thin_manifests = parse_bool(key, value)?,
+                "sign-manifests" => layout.sign_manifests = parse_bool(key, value)?,
+                "profile-formats" => layout.profile_formats = words(),
+                _ => {}
+            }
+        }
+
+        // layout.conf's repo-name wins, but most trees only name themselves here
+        if layout.repo_name.is_none() {
+            layout.repo_name = repo_name
+                .and_then(|name| name.lines().next().map(|l| l.trim().to_string()))
+                .filter(|name| !name.is_empty());
+        }
+
+        Ok(layout)
+    }
+
+    /// Warn about anything in the layout that storm won't honor.
+    pub fn warn_unsupported(&self, location: &Path) {
+        for format in &self.profile_formats {
+            if !SUPPORTED_PROFILE_FORMATS.contains(&format.as_str()) {
+                eprintln!(
+                    "warning: {} uses profile format '{}', which storm ignores",
+                    location.display(),
+                    format
+                );
+            }
+        }
+        if self.sign_manifests {
+            eprintln!(
+                "warning: {} signs its Manifests, but storm doesn't check Manifest signatures",
+                location.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_layout() {
+        let layout = Layout::parse(
+            Some(
+                "# comment\nrepo-name = ov\nmasters = gentoo other\n\
+                 thin-manifests = True\nprofile-formats = portage-2\n",
+            ),
+            Some("ignored\n"),
+        )
+        .unwrap();
+        assert_eq!(layout.repo_name.as_deref(), Some("ov"));
+        assert_eq!(layout.masters, vec!["gentoo", "other"]);
+        assert!(layout.thin_manifests);
+        assert!(!layout.sign_manifests);
+        assert_eq!(layout.profile_formats, vec!["portage-2"]);
+    }
+
+    #[test]
+    fn defaults_without_layout() {
+        let layout = Layout::parse(None, Some("gentoo\n")).unwrap();
+        assert_eq!(layout.repo_name.as_deref(), Some("gentoo"));
+        assert!(layout.masters.is_empty());
+        assert!(!layout.thin_manifests);
+        assert!(!layout.sign_manifests);
+        assert!(Layout::parse(None, Some("\n")).unwrap().repo_name.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_booleans() {
+        assert!(matches!(
+            Layout::parse(Some("thin-manifests = yes"), None),
+            Err(GentooError::InvalidLayout(..))
+        ));
+    }
+}
@@ -0,0 +1,550 @@
+use super::GentooError;
+use blake2::Blake2b512;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha512};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    ffi::OsStr,
+    fs,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+const MANIFEST_FILE: &str = "Manifest";
+
+/// The directory a package's auxiliary files (patches and the like) are kept in.
+const AUX_DIR: &str = "files";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A source tarball, found in the distfiles directory.
+    Dist,
+    Ebuild,
+    /// A file under `files/`, named relative to it.
+    Aux,
+    /// Anything else in the package directory, such as metadata.xml.
+    Misc,
+    /// Any file in a tree with GLEP 74 Manifests.
+    Data,
+    /// A Manifest covering a subdirectory, possibly compressed.
+    Manifest,
+    /// A path that isn't part of the tree, such as distfiles. It has no size or hashes.
+    Ignore,
+    /// A path that can be missing from the tree, which is otherwise treated like `Ignore`.
+    Optional,
+}
+
+impl Kind {
+    /// Whether entries of this kind are files in the tree, which have to be there.
+    fn is_file(self) -> bool {
+        matches!(self, Kind::Ebuild | Kind::Aux | Kind::Misc | Kind::Data)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: Kind,
+    name: String,
+    size: u64,
+    hashes: Vec<(String, String)>,
+}
+
+/// Read a Manifest, decompressing it if it's one of a tree's `Manifest.gz` (or `.xz`) files.
+fn read(path: &Path) -> Result<String, Box<dyn Error>> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("gz") => {
+            let mut contents = String::new();
+            GzDecoder::new(fs::File::open(path)?).read_to_string(&mut contents)?;
+            Ok(contents)
+        }
+        Some("xz") => {
+            let mut contents = Vec::new();
+            lzma_rs::xz_decompress(&mut BufReader::new(fs::File::open(path)?), &mut contents)?;
+            Ok(String::from_utf8(contents)?)
+        }
+        _ => Ok(fs::read_to_string(path)?),
+    }
+}
+
+/// Read a Manifest, which may be OpenPGP-signed (signatures aren't checked).
+fn parse(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for (line_number, line) in read(path)?.lines().enumerate() {
+        if line.starts_with("-----BEGIN PGP SIGNATURE") {
+            break;
+        }
+
+        let mut fields = line.split_whitespace();
+        let kind = match fields.next() {
+            Some("DIST") => Kind::Dist,
+            Some("EBUILD") => Kind::Ebuild,
+            Some("AUX") => Kind::Aux,
+            Some("MISC") => Kind::Misc,
+            Some("DATA") => Kind::Data,
+            Some("MANIFEST") => Kind::Manifest,
+            Some("IGNORE") => Kind::Ignore,
+            Some("OPTIONAL") => Kind::Optional,
+            // blank lines, TIMESTAMP and the armor of signed Manifests
+            _ => continue,
+        };
+        let invalid = || GentooError::InvalidManifest(path.to_path_buf(), line_number + 1);
+        let name = fields.next().ok_or_else(invalid)?.to_string();
+        if matches!(kind, Kind::Ignore | Kind::Optional) {
+            entries.push(Entry {
+                kind,
+                name,
+                size: 0,
+                hashes: Vec::new(),
+            });
+            continue;
+        }
+        let size = fields
+            .next()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let mut hashes = Vec::new();
+        while let Some(algorithm) = fields.next() {
+            let hash = fields.next().ok_or_else(invalid)?;
+            hashes.push((algorithm.to_string(), hash.to_lowercase()));
+        }
+
+        entries.push(Entry {
+            kind,
+            name,
+            size,
+            hashes,
+        });
+    }
+    Ok(entries)
+}
+
+/// Check a file's size and every BLAKE2B and SHA512 hash its entry lists. Other hashes
+/// are ignored, but at least one of those two has to be there.
+fn check(entry: &Entry, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mismatch = |what| Box::new(GentooError::ManifestMismatch(path.to_path_buf(), what));
+    if fs::metadata(path)?.len() != entry.size {
+        return Err(mismatch("size"));
+    }
+
+    let mut blake2b = Blake2b512::new();
+    let mut sha512 = Sha512::new();
+    let mut file = fs::File::open(path)?;
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => {
+                blake2b.update(&buf[..n]);
+                sha512.update(&buf[..n]);
+            }
+        }
+    }
+    let blake2b = format!("{:x}", blake2b.finalize());
+    let sha512 = format!("{:x}", sha512.finalize());
+
+    let mut checked = false;
+    for (algorithm, hash) in &entry.hashes {
+        let (name, actual) = match algorithm.as_str() {
+            "BLAKE2B" => ("BLAKE2B hash", &blake2b),
+            "SHA512" => ("SHA512 hash", &sha512),
+            _ => continue,
+        };
+        if actual != hash {
+            return Err(mismatch(name));
+        }
+        checked = true;
+    }
+
+    if checked {
+        Ok(())
+    } else {
+        Err(Box::new(GentooError::NoSupportedHash(path.to_path_buf())))
+    }
+}
+
+/// Every file under a directory, relative to it.
+fn files_under(dir: &Path, prefix: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = prefix.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            files_under(&entry.path(), &name, files)?;
+        } else {
+            files.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Check a package directory against its Manifest, along with whichever of its distfiles
+/// have been downloaded to `distdir`. With thin Manifests, only distfiles are listed.
+pub(super) fn verify_package(dir: &Path, distdir: &Path, thin: bool) -> Result<(), Box<dyn Error>> {
+    let manifest = dir.join(MANIFEST_FILE);
+    let entries = if manifest.is_file() {
+        parse(&manifest)?
+    } else if thin {
+        // packages without any distfiles don't need a thin Manifest at all
+        Vec::new()
+    } else {
+        return Err(Box::new(GentooError::NoManifest(dir.to_path_buf())));
+    };
+
+    for entry in entries.iter().filter(|e| e.kind == Kind::Dist) {
+        let path = distdir.join(&entry.name);
+        if path.exists() {
+            check(entry, &path)?;
+        }
+    }
+    if thin {
+        return Ok(());
+    }
+
+    // everything in a thick Manifest's package has to be listed, so nothing can be slipped in
+    let mut files = Vec::new();
+    files_under(dir, Path::new(""), &mut files)?;
+    for file in files.iter().filter(|f| *f != Path::new(MANIFEST_FILE)) {
+        let (kind, name) = match file.strip_prefix(AUX_DIR) {
+            Ok(name) => (Kind::Aux, name),
+            Err(_) if file.extension().is_some_and(|e| e == "ebuild") => (Kind::Ebuild, &**file),
+            Err(_) => (Kind::Misc, &**file),
+        };
+        let entry = entries
+            .iter()
+            .find(|e| e.kind == kind && Path::new(&e.name) == name);
+        match entry {
+            Some(entry) => check(entry, &dir.join(file))?,
+            // Portage doesn't insist on MISC entries either
+            None if kind == Kind::Misc => {}
+            None => return Err(Box::new(GentooError::NotInManifest(dir.join(file)))),
+        }
+    }
+
+    for entry in entries.iter().filter(|e| e.kind.is_file()) {
+        let path = entry_path(dir, entry);
+        if !path.exists() {
+            return Err(Box::new(GentooError::MissingFromTree(path)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the file an entry of a Manifest in `dir` is for is.
+fn entry_path(dir: &Path, entry: &Entry) -> PathBuf {
+    match entry.kind {
+        Kind::Aux => dir.join(AUX_DIR).join(&entry.name),
+        _ => dir.join(&entry.name),
+    }
+}
+
+/// Whether a tree has GLEP 74 Manifests, which cover every file in it (eclasses, the metadata
+/// cache and so on) rather than just each package's. rsync and webrsync trees have them.
+pub(super) fn has_tree_manifest(location: &Path) -> bool {
+    location.join(MANIFEST_FILE).is_file()
+}
+
+/// Check `target` (a file or directory in a tree, relative to it) against the tree's GLEP 74
+/// Manifests, following sub-Manifests down from the top one to the deepest that covers it.
+pub(super) fn verify_tree(location: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    let mut dir = PathBuf::new();
+    let mut entries = parse(&location.join(MANIFEST_FILE))?;
+    loop {
+        let deeper = entries.iter().find(|e| {
+            let sub_dir = entry_path(&dir, e).parent().map(Path::to_path_buf);
+            e.kind == Kind::Manifest
+                && sub_dir.is_some_and(|sub_dir| sub_dir != dir && target.starts_with(sub_dir))
+        });
+        let path = match deeper {
+            Some(entry) => {
+                let path = entry_path(&dir, entry);
+                check(entry, &location.join(&path))?;
+                path
+            }
+            None => break,
+        };
+        entries = parse(&location.join(&path))?;
+        dir = path.parent().unwrap().to_path_buf();
+    }
+
+    verify_covered(location, &dir, &entries, target)
+}
+
+/// Check everything under `target` that the Manifest in `dir` covers: every file has to be
+/// listed and match, and every file listed has to be there.
+fn verify_covered(
+    location: &Path,
+    dir: &Path,
+    entries: &[Entry],
+    target: &Path,
+) -> Result<(), Box<dyn Error>> {
+    // sub-Manifests under the target cover their own directories
+    let mut sub_dirs = Vec::new();
+    for entry in entries.iter().filter(|e| e.kind == Kind::Manifest) {
+        let path = entry_path(dir, entry);
+        let sub_dir = path.parent().unwrap_or(dir);
+        if sub_dir != dir && sub_dir.starts_with(target) {
+            check(entry, &location.join(&path))?;
+            verify_covered(location, sub_dir, &parse(&location.join(&path))?, sub_dir)?;
+            sub_dirs.push(sub_dir.to_path_buf());
+        }
+    }
+
+    let files = entries
+        .iter()
+        .filter(|e| e.kind.is_file())
+        .map(|e| (entry_path(dir, e), e))
+        .collect::<HashMap<_, _>>();
+    let skipped = |file: &Path| {
+        // Manifests are checked by the one above them, and hidden files aren't part of a tree
+        let hidden = file
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        let manifest = file.file_name().is_some_and(|name| {
+            name == MANIFEST_FILE || name.to_string_lossy().starts_with("Manifest.")
+        });
+        let ignored = entries.iter().any(|e| {
+            matches!(e.kind, Kind::Ignore | Kind::Optional) && file.starts_with(dir.join(&e.name))
+        });
+        hidden || manifest || ignored || sub_dirs.iter().any(|d| file.starts_with(d))
+    };
+
+    let mut found = Vec::new();
+    let full = location.join(target);
+    if full.is_dir() {
+        files_under(&full, target, &mut found)?;
+    } else if full.exists() {
+        found.push(target.to_path_buf());
+    }
+    for file in found.iter().filter(|f| !skipped(f)) {
+        match files.get(file) {
+            Some(entry) => check(entry, &location.join(file))?,
+            None => {
+                return Err(Box::new(GentooError::NotInManifest(location.join(file))));
+            }
+        }
+    }
+
+    for path in files.keys().filter(|p| p.starts_with(target)) {
+        if !location.join(path).exists() {
+            return Err(Box::new(GentooError::MissingFromTree(location.join(path))));
+        }
+    }
+
+    Ok(())
+}
+
+/// The distfiles a package's Manifest lists.
+pub(super) fn distfiles(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let manifest = dir.join(MANIFEST_FILE);
+    if !manifest.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(parse(&manifest)?
+        .into_iter()
+        .filter(|e| e.kind == Kind::Dist)
+        .map(|e| e.name)
+        .collect())
+}
+
+/// Checksums of the distfiles a package's Manifest lists, as `algorithm:hex`.
+pub(super) fn dist_checksums(dir: &Path) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let manifest = dir.join(MANIFEST_FILE);
+    if !manifest.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(parse(&manifest)?
+        .into_iter()
+        .filter(|e| e.kind == Kind::Dist)
+        .filter_map(|e| {
+            let (algorithm, hash) = ["BLAKE2B", "SHA512"]
+                .iter()
+                .find_map(|&a| e.hashes.iter().find(|(algorithm, _)| algorithm == a))?;
+            let checksum = format!("{}:{}", algorithm.to_lowercase(), hash);
+            Some((e.name, checksum))
+        })
+        .collect())
+}
+
+/// Check a distfile that was just downloaded against the package's Manifest.
+pub(super) fn verify_distfile(
+    dir: &Path,
+    distdir: &Path,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let path = distdir.join(name);
+    let entries = parse(&dir.join(MANIFEST_FILE))?;
+    match entries
+        .iter()
+        .find(|e| e.kind == Kind::Dist && e.name == name)
+    {
+        Some(entry) => check(entry, &path),
+        None => Err(Box::new(GentooError::NotInManifest(path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// A Manifest line for a file as it is now.
+    fn line(kind: &str, name: &str, path: &Path) -> String {
+        let contents = fs::read(path).unwrap();
+        format!(
+            "{} {} {} BLAKE2B {:x} SHA512 {:x}\n",
+            kind,
+            name,
+            contents.len(),
+            Blake2b512::digest(&contents),
+            Sha512::digest(&contents)
+        )
+    }
+
+    /// A tree with GLEP 74 Manifests: a gzipped one for its category, a plain one for its
+    /// package and one for its eclasses.
+    fn tree() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        write(&root.join("app-misc/hello/hello-1.ebuild"), b"EAPI=8\n");
+        write(&root.join("app-misc/hello/files/fix.patch"), b"--- a\n");
+        write(&root.join("eclass/foo.eclass"), b"foo() { :; }\n");
+        write(&root.join("distfiles/hello-1.tar.gz"), b"local");
+
+        let package = root.join("app-misc/hello/Manifest");
+        let mut manifest = line(
+            "DATA",
+            "hello-1.ebuild",
+            &root.join("app-misc/hello/hello-1.ebuild"),
+        );
+        manifest += &line(
+            "DATA",
+            "files/fix.patch",
+            &root.join("app-misc/hello/files/fix.patch"),
+        );
+        manifest += "DIST hello-1.tar.gz 5 BLAKE2B 00 SHA512 00\n";
+        write(&package, manifest.as_bytes());
+
+        let category = root.join("app-misc/Manifest.gz");
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(line("MANIFEST", "hello/Manifest", &package).as_bytes())
+            .unwrap();
+        write(&category, &gz.finish().unwrap());
+
+        let eclass = root.join("eclass/Manifest");
+        write(
+            &eclass,
+            line("DATA", "foo.eclass", &root.join("eclass/foo.eclass")).as_bytes(),
+        );
+
+        let mut top = String::from("TIMESTAMP 2024-01-01T00:00:00Z\nIGNORE distfiles\n");
+        top += &line("MANIFEST", "app-misc/Manifest.gz", &category);
+        top += &line("MANIFEST", "eclass/Manifest", &eclass);
+        write(&root.join(MANIFEST_FILE), top.as_bytes());
+        tmp
+    }
+
+    #[test]
+    fn parses_entries() {
+        let tmp = tree();
+        let entries = parse(&tmp.path().join(MANIFEST_FILE)).unwrap();
+        let kinds = entries.iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![Kind::Ignore, Kind::Manifest, Kind::Manifest]);
+        assert_eq!(entries[1].name, "app-misc/Manifest.gz");
+
+        let category = parse(&tmp.path().join("app-misc/Manifest.gz")).unwrap();
+        assert_eq!(category[0].name, "hello/Manifest");
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(MANIFEST_FILE);
+        write(&path, b"EBUILD foo-1.ebuild 12 BLAKE2B\n");
+        let err = parse(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::InvalidManifest(_, 1))
+        ));
+    }
+
+    #[test]
+    fn verifies_tree_manifests() {
+        let tmp = tree();
+        assert!(has_tree_manifest(tmp.path()));
+        verify_tree(tmp.path(), Path::new("app-misc/hello")).unwrap();
+        verify_tree(tmp.path(), Path::new("eclass")).unwrap();
+        verify_tree(tmp.path(), Path::new("")).unwrap();
+    }
+
+    #[test]
+    fn catches_changed_eclasses() {
+        let tmp = tree();
+        write(&tmp.path().join("eclass/foo.eclass"), b"foo() { evil; }\n");
+        let err = verify_tree(tmp.path(), Path::new("eclass")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::ManifestMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn catches_unlisted_and_missing_files() {
+        let tmp = tree();
+        let patch = tmp.path().join("app-misc/hello/files/extra.patch");
+        write(&patch, b"+++ b\n");
+        let err = verify_tree(tmp.path(), Path::new("app-misc/hello")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::NotInManifest(path)) if *path == patch
+        ));
+
+        fs::remove_file(&patch).unwrap();
+        fs::remove_file(tmp.path().join("app-misc/hello/files/fix.patch")).unwrap();
+        let err = verify_tree(tmp.path(), Path::new("app-misc/hello")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::MissingFromTree(..))
+        ));
+    }
+
+    #[test]
+    fn catches_changed_sub_manifests() {
+        let tmp = tree();
+        let package = tmp.path().join("app-misc/hello/Manifest");
+        let mut contents = fs::read(&package).unwrap();
+        contents.extend(b"DIST more.tar.gz 1 BLAKE2B 00\n");
+        write(&package, &contents);
+        let err = verify_tree(tmp.path(), Path::new("app-misc/hello")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::ManifestMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn verifies_thick_package_manifests() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("hello");
+        write(&dir.join("hello-1.ebuild"), b"EAPI=8\n");
+        write(&dir.join("files/fix.patch"), b"--- a\n");
+        write(&dir.join("metadata.xml"), b"<pkgmetadata/>\n");
+        let mut manifest = line("EBUILD", "hello-1.ebuild", &dir.join("hello-1.ebuild"));
+        manifest += &line("AUX", "fix.patch", &dir.join("files/fix.patch"));
+        write(&dir.join(MANIFEST_FILE), manifest.as_bytes());
+        verify_package(&dir, &tmp.path().join("distfiles"), false).unwrap();
+
+        write(&dir.join("hello-2.ebuild"), b"EAPI=8\n");
+        assert!(verify_package(&dir, &tmp.path().join("distfiles"), false).is_err());
+        verify_package(&dir, &tmp.path().join("distfiles"), true).unwrap();
+    }
+}
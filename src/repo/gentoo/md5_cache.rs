@@ -0,0 +1,315 @@
+use super::manifest;
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    version::Version,
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where egencache writes the metadata of every ebuild, so it can be read without bash.
+const CACHE_DIR: &str = "metadata/md5-cache";
+
+lazy_static! {
+    // PMS section 3.2
+    static ref VERSION: Regex = Regex::new(
+        r"^[0-9]+(\.[0-9]+)*[a-z]?((_alpha|_beta|_pre|_rc|_p)[0-9]*)*(-r[0-9]+)?$"
+    )
+    .unwrap();
+}
+
+/// Split a package and version such as `ffmpeg-4.2.2-r1` at the first hyphen followed by a
+/// valid version. Package names can't end in something that looks like a version, so this
+/// is never ambiguous.
+pub(super) fn split_version(pf: &str) -> Option<(&str, &str)> {
+    pf.match_indices('-')
+        .map(|(idx, _)| (&pf[..idx], &pf[idx + 1..]))
+        .find(|(name, version)| !name.is_empty() && VERSION.is_match(version))
+}
+
+/// Split a dependency specification into its top-level clauses, keeping USE-conditional and
+/// any-of groups (`ssl? ( dev-libs/openssl )`, `|| ( a b )`) together as one clause each.
+fn clauses(deps: &str) -> Vec<String> {
+    let mut clauses = Vec::new();
+    let mut clause = Vec::new();
+    let mut depth = 0usize;
+    for token in deps.split_whitespace() {
+        match token {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        clause.push(token);
+
+        let prefix = token == "||" || token.ends_with('?');
+        if depth == 0 && !prefix {
+            clauses.push(clause.join(" "));
+            clause.clear();
+        }
+    }
+    if !clause.is_empty() {
+        clauses.push(clause.join(" "));
+    }
+    clauses
+}
+
+/// The names distfiles are saved as, from a SRC_URI like
+/// `https://example.org/foo-1.0.tar.gz -> foo.tar.gz ssl? ( https://example.org/ssl.patch )`.
+fn distfile_names(src_uri: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut tokens = src_uri.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "(" | ")" | "||" => {}
+            _ if token.ends_with('?') => {}
+            "->" => {
+                if let (Some(name), Some(renamed)) = (names.last_mut(), tokens.next()) {
+                    *name = renamed.to_string();
+                }
+            }
+            _ => names.push(token.rsplit('/').next().unwrap_or(token).to_string()),
+        }
+    }
+    names
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(str::to_string).collect()
+}
+
+/// Where the cached metadata of a version of a package is.
+pub(super) fn path(location: &Path, name: &str, version: &Version) -> PathBuf {
+    location
+        .join(CACHE_DIR)
+        .join(format!("{}-{}", name, version))
+}
+
+/// Read the `KEY=value` lines of a cache entry.
+pub(super) fn read_metadata(path: &Path) -> io::Result<HashMap<String, String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let idx = line.find('=')?;
+            Some((line[..idx].to_string(), line[idx + 1..].to_string()))
+        })
+        .collect())
+}
+
+/// Read a cache entry, with checksums of the ebuild and its distfiles (out of all the
+/// package's distfiles, `dist_checksums`) so lockfiles can tell if any of them change.
+fn read_entry(
+    path: &Path,
+    version: Version,
+    ebuild: &str,
+    dist_checksums: &BTreeMap<String, String>,
+) -> io::Result<IndexEntry> {
+    let mut fields = read_metadata(path)?;
+    let mut field = |key: &str| fields.remove(key).unwrap_or_default();
+
+    let mut entry = IndexEntry::new(version);
+    let md5 = field("_md5_");
+    if !md5.is_empty() {
+        entry
+            .checksums
+            .insert(ebuild.to_string(), format!("md5:{}", md5));
+    }
+    for name in distfile_names(&field("SRC_URI")) {
+        if let Some(checksum) = dist_checksums.get(&name) {
+            entry.checksums.insert(name, checksum.clone());
+        }
+    }
+
+    entry.summary = field("DESCRIPTION");
+    entry.homepage = field("HOMEPAGE");
+    entry.license = field("LICENSE");
+    entry.slot = field("SLOT");
+    entry.depends = clauses(&field("RDEPEND"));
+    entry.build_depends = clauses(&field("DEPEND"));
+    entry.host_depends = clauses(&field("BDEPEND"));
+    entry.variants = words(&field("IUSE"));
+    entry.keywords = words(&field("KEYWORDS"));
+    Ok(entry)
+}
+
+pub(super) fn sorted_dir(path: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok((
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            ))
+        })
+        .filter(|entry: &io::Result<(String, PathBuf)>| {
+            entry
+                .as_ref()
+                .map_or(true, |(name, _)| !name.starts_with('.'))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// Index every ebuild in a tree's metadata cache, or return `None` if it has no cache. Entries
+/// (and Manifests) that can't be read are skipped with a warning rather than failing the
+/// whole index over one package.
+pub(super) fn index(location: &Path) -> Result<Option<PackageIndex>, Box<dyn Error>> {
+    let cache = location.join(CACHE_DIR);
+    if !cache.is_dir() {
+        return Ok(None);
+    }
+
+    let mut index = PackageIndex::default();
+    // versions of a package are sorted together, so each Manifest is only read once
+    let mut manifest: Option<(String, BTreeMap<String, String>)> = None;
+    for (category, category_path) in sorted_dir(&cache)? {
+        if !category_path.is_dir() {
+            continue;
+        }
+
+        let entries = match sorted_dir(&category_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("warning: skipping '{}': {}", category_path.display(), e);
+                continue;
+            }
+        };
+        for (pf, path) in entries {
+            let parsed = split_version(&pf).and_then(|(name, version)| {
+                version
+                    .parse::<Version>()
+                    .ok()
+                    .map(|version| (name, version))
+            });
+            let (name, version) = match parsed {
+                Some((name, version)) => (format!("{}/{}", category, name), version),
+                None => {
+                    eprintln!(
+                        "warning: skipping '{}', which isn't named like an ebuild",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+
+            if manifest.as_ref().is_none_or(|(n, _)| *n != name) {
+                let dir = location.join(&name);
+                let checksums = manifest::dist_checksums(&dir).unwrap_or_else(|e| {
+                    eprintln!("warning: {} won't have distfile checksums: {}", name, e);
+                    BTreeMap::new()
+                });
+                manifest = Some((name.clone(), checksums));
+            }
+            let ebuild = format!("{}.ebuild", pf);
+            let checksums = &manifest.as_ref().unwrap().1;
+            match read_entry(&path, version, &ebuild, checksums) {
+                Ok(entry) => index.insert(name, entry),
+                Err(e) => eprintln!("warning: skipping '{}': {}", path.display(), e),
+            }
+        }
+    }
+
+    Ok(Some(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn splits_versions() {
+        assert_eq!(
+            split_version("ffmpeg-4.2.2-r1"),
+            Some(("ffmpeg", "4.2.2-r1"))
+        );
+        assert_eq!(
+            split_version("font-misc-1.0_p2"),
+            Some(("font-misc", "1.0_p2"))
+        );
+        assert_eq!(split_version("x-1a_rc3_p1"), Some(("x", "1a_rc3_p1")));
+        assert_eq!(split_version("-1.0"), None);
+        assert_eq!(split_version("gtk-2"), Some(("gtk", "2")));
+        assert_eq!(split_version("gtk+"), None);
+    }
+
+    #[test]
+    fn splits_dependency_clauses() {
+        assert_eq!(
+            clauses("a/b ssl? ( dev-libs/openssl ) || ( x/y x/z ) !c/d"),
+            vec!["a/b", "ssl? ( dev-libs/openssl )", "|| ( x/y x/z )", "!c/d"]
+        );
+    }
+
+    #[test]
+    fn names_distfiles() {
+        assert_eq!(
+            distfile_names(
+                "https://example.org/foo-1.0.tar.gz -> foo.tar.gz \
+                 ssl? ( https://example.org/a/ssl.patch ) bar.zip"
+            ),
+            vec!["foo.tar.gz", "ssl.patch", "bar.zip"]
+        );
+    }
+
+    #[test]
+    fn indexes_with_checksums_and_skips_broken_entries() {
+        let tmp = TempDir::new().unwrap();
+        let location = tmp.path();
+        let cache = location.join(CACHE_DIR);
+        write(
+            &cache.join("app-misc/hello-1.0"),
+            b"DESCRIPTION=Hi\nSRC_URI=https://example.org/hello-1.0.tar.gz\n\
+              KEYWORDS=amd64 ~arm64\nRDEPEND=a/b ssl? ( c/d )\n_md5_=0123\n",
+        );
+        write(&cache.join("app-misc/hello-2.0"), b"DESCRIPTION=\xff\xfe\n");
+        write(&cache.join("app-misc/README"), b"not an ebuild\n");
+        write(
+            &location.join("app-misc/hello/Manifest"),
+            b"DIST hello-1.0.tar.gz 3 BLAKE2B abc SHA512 def\n",
+        );
+
+        let index = index(location).unwrap().unwrap();
+        let entries = index.get("app-misc/hello");
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.version, "1.0".parse().unwrap());
+        assert_eq!(entry.summary, "Hi");
+        assert_eq!(entry.keywords, vec!["amd64", "~arm64"]);
+        assert_eq!(entry.depends, vec!["a/b", "ssl? ( c/d )"]);
+        assert_eq!(entry.checksums["hello-1.0.ebuild"], "md5:0123");
+        assert_eq!(entry.checksums["hello-1.0.tar.gz"], "blake2b:abc");
+    }
+
+    #[test]
+    fn indexes_without_broken_manifests() {
+        let tmp = TempDir::new().unwrap();
+        let location = tmp.path();
+        write(
+            &location.join(CACHE_DIR).join("app-misc/hello-1.0"),
+            b"SRC_URI=hello-1.0.tar.gz\n",
+        );
+        write(&location.join("app-misc/hello/Manifest"), b"DIST broken\n");
+
+        let index = index(location).unwrap().unwrap();
+        let entries = index.get("app-misc/hello");
+        assert_eq!(entries.len(), 1);
+        assert!(!entries[0].checksums.contains_key("hello-1.0.tar.gz"));
+    }
+
+    #[test]
+    fn has_no_index_without_a_cache() {
+        let tmp = TempDir::new().unwrap();
+        assert!(index(tmp.path()).unwrap().is_none());
+    }
+}
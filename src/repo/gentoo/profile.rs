@@ -0,0 +1,462 @@
+use super::{atom::Atom, layout::Layout, GentooError, GentooRepo};
+use crate::{index::IndexEntry, package::Variants};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs, io,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+};
+
+pub(super) const PROFILES_DIR: &str = "profiles";
+
+/// make.defaults variables that profiles add to (or subtract from with `-flag`, or clear
+/// with `-*`) rather than replace. Every `USE_EXPAND` variable is incremental too.
+const INCREMENTALS: &[&str] = &[
+    "ACCEPT_KEYWORDS",
+    "ACCEPT_LICENSE",
+    "CONFIG_PROTECT",
+    "CONFIG_PROTECT_MASK",
+    "FEATURES",
+    "IUSE_IMPLICIT",
+    "USE",
+    "USE_EXPAND",
+    "USE_EXPAND_HIDDEN",
+    "USE_EXPAND_IMPLICIT",
+    "USE_EXPAND_UNPREFIXED",
+];
+
+#[derive(Debug)]
+struct Mask {
+    line: String,
+    atom: Atom,
+    /// The comment above the mask (which says why) and the file it's in.
+    reason: String,
+}
+
+/// A Gentoo profile with its parents applied, which decides which versions can be used.
+#[derive(Debug, Default)]
+pub(super) struct Profile {
+    masks: Vec<Mask>,
+    unmasks: Vec<(String, Atom)>,
+    use_mask: Vec<String>,
+    use_force: Vec<String>,
+    vars: BTreeMap<String, String>,
+}
+
+/// Apply incremental tokens to a list: `-*` clears it, `-token` removes a token and
+/// anything else is added.
+fn apply_tokens<'a, I: IntoIterator<Item = &'a str>>(list: &mut Vec<String>, tokens: I) {
+    for token in tokens {
+        if token == "-*" {
+            list.clear();
+        } else if let Some(token) = token.strip_prefix('-') {
+            list.retain(|t| t != token);
+        } else if !list.iter().any(|t| t == token) {
+            list.push(token.to_string());
+        }
+    }
+}
+
+/// The contents of a profile file, or of every file in it if it's a directory (allowed by
+/// the portage-1 profile format). Missing files are empty.
+fn read_files(path: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut paths = if path.is_dir() {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .filter(|entry| {
+                entry.as_ref().map_or(true, |p| {
+                    !p.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .starts_with('.')
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        match fs::read_to_string(&path) {
+            Ok(contents) => files.push((path, contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(files)
+}
+
+/// Parse an atom, warning about (and skipping) ones storm can't make sense of rather than
+/// making the whole profile unusable.
+fn parse_atom(path: &Path, line: &str) -> Option<Atom> {
+    line.parse()
+        .map_err(|e| eprintln!("warning: {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Non-comment lines, trimmed.
+fn lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Expand a `$VAR` or `${VAR}` reference, the `$` having been read already.
+fn expand<F: Fn(&str) -> String>(chars: &mut Peekable<Chars>, lookup: F) -> String {
+    let mut name = String::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        for c in chars.by_ref() {
+            if c == '}' {
+                break;
+            }
+            name.push(c);
+        }
+    } else {
+        while let Some(&c) = chars
+            .peek()
+            .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+        {
+            name.push(c);
+            chars.next();
+        }
+    }
+    lookup(&name)
+}
+
+/// Parse make.defaults, which is a list of shell-style `KEY="value"` assignments that can
+/// refer to variables set before them.
+fn parse_make_defaults(
+    path: &Path,
+    contents: &str,
+    vars: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut assignments: Vec<(String, String)> = Vec::new();
+    let mut chars = contents.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let first = match chars.peek() {
+            Some(&c) => c,
+            None => break,
+        };
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            key.push(c);
+        }
+        if first == '#' || key.is_empty() || chars.next_if_eq(&'=').is_none() {
+            if first != '#' {
+                eprintln!("warning: {}: ignoring unparseable line", path.display());
+            }
+            while chars.next_if(|c| *c != '\n').is_some() {}
+            continue;
+        }
+
+        let lookup = |name: &str| {
+            assignments
+                .iter()
+                .rev()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .or_else(|| vars.get(name))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let mut value = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (Some('\''), c) => value.push(c),
+                (_, '\\') => match chars.next() {
+                    Some('\n') | None => {}
+                    Some(c) => value.push(c),
+                },
+                (_, '$') => value.push_str(&expand(&mut chars, lookup)),
+                (_, c) => value.push(c),
+            }
+        }
+        assignments.push((key, value));
+    }
+    assignments
+}
+
+/// Loads a profile directory after its parents, which it overrides.
+struct Loader<'a> {
+    /// The repo names and locations of the repo and its masters, for `repo:path` parents.
+    trees: Vec<(Option<String>, &'a Path)>,
+    /// The directories being loaded, to catch parents that include themselves.
+    stack: Vec<PathBuf>,
+    profile: Profile,
+}
+
+impl Loader<'_> {
+    /// Find a profile path: `repo:path` (the portage-2 profile format) is in the profiles of
+    /// the repo with that repo name, and anything else is relative to `base`.
+    fn resolve(&self, path: &str, base: &Path) -> Result<PathBuf, GentooError> {
+        match path.find(':') {
+            Some(idx) => {
+                let (name, path) = (&path[..idx], &path[idx + 1..]);
+                self.trees
+                    .iter()
+                    .find(|(repo_name, _)| repo_name.as_deref() == Some(name))
+                    .map(|(_, location)| location.join(PROFILES_DIR).join(path))
+                    .ok_or_else(|| GentooError::UnknownProfileRepo(name.to_string()))
+            }
+            None => Ok(base.join(path)),
+        }
+    }
+
+    fn load(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let canonical = fs::canonicalize(dir)
+            .ok()
+            .filter(|dir| dir.is_dir())
+            .ok_or_else(|| GentooError::NoProfile(dir.to_path_buf()))?;
+        if self.stack.contains(&canonical) {
+            return Err(Box::new(GentooError::ProfileCycle(canonical)));
+        }
+        self.stack.push(canonical);
+
+        for (_, contents) in read_files(&dir.join("parent"))? {
+            for parent in lines(&contents) {
+                let parent = self.resolve(parent, dir)?;
+                self.load(&parent)?;
+            }
+        }
+
+        for (path, contents) in read_files(&dir.join("make.defaults"))? {
+            self.make_defaults(&path, &contents);
+        }
+        self.package_mask(&dir.join("package.mask"))?;
+        for (path, contents) in read_files(&dir.join("package.unmask"))? {
+            for line in lines(&contents) {
+                if let Some(line) = line.strip_prefix('-') {
+                    self.profile.unmasks.retain(|(l, _)| l != line);
+                } else if let Some(atom) = parse_atom(&path, line) {
+                    self.profile.unmasks.push((line.to_string(), atom));
+                }
+            }
+        }
+        for (file, flags) in [
+            ("use.mask", &mut self.profile.use_mask),
+            ("use.force", &mut self.profile.use_force),
+        ] {
+            for (_, contents) in read_files(&dir.join(file))? {
+                apply_tokens(flags, lines(&contents));
+            }
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn make_defaults(&mut self, path: &Path, contents: &str) {
+        let vars = &mut self.profile.vars;
+        for (key, value) in parse_make_defaults(path, contents, vars) {
+            let incremental = INCREMENTALS.contains(&key.as_str())
+                || vars
+                    .get("USE_EXPAND")
+                    .is_some_and(|expand| expand.split_whitespace().any(|v| v == key));
+            if incremental {
+                let mut tokens = vars
+                    .get(&key)
+                    .map(|v| v.split_whitespace().map(str::to_string).collect())
+                    .unwrap_or_default();
+                apply_tokens(&mut tokens, value.split_whitespace());
+                vars.insert(key, tokens.join(" "));
+            } else {
+                vars.insert(key, value);
+            }
+        }
+    }
+
+    fn package_mask(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        for (path, contents) in read_files(path)? {
+            // each mask (or group of them) is explained by the comment above it
+            let mut comment = Vec::new();
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() {
+                    comment.clear();
+                } else if let Some(text) = line.strip_prefix('#') {
+                    comment.push(text.trim());
+                } else if let Some(line) = line.strip_prefix('-') {
+                    self.profile.masks.retain(|mask| mask.line != line);
+                } else if let Some(atom) = parse_atom(&path, line) {
+                    let reason = if comment.is_empty() {
+                        path.display().to_string()
+                    } else {
+                        format!("{} ({})", comment.join(" "), path.display())
+                    };
+                    self.profile.masks.push(Mask {
+                        line: line.to_string(),
+                        atom,
+                        reason,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Profile {
+    /// Load the repo's profile and its parents, along with the package.mask of the repo and
+    /// its masters (which apply whatever the profile).
+    pub fn load(repo: &GentooRepo, masters: &[&GentooRepo]) -> Result<Self, Box<dyn Error>> {
+        let mut loader = Loader {
+            trees: Vec::new(),
+            stack: Vec::new(),
+            profile: Profile::default(),
+        };
+        for tree in masters.iter().copied().chain([repo]) {
+            loader
+                .trees
+                .push((Layout::read(&tree.location)?.repo_name, &tree.location));
+            loader.package_mask(&tree.location.join(PROFILES_DIR).join("package.mask"))?;
+        }
+
+        if let Some(profile) = &repo.profile {
+            let dir = loader.resolve(profile, &repo.location.join(PROFILES_DIR))?;
+            loader.load(&dir)?;
+        }
+
+        // the repo's own setting goes on top, like ACCEPT_KEYWORDS in make.conf
+        let mut profile = loader.profile;
+        if let Some(keywords) = &repo.accept_keywords {
+            let mut accepted = profile.accept_keywords();
+            apply_tokens(&mut accepted, keywords.split_whitespace());
+            profile
+                .vars
+                .insert("ACCEPT_KEYWORDS".to_string(), accepted.join(" "));
+        }
+        Ok(profile)
+    }
+
+    fn accept_keywords(&self) -> Vec<String> {
+        self.vars
+            .get("ACCEPT_KEYWORDS")
+            .map(|v| v.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// The profile's variables, such as `CHOST` and `CFLAGS`, for ebuilds to build with.
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    /// The USE flags to build an ebuild with the given IUSE with, and every flag it may check
+    /// (its IUSE_EFFECTIVE). Requested variants override IUSE defaults and the profile's USE,
+    /// and use.force and use.mask override everything.
+    pub fn use_flags(&self, iuse: &[String], variants: &Variants) -> (Vec<String>, Vec<String>) {
+        let var = |name: &str| {
+            self.vars
+                .get(name)
+                .map(String::as_str)
+                .unwrap_or_default()
+                .split_whitespace()
+        };
+
+        let mut effective = iuse
+            .iter()
+            .map(|flag| flag.trim_start_matches(['+', '-']).to_string())
+            .chain(var("IUSE_IMPLICIT").map(str::to_string))
+            .collect::<Vec<_>>();
+        let mut enabled = Vec::new();
+        apply_tokens(
+            &mut enabled,
+            iuse.iter().filter_map(|f| f.strip_prefix('+')),
+        );
+        apply_tokens(&mut enabled, var("USE"));
+
+        // e.g. PYTHON_TARGETS="python3_12" is USE="python_targets_python3_12", and ARCH="amd64"
+        // (which isn't prefixed) is USE="amd64"
+        let mut expanded = Vec::new();
+        for name in var("USE_EXPAND") {
+            let prefix = name.to_lowercase();
+            expanded.extend(var(name).map(|value| format!("{}_{}", prefix, value)));
+        }
+        for name in var("USE_EXPAND_UNPREFIXED") {
+            expanded.extend(var(name).map(str::to_string));
+        }
+        apply_tokens(&mut effective, expanded.iter().map(String::as_str));
+        apply_tokens(&mut enabled, expanded.iter().map(String::as_str));
+
+        let variants = variants.flags().collect::<Vec<_>>();
+        apply_tokens(&mut enabled, variants.iter().map(String::as_str));
+        apply_tokens(&mut enabled, self.use_force.iter().map(String::as_str));
+        enabled.retain(|flag| !self.use_mask.contains(flag) && effective.contains(flag));
+
+        enabled.sort();
+        effective.sort();
+        effective.dedup();
+        (enabled, effective)
+    }
+
+    /// Whether any of a version's keywords are accepted. `*` accepts any stable keyword,
+    /// `~*` any testing one and `**` everything, even versions with no keywords at all.
+    fn accepts(accepted: &[String], keywords: &[String]) -> bool {
+        accepted.iter().any(|a| a == "**")
+            || keywords.iter().filter(|k| !k.starts_with('-')).any(|k| {
+                accepted.iter().any(|a| {
+                    a == k || (a == "*" && !k.starts_with('~')) || (a == "~*" && k.starts_with('~'))
+                })
+            })
+    }
+
+    /// Why a version from the repo named `repo` can't be used with the requested variants,
+    /// or `None` if it can.
+    pub fn mask_reason(
+        &self,
+        repo: Option<&str>,
+        name: &str,
+        entry: &IndexEntry,
+        variants: &Variants,
+    ) -> Option<String> {
+        let masked = self
+            .masks
+            .iter()
+            .rev()
+            .find(|mask| mask.atom.matches(repo, name, entry));
+        if let Some(mask) = masked {
+            if !self
+                .unmasks
+                .iter()
+                .any(|(_, a)| a.matches(repo, name, entry))
+            {
+                return Some(format!("masked by package.mask: {}", mask.reason));
+            }
+        }
+
+        // without a profile there's nothing to say which keywords are acceptable
+        let accepted = self.accept_keywords();
+        if !accepted.is_empty() && !Self::accepts(&accepted, &entry.keywords) {
+            let keywords = if entry.keywords.is_empty() {
+                "no keywords".to_string()
+            } else {
+                format!("keywords \"{}\"", entry.keywords.join(" "))
+            };
+            return Some(format!(
+                "has {}, but ACCEPT_KEYWORDS is \"{}\"",
+                keywords,
+                accepted.join(" ")
+            ));
+        }
+
+        variants.iter().find_map(|(flag, enabled)| {
+            if enabled && self.use_mask.iter().any(|f| f == flag) {
+                Some(format!("USE flag '{}' is masked by the profile", flag))
+            } else if !enabled && self.use_force.iter().any(|f| f == flag) {
+                Some(format!("USE flag '{}' is forced on by the profile", flag))
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -0,0 +1,277 @@
+use super::{GentooRepo, SyncType};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Portage's default when a repo doesn't set one; the main repo defaults to `MAIN_PRIORITY`.
+const DEFAULT_PRIORITY: i32 = 0;
+const MAIN_PRIORITY: i32 = -1000;
+
+/// A repo from repos.conf, with the priority Portage would give it.
+#[derive(Debug)]
+pub(in crate::repo) struct ImportedRepo {
+    pub name: String,
+    pub repo: GentooRepo,
+    pub priority: i32,
+}
+
+type Sections = BTreeMap<String, BTreeMap<String, String>>;
+
+/// repos.conf can be a single file or a directory of them, read in name order.
+fn conf_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Portage skips hidden files and editor backups
+    files.retain(|f| {
+        let name = f.file_name().unwrap_or_default().to_string_lossy();
+        !name.starts_with('.') && !name.ends_with('~') && f.is_file()
+    });
+    files.sort();
+    Ok(files)
+}
+
+/// Parse INI sections, later files overriding earlier ones key by key.
+fn parse(path: &Path, contents: &str, sections: &mut Sections) {
+    let mut section = None;
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(name.trim().to_string());
+            sections.entry(name.trim().to_string()).or_default();
+            continue;
+        }
+
+        match (&section, line.find('=')) {
+            (Some(section), Some(idx)) => {
+                sections.get_mut(section).unwrap().insert(
+                    line[..idx].trim().to_string(),
+                    line[idx + 1..].trim().to_string(),
+                );
+            }
+            _ => eprintln!(
+                "warning: {}:{}: ignoring unparseable line",
+                path.display(),
+                line_number + 1
+            ),
+        }
+    }
+}
+
+/// The keys a repo can set, directly or in `[DEFAULT]`.
+const KEYS: &[&str] = &[
+    "location",
+    "priority",
+    "sync-cvs-repo",
+    "sync-depth",
+    "sync-openpgp-key-path",
+    "sync-type",
+    "sync-uri",
+];
+
+fn import_section(
+    name: &str,
+    mut keys: BTreeMap<String, String>,
+    defaults: &BTreeMap<String, String>,
+    main_repo: Option<&str>,
+) -> Result<ImportedRepo, Box<dyn Error>> {
+    // like configparser, which Portage reads repos.conf with, sections inherit [DEFAULT]
+    let mut take = |key: &str| keys.remove(key).or_else(|| defaults.get(key).cloned());
+    let location = take("location").ok_or("it has no location")?;
+    let sync_type = take("sync-type")
+        .map(|t| t.parse::<SyncType>())
+        .transpose()?;
+    let priority = match take("priority") {
+        Some(priority) => priority.parse()?,
+        None if main_repo == Some(name) => MAIN_PRIORITY,
+        None => DEFAULT_PRIORITY,
+    };
+
+    let repo = GentooRepo {
+        location: PathBuf::from(location),
+        sync_type,
+        sync_uri: take("sync-uri"),
+        sync_depth: take("sync-depth")
+            .map(|d| d.parse())
+            .transpose()?
+            .filter(|&depth| depth != 0),
+        sync_openpgp_key_path: take("sync-openpgp-key-path").map(PathBuf::from),
+        sync_cvs_repo: take("sync-cvs-repo"),
+        ..GentooRepo::default()
+    };
+    repo.validate()?;
+
+    for key in keys.keys() {
+        eprintln!(
+            "warning: ignoring unsupported key '{}' for repo '{}'",
+            key, name
+        );
+    }
+
+    Ok(ImportedRepo {
+        name: name.to_string(),
+        repo,
+        priority,
+    })
+}
+
+/// Read every repo from a repos.conf file or directory. Repos that can't be imported are
+/// reported and skipped rather than failing the whole import.
+pub(in crate::repo) fn import(path: &Path) -> Result<Vec<ImportedRepo>, Box<dyn Error>> {
+    let mut sections = Sections::new();
+    let unreadable = |path: &Path, e| format!("couldn't read '{}': {}", path.display(), e);
+    for file in conf_files(path).map_err(|e| unreadable(path, e))? {
+        let contents = fs::read_to_string(&file).map_err(|e| unreadable(&file, e))?;
+        parse(&file, &contents, &mut sections);
+    }
+
+    let mut defaults = sections.remove("DEFAULT").unwrap_or_default();
+    let main_repo = defaults.remove("main-repo");
+    for key in defaults.keys().filter(|k| !KEYS.contains(&k.as_str())) {
+        eprintln!("warning: ignoring unsupported key '{}' in [DEFAULT]", key);
+    }
+
+    let mut imported = Vec::new();
+    for (name, keys) in sections {
+        match import_section(&name, keys, &defaults, main_repo.as_deref()) {
+            Ok(repo) => imported.push(repo),
+            Err(e) => eprintln!("warning: not importing repo '{}': {}", name, e),
+        }
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn import_str(contents: &str) -> Vec<ImportedRepo> {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("repos.conf");
+        fs::write(&path, contents).unwrap();
+        import(&path).unwrap()
+    }
+
+    fn section(keys: &[(&str, &str)]) -> BTreeMap<String, String> {
+        keys.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sections_inherit_defaults() {
+        let imported = import_str(
+            "[DEFAULT]\n\
+             main-repo = gentoo\n\
+             sync-type = git\n\
+             sync-depth = 1\n\
+             \n\
+             [gentoo]\n\
+             location = /var/db/repos/gentoo\n\
+             sync-uri = https://example.org/gentoo.git\n\
+             \n\
+             [overlay]\n\
+             location = /var/db/repos/overlay\n\
+             sync-type = rsync\n\
+             sync-uri = rsync://example.org/overlay\n\
+             sync-depth = 0\n\
+             priority = 50\n",
+        );
+        assert_eq!(imported.len(), 2);
+
+        let gentoo = &imported[0];
+        assert_eq!(gentoo.name, "gentoo");
+        assert_eq!(gentoo.repo.sync_type, Some(SyncType::Git));
+        assert_eq!(gentoo.repo.sync_depth, Some(1));
+        assert_eq!(gentoo.priority, MAIN_PRIORITY);
+
+        // a section's own keys win, even when they turn a default off
+        let overlay = &imported[1];
+        assert_eq!(overlay.name, "overlay");
+        assert_eq!(overlay.repo.sync_type, Some(SyncType::Rsync));
+        assert_eq!(overlay.repo.sync_depth, None);
+        assert_eq!(overlay.priority, 50);
+    }
+
+    #[test]
+    fn skips_repos_without_a_location() {
+        let imported = import_str(
+            "[nowhere]\n\
+             sync-type = git\n\
+             sync-uri = https://example.org/nowhere.git\n\
+             \n\
+             [local]\n\
+             location = /var/db/repos/local\n",
+        );
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].name, "local");
+        assert_eq!(imported[0].repo.sync_type, None);
+        assert_eq!(imported[0].priority, DEFAULT_PRIORITY);
+
+        let err = import_section("nowhere", section(&[]), &section(&[]), None).unwrap_err();
+        assert_eq!(err.to_string(), "it has no location");
+        // but one can come from [DEFAULT]
+        let defaults = section(&[("location", "/var/db/repos/default")]);
+        let repo = import_section("nowhere", section(&[]), &defaults, None).unwrap();
+        assert_eq!(repo.repo.location, Path::new("/var/db/repos/default"));
+    }
+
+    #[test]
+    fn skips_repos_with_an_unknown_sync_type() {
+        let imported = import_str(
+            "[darcs]\n\
+             location = /var/db/repos/darcs\n\
+             sync-type = darcs\n\
+             sync-uri = https://example.org/darcs\n",
+        );
+        assert!(imported.is_empty());
+
+        let keys = section(&[("location", "/var/db/repos/darcs"), ("sync-type", "darcs")]);
+        let err = import_section("darcs", keys, &section(&[]), None).unwrap_err();
+        assert_eq!(err.to_string(), "unknown sync type 'darcs'");
+    }
+
+    #[test]
+    fn merges_duplicate_repos_key_by_key() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(
+            tmp.path().join("00-gentoo.conf"),
+            "[gentoo]\n\
+             location = /var/db/repos/gentoo\n\
+             sync-type = git\n\
+             sync-uri = https://example.org/gentoo.git\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp.path().join("10-local.conf"),
+            "[gentoo]\nlocation = /srv/gentoo\n\n[gentoo]\npriority = 10\n",
+        )
+        .unwrap();
+        // ignored, like Portage does
+        fs::write(
+            tmp.path().join("20-local.conf~"),
+            "[gentoo]\npriority = 20\n",
+        )
+        .unwrap();
+
+        let imported = import(tmp.path()).unwrap();
+        assert_eq!(imported.len(), 1);
+        let gentoo = &imported[0];
+        assert_eq!(gentoo.repo.location, Path::new("/srv/gentoo"));
+        assert_eq!(gentoo.repo.sync_type, Some(SyncType::Git));
+        assert_eq!(gentoo.priority, 10);
+    }
+}
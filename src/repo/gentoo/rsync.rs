@@ -0,0 +1,221 @@
+use super::{
+    layout::{Layout, LAYOUT_FILE, REPO_NAME_FILE},
+    run_client, GentooRepo, LOCAL_DIRS,
+};
+use std::{error::Error, ffi::OsString, fs, path::Path};
+
+/// Written by the tree's maintainers every time it changes.
+const TIMESTAMP: &str = "metadata/timestamp.chk";
+
+const OPTIONS: &[&str] = &[
+    "--recursive",
+    "--links",
+    "--safe-links",
+    "--perms",
+    "--times",
+    "--omit-dir-times",
+    "--compress",
+    "--force",
+    "--whole-file",
+    "--delete",
+    "--timeout=180",
+    "--exclude=/.git",
+];
+
+fn read_timestamp(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Mirror the tree into the location, returning its timestamp (if it has one) as the revision.
+pub(super) fn sync(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+) -> Result<Option<String>, Box<dyn Error>> {
+    fs::create_dir_all(&repo.location)?;
+    let staging = repo.staging_path();
+    repo.clear_staging()?;
+    fs::create_dir(&staging)?;
+
+    let synced = sync_in(repo, uri.trim_end_matches('/'), others, &staging);
+    let _ = fs::remove_dir_all(&staging);
+    synced
+}
+
+fn sync_in(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+    staging: &Path,
+) -> Result<Option<String>, Box<dyn Error>> {
+    // fetching just the timestamp (and the layout, to check before anything changes) is much
+    // cheaper than having rsync compare the whole tree
+    let mut args = vec![
+        OsString::from("--quiet"),
+        "--timeout=180".into(),
+        "--ignore-missing-args".into(),
+    ];
+    args.extend(
+        [TIMESTAMP, LAYOUT_FILE, REPO_NAME_FILE]
+            .iter()
+            .map(|path| format!("{}/{}", uri, path).into()),
+    );
+    let mut destination = staging.to_path_buf().into_os_string();
+    destination.push("/");
+    args.push(destination);
+    run_client("rsync", &args, None)?;
+
+    let staged = |path: &str| staging.join(Path::new(path).file_name().unwrap());
+    let timestamp = repo.location.join(TIMESTAMP);
+    let remote = read_timestamp(&staged(TIMESTAMP));
+    if remote.is_some() && remote == read_timestamp(&timestamp) {
+        println!(
+            "{} hasn't changed since it was last synced",
+            repo.location.display()
+        );
+        return Ok(remote);
+    }
+
+    let read = |path: &str| fs::read_to_string(staged(path)).ok();
+    let layout = Layout::parse(
+        read(LAYOUT_FILE).as_deref(),
+        read(REPO_NAME_FILE).as_deref(),
+    )?;
+    repo.check_incoming(&layout, others)?;
+
+    // the timestamp only goes in once the rest of the tree has, so an interrupted sync
+    // doesn't look finished to the next one
+    let mut destination = repo.location.clone().into_os_string();
+    destination.push("/");
+    let mut args = OPTIONS.iter().map(OsString::from).collect::<Vec<_>>();
+    args.extend(
+        LOCAL_DIRS
+            .iter()
+            .chain(&[TIMESTAMP])
+            .map(|path| format!("--exclude=/{}", path).into()),
+    );
+    // trailing slashes make rsync mirror the contents rather than the directory itself
+    args.extend(vec![format!("{}/", uri).into(), destination]);
+    run_client("rsync", &args, None)?;
+
+    if remote.is_some() {
+        fs::create_dir_all(timestamp.parent().unwrap())?;
+        fs::rename(staged(TIMESTAMP), &timestamp)?;
+    } else if timestamp.exists() {
+        fs::remove_file(&timestamp)?;
+    }
+    Ok(remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::PermissionsExt, process::Command};
+    use tempfile::TempDir;
+
+    fn have_rsync() -> bool {
+        let found = Command::new("rsync").arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: rsync isn't installed");
+        }
+        found
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn syncs_only_when_the_timestamp_changes() {
+        if !have_rsync() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let upstream = tmp.path().join("upstream");
+        let uri = upstream.to_str().unwrap();
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        write(&upstream.join(TIMESTAMP), "one\n");
+        write(&upstream.join("cat/pkg/pkg-1.ebuild"), "1");
+        write(&repo.location.join("distfiles/pkg-1.tar.gz"), "local");
+
+        assert_eq!(sync(&repo, uri, &[]).unwrap().as_deref(), Some("one"));
+        let ebuild = repo.location.join("cat/pkg/pkg-1.ebuild");
+        assert_eq!(fs::read_to_string(&ebuild).unwrap(), "1");
+        assert_eq!(
+            read_timestamp(&repo.location.join(TIMESTAMP)).unwrap(),
+            "one"
+        );
+        assert!(repo.location.join("distfiles/pkg-1.tar.gz").exists());
+        assert!(!repo.staging_path().exists());
+
+        write(&upstream.join("cat/pkg/pkg-1.ebuild"), "2");
+        sync(&repo, uri, &[]).unwrap();
+        assert_eq!(fs::read_to_string(&ebuild).unwrap(), "1");
+
+        write(&upstream.join(TIMESTAMP), "two\n");
+        assert_eq!(sync(&repo, uri, &[]).unwrap().as_deref(), Some("two"));
+        assert_eq!(fs::read_to_string(&ebuild).unwrap(), "2");
+    }
+
+    #[test]
+    fn failed_transfer_keeps_the_old_timestamp() {
+        if !have_rsync() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let upstream = tmp.path().join("upstream");
+        let uri = upstream.to_str().unwrap();
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        write(&upstream.join(TIMESTAMP), "one\n");
+        write(&upstream.join("cat/pkg/pkg-1.ebuild"), "1");
+        sync(&repo, uri, &[]).unwrap();
+
+        // a file rsync can't read fails the transfer after the timestamp was fetched
+        write(&upstream.join(TIMESTAMP), "two\n");
+        let unreadable = upstream.join("cat/pkg/pkg-2.ebuild");
+        write(&unreadable, "2");
+        fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read(&unreadable).is_ok() {
+            eprintln!("skipping: running as root, so nothing is unreadable");
+            return;
+        }
+
+        assert!(sync(&repo, uri, &[]).is_err());
+        assert_eq!(
+            read_timestamp(&repo.location.join(TIMESTAMP)).unwrap(),
+            "one"
+        );
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn refuses_trees_needing_missing_masters() {
+        if !have_rsync() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let upstream = tmp.path().join("upstream");
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        write(&upstream.join(TIMESTAMP), "one\n");
+        write(&upstream.join(LAYOUT_FILE), "masters = gentoo\n");
+        write(&upstream.join("cat/pkg/pkg-1.ebuild"), "1");
+
+        let err = sync(&repo, upstream.to_str().unwrap(), &[]).unwrap_err();
+        assert!(crate::repo::gentoo::is_missing_master(&*err));
+        assert!(!repo.location.join("cat").exists());
+        assert!(!repo.staging_path().exists());
+    }
+}
@@ -0,0 +1,187 @@
+use super::{
+    layout::{Layout, LAYOUT_FILE, REPO_NAME_FILE},
+    run_client, GentooRepo,
+};
+use std::error::Error;
+
+/// Check out the repo on the first sync and update it afterwards, returning the synced revision.
+pub(super) fn sync(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+) -> Result<String, Box<dyn Error>> {
+    if repo.location.join(".svn").exists() {
+        // pin the revision, so the layout checked is the one the update gets
+        let revision = run_client(
+            "svn",
+            &[
+                "info",
+                "--show-item",
+                "revision",
+                "--non-interactive",
+                "--",
+                uri,
+            ],
+            None,
+        )?;
+        // a missing file can't be told apart from a failed cat, but the update would fail too
+        let cat = |path: &str| {
+            let target = format!("{}/{}@{}", uri, path, revision);
+            run_client("svn", &["cat", "--non-interactive", "--", &target], None).ok()
+        };
+        let layout = Layout::parse(cat(LAYOUT_FILE).as_deref(), cat(REPO_NAME_FILE).as_deref())?;
+        repo.check_incoming(&layout, others)?;
+
+        run_client(
+            "svn",
+            &[
+                "update",
+                "--quiet",
+                "--non-interactive",
+                "--revision",
+                &revision,
+            ],
+            Some(&repo.location),
+        )?;
+    } else {
+        repo.check_out(others, |staging| {
+            run_client(
+                "svn",
+                &[
+                    "checkout".as_ref(),
+                    "--quiet".as_ref(),
+                    "--non-interactive".as_ref(),
+                    "--".as_ref(),
+                    uri.as_ref(),
+                    staging.as_os_str(),
+                ],
+                None,
+            )
+            .map(drop)
+        })?;
+    }
+
+    run_client(
+        "svn",
+        &["info", "--show-item", "revision"],
+        Some(&repo.location),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+    use tempfile::TempDir;
+
+    fn have_svn() -> bool {
+        let found = Command::new("svnadmin").arg("--version").output().is_ok()
+            && Command::new("svn").arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: svn isn't installed");
+        }
+        found
+    }
+
+    fn svn(dir: &Path, args: &[&str]) -> String {
+        run_client("svn", args, Some(dir)).unwrap()
+    }
+
+    /// Write a file into a working copy and commit it.
+    fn commit(work: &Path, file: &str, contents: &str) {
+        let path = work.join(file);
+        let new = !path.exists();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        if new {
+            svn(work, &["add", "--quiet", "--parents", file]);
+        }
+        svn(work, &["commit", "--quiet", "--message", file]);
+    }
+
+    /// An empty upstream repo with a working copy of it to commit from.
+    fn repo(tmp: &TempDir) -> (GentooRepo, String, PathBuf) {
+        let upstream = tmp.path().join("upstream");
+        run_client(
+            "svnadmin",
+            &["create", upstream.to_str().unwrap()],
+            Some(tmp.path()),
+        )
+        .unwrap();
+        let uri = format!("file://{}", upstream.display());
+        svn(tmp.path(), &["checkout", "--quiet", &uri, "work"]);
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        (repo, uri, tmp.path().join("work"))
+    }
+
+    #[test]
+    fn checks_out_then_updates() {
+        if !have_svn() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let (repo, uri, work) = repo(&tmp);
+
+        commit(&work, "a", "1");
+        assert_eq!(sync(&repo, &uri, &[]).unwrap(), "1");
+        assert_eq!(fs::read_to_string(repo.location.join("a")).unwrap(), "1");
+        assert!(!repo.staging_path().exists());
+
+        commit(&work, "b", "2");
+        commit(&work, "a", "3");
+        assert_eq!(sync(&repo, &uri, &[]).unwrap(), "3");
+        assert_eq!(fs::read_to_string(repo.location.join("b")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(repo.location.join("a")).unwrap(), "3");
+    }
+
+    #[test]
+    fn failed_checkout_leaves_nothing_behind() {
+        if !have_svn() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        let missing = format!("file://{}", tmp.path().join("missing").display());
+
+        assert!(sync(&repo, &missing, &[]).is_err());
+        assert!(!repo.location.exists());
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn refuses_updates_needing_missing_masters() {
+        if !have_svn() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let (repo, uri, work) = repo(&tmp);
+        commit(&work, LAYOUT_FILE, "masters =\n");
+        sync(&repo, &uri, &[]).unwrap();
+
+        commit(&work, LAYOUT_FILE, "masters = gentoo\n");
+        let err = sync(&repo, &uri, &[]).unwrap_err();
+        assert!(crate::repo::gentoo::is_missing_master(&*err));
+        assert_eq!(
+            fs::read_to_string(repo.location.join(LAYOUT_FILE)).unwrap(),
+            "masters =\n"
+        );
+
+        let master = GentooRepo {
+            location: tmp.path().join("gentoo"),
+            ..GentooRepo::default()
+        };
+        fs::create_dir_all(master.location.join("profiles")).unwrap();
+        fs::write(master.location.join(REPO_NAME_FILE), "gentoo\n").unwrap();
+        assert_eq!(sync(&repo, &uri, &[&master]).unwrap(), "2");
+    }
+}
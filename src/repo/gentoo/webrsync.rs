@@ -0,0 +1,354 @@
+use super::{carry_over, civil_date, layout::Layout, run_client, GentooError, GentooRepo};
+use md5::{Digest, Md5};
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fs,
+    io::{self, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where Gentoo's release key is installed by app-crypt/openpgp-keys-gentoo-release.
+const DEFAULT_KEY: &str = "/usr/share/openpgp-keys/gentoo-release.asc";
+
+/// Snapshots are published daily, but the newest few may not have reached every mirror.
+const MAX_AGE_DAYS: u64 = 7;
+
+/// The date `days_ago` days before today (UTC) as `YYYYMMDD`, the way snapshots are named.
+fn snapshot_date(days_ago: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let (year, month, day) = civil_date(now.as_secs() / 86400 - days_ago);
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+fn download(uri: &str, name: &str, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let path = dir.join(name);
+    run_client(
+        "curl",
+        &[
+            "--fail".as_ref(),
+            "--silent".as_ref(),
+            "--location".as_ref(),
+            "--output".as_ref(),
+            path.as_os_str(),
+            format!("{}/{}", uri, name).as_ref(),
+        ],
+        None,
+    )?;
+    Ok(path)
+}
+
+fn verify_checksum(tarball: &Path, checksum_file: &Path) -> Result<(), Box<dyn Error>> {
+    let name = tarball.file_name().unwrap().to_string_lossy().into_owned();
+    // md5sum format: the checksum, whitespace, then the file name
+    let expected = fs::read_to_string(checksum_file)?
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut hasher = Md5::new();
+    io::copy(&mut fs::File::open(tarball)?, &mut hasher)?;
+    if format!("{:x}", hasher.finalize()) != expected {
+        return Err(Box::new(GentooError::ChecksumMismatch(name)));
+    }
+
+    Ok(())
+}
+
+fn verify_signature(
+    key: &Path,
+    tarball: &Path,
+    signature: &Path,
+    dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if !key.is_file() {
+        return Err(Box::new(GentooError::NoKey(key.to_path_buf())));
+    }
+
+    // a throwaway keyring holding only the configured key, so no other key can vouch for it
+    let home = dir.join("gnupg");
+    fs::create_dir(&home)?;
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+    let gpg = |args: &[&OsStr]| {
+        // the key was configured explicitly, so it's trusted without needing certification
+        let mut all = vec![
+            "--homedir".as_ref(),
+            home.as_os_str(),
+            "--batch".as_ref(),
+            "--quiet".as_ref(),
+            "--trust-model=always".as_ref(),
+        ];
+        all.extend(args);
+        run_client("gpg", &all, None)
+    };
+
+    gpg(&["--import".as_ref(), key.as_os_str()])?;
+    gpg(&[
+        "--verify".as_ref(),
+        signature.as_os_str(),
+        tarball.as_os_str(),
+    ])
+    .map_err(|_| {
+        GentooError::BadSignature(tarball.file_name().unwrap().to_string_lossy().into_owned())
+    })?;
+
+    Ok(())
+}
+
+/// Swap a freshly unpacked tree into the location, carrying over this machine's own
+/// directories (distfiles and the like) from the old tree. This takes two renames and then
+/// one per directory carried over, so it isn't atomic; if it's interrupted, the old tree is
+/// left in `old` for [`GentooRepo::clear_staging`] to restore or finish with.
+fn swap_in(tree: &Path, location: &Path, old: &Path) -> Result<(), Box<dyn Error>> {
+    let had_old = match fs::rename(location, old) {
+        Ok(()) => true,
+        Err(e) if e.kind() == ErrorKind::NotFound => false,
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    if let Err(e) = fs::rename(tree, location) {
+        if had_old {
+            fs::rename(old, location)?;
+        }
+        return Err(Box::new(e));
+    }
+
+    if had_old {
+        carry_over(old, location)?;
+    }
+
+    Ok(())
+}
+
+fn sync_in(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+    staging: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let uri = uri.trim_end_matches('/');
+    let snapshot = |date: &str| format!("portage-{}.tar.xz", date);
+
+    let (date, checksum_file) = (0..MAX_AGE_DAYS)
+        .map(snapshot_date)
+        .find_map(|date| {
+            let checksum_file = download(uri, &format!("{}.md5sum", snapshot(&date)), staging);
+            checksum_file.ok().map(|path| (date, path))
+        })
+        .ok_or_else(|| GentooError::NoSnapshot(uri.to_string()))?;
+
+    let tarball = download(uri, &snapshot(&date), staging)?;
+    let signature = download(uri, &format!("{}.gpgsig", snapshot(&date)), staging)?;
+    verify_checksum(&tarball, &checksum_file)?;
+    let key = repo
+        .sync_openpgp_key_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_KEY));
+    verify_signature(&key, &tarball, &signature, staging)?;
+
+    // snapshots hold a single portage/ directory
+    let tree = staging.join("tree");
+    fs::create_dir(&tree)?;
+    run_client(
+        "tar",
+        &[
+            "--extract".as_ref(),
+            "--strip-components=1".as_ref(),
+            "--file".as_ref(),
+            tarball.as_os_str(),
+            "--directory".as_ref(),
+            tree.as_os_str(),
+        ],
+        None,
+    )?;
+
+    repo.check_incoming(&Layout::read(&tree)?, others)?;
+    swap_in(&tree, &repo.location, &staging.join("old"))?;
+    Ok(date)
+}
+
+/// Download, verify and unpack the newest snapshot, returning its date as the revision.
+pub(super) fn sync(
+    repo: &GentooRepo,
+    uri: &str,
+    others: &[&GentooRepo],
+) -> Result<String, Box<dyn Error>> {
+    // everything is downloaded and unpacked next to the location, so the swap is just a rename
+    let staging = repo.staging_path();
+    repo.clear_staging()?;
+    fs::create_dir_all(&staging)?;
+
+    let synced = sync_in(repo, uri, others, &staging);
+    let cleared = repo.clear_staging();
+    synced.and_then(|date| cleared.map(|_| date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Publish today's snapshot of a one-package tree to `<tmp>/mirror`, signed with a fresh
+    /// key exported to `<tmp>/key.asc`.
+    fn publish(tmp: &Path) -> (GentooRepo, String) {
+        let gnupg = tmp.join("gnupg");
+        fs::create_dir(&gnupg).unwrap();
+        fs::set_permissions(&gnupg, fs::Permissions::from_mode(0o700)).unwrap();
+        let gpg = |args: &[&OsStr]| {
+            let mut all = vec![
+                "--homedir".as_ref(),
+                gnupg.as_os_str(),
+                "--batch".as_ref(),
+                "--quiet".as_ref(),
+                "--passphrase=".as_ref(),
+            ];
+            all.extend(args);
+            run_client("gpg", &all, None).unwrap();
+        };
+        gpg(&[
+            "--quick-gen-key".as_ref(),
+            "storm <storm@localhost>".as_ref(),
+            "ed25519".as_ref(),
+            "sign".as_ref(),
+            "never".as_ref(),
+        ]);
+        let key = tmp.join("key.asc");
+        gpg(&[
+            "--armor".as_ref(),
+            "--output".as_ref(),
+            key.as_os_str(),
+            "--export".as_ref(),
+        ]);
+
+        write(&tmp.join("src/portage/cat/pkg/pkg-1.ebuild"), "EAPI=8\n");
+        let mirror = tmp.join("mirror");
+        fs::create_dir(&mirror).unwrap();
+        let date = snapshot_date(0);
+        let tarball = mirror.join(format!("portage-{}.tar.xz", date));
+        run_client(
+            "tar",
+            &[
+                "--create".as_ref(),
+                "--xz".as_ref(),
+                "--file".as_ref(),
+                tarball.as_os_str(),
+                "--directory".as_ref(),
+                tmp.join("src").as_os_str(),
+                "portage".as_ref(),
+            ],
+            None,
+        )
+        .unwrap();
+
+        let mut checksum = tarball.clone().into_os_string();
+        checksum.push(".md5sum");
+        let digest = Md5::digest(fs::read(&tarball).unwrap());
+        fs::write(checksum, format!("{:x}  portage-{}.tar.xz\n", digest, date)).unwrap();
+        let mut signature = tarball.clone().into_os_string();
+        signature.push(".gpgsig");
+        gpg(&[
+            "--output".as_ref(),
+            signature.as_os_str(),
+            "--detach-sign".as_ref(),
+            tarball.as_os_str(),
+        ]);
+        let _ = run_client(
+            "gpgconf",
+            &[
+                "--homedir".as_ref(),
+                gnupg.as_os_str(),
+                "--kill".as_ref(),
+                "gpg-agent".as_ref(),
+            ],
+            None,
+        );
+
+        let repo = GentooRepo {
+            location: tmp.join("tree"),
+            sync_openpgp_key_path: Some(key),
+            ..GentooRepo::default()
+        };
+        (repo, format!("file://{}", mirror.display()))
+    }
+
+    #[test]
+    fn swaps_in_verified_snapshots() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, uri) = publish(tmp.path());
+        write(&repo.location.join("cat/gone/gone-1.ebuild"), "EAPI=8\n");
+        write(&repo.location.join("distfiles/pkg-1.tar.gz"), "local");
+
+        assert_eq!(sync(&repo, &uri, &[]).unwrap(), snapshot_date(0));
+        assert!(repo.location.join("cat/pkg/pkg-1.ebuild").exists());
+        assert!(!repo.location.join("cat/gone").exists());
+        assert!(repo.location.join("distfiles/pkg-1.tar.gz").exists());
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn rejects_tampered_snapshots() {
+        let tmp = TempDir::new().unwrap();
+        let (repo, uri) = publish(tmp.path());
+        write(&repo.location.join("cat/old/old-1.ebuild"), "EAPI=8\n");
+        let tarball = tmp
+            .path()
+            .join(format!("mirror/portage-{}.tar.xz", snapshot_date(0)));
+        let mut contents = fs::read(&tarball).unwrap();
+        contents.push(0);
+        fs::write(&tarball, contents).unwrap();
+
+        let err = sync(&repo, &uri, &[]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::ChecksumMismatch(_))
+        ));
+        assert!(repo.location.join("cat/old/old-1.ebuild").exists());
+        assert!(!repo.staging_path().exists());
+    }
+
+    #[test]
+    fn restores_trees_from_interrupted_swaps() {
+        let tmp = TempDir::new().unwrap();
+        let repo = GentooRepo {
+            location: tmp.path().join("tree"),
+            ..GentooRepo::default()
+        };
+        let old = repo.staging_path().join("old");
+        write(&old.join("cat/pkg/pkg-1.ebuild"), "EAPI=8\n");
+        write(&old.join("distfiles/pkg-1.tar.gz"), "local");
+
+        // interrupted between the renames: the location is gone
+        repo.clear_staging().unwrap();
+        assert!(repo.location.join("cat/pkg/pkg-1.ebuild").exists());
+        assert!(repo.location.join("distfiles/pkg-1.tar.gz").exists());
+        assert!(!repo.staging_path().exists());
+
+        // interrupted while carrying over: the new tree is in place
+        fs::rename(&repo.location, tmp.path().join("moved")).unwrap();
+        write(&old.join("distfiles/pkg-1.tar.gz"), "local");
+        write(&repo.location.join("cat/pkg/pkg-2.ebuild"), "EAPI=8\n");
+        repo.check_out(&[], |_| Err("offline".into())).unwrap_err();
+        assert!(repo.location.join("cat/pkg/pkg-2.ebuild").exists());
+        assert!(repo.location.join("distfiles/pkg-1.tar.gz").exists());
+        assert!(!repo.staging_path().exists());
+
+        // distfiles on both sides can't be merged, so the old ones are kept for the user
+        write(&old.join("distfiles/pkg-2.tar.gz"), "local");
+        let err = repo.clear_staging().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(GentooError::InterruptedSwap(..))
+        ));
+        assert!(old.join("distfiles/pkg-2.tar.gz").exists());
+    }
+}
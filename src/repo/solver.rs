@@ -0,0 +1,348 @@
+use crate::{
+    index::{IndexEntry, PackageIndex},
+    version::{Compare, Constraint, Op, Version},
+};
+use quick_error::quick_error;
+use std::collections::HashMap;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum SolveError {
+        InvalidRelation(relation: String) {
+            display("'{}' isn't a valid package relation", relation)
+        }
+        Unsatisfiable(package: String, relation: String, reason: String) {
+            display("{} needs '{}', but {}", package, relation, reason)
+        }
+    }
+}
+
+/// How many candidates to try before giving up on finding another way around a failure.
+const MAX_STEPS: usize = 100_000;
+
+/// The versions of a package a relation accepts.
+#[derive(Debug, Clone)]
+pub(super) enum Bound {
+    Constraint(Constraint),
+    /// Any version starting with this one, like Alpine's `~1.2` (1.2.3, but not 1.20).
+    Prefix(Version),
+}
+
+impl Bound {
+    fn matches(&self, version: &Version, compare: Compare) -> bool {
+        match self {
+            Bound::Constraint(c) => c.matches_by(version, compare),
+            Bound::Prefix(prefix) => {
+                let (version, prefix) = (version.to_string(), prefix.to_string());
+                version.strip_prefix(&prefix).is_some_and(|rest| {
+                    !(rest.starts_with(|c: char| c.is_ascii_digit())
+                        && prefix.ends_with(|c: char| c.is_ascii_digit()))
+                })
+            }
+        }
+    }
+}
+
+/// One side of a relation, like `libc6 (>= 2.34)` in a dependency or `mail-transport-agent`
+/// in what a package provides.
+#[derive(Debug, Clone)]
+pub(super) struct Target {
+    pub name: String,
+    pub bound: Option<Bound>,
+}
+
+impl Target {
+    /// Whether a package satisfies this, either itself or through something it provides.
+    fn matches(
+        &self,
+        name: &str,
+        version: &Version,
+        provides: &[Target],
+        compare: Compare,
+    ) -> bool {
+        (self.name == name
+            && self
+                .bound
+                .as_ref()
+                .is_none_or(|b| b.matches(version, compare)))
+            || provides
+                .iter()
+                .any(|provided| self.provided_by(provided, compare))
+    }
+
+    fn provided_by(&self, provided: &Target, compare: Compare) -> bool {
+        provided.name == self.name
+            && match &self.bound {
+                None => true,
+                // only a versioned provide can satisfy a versioned relation
+                Some(bound) => match &provided.bound {
+                    Some(Bound::Constraint(p)) if p.op == Op::Eq => {
+                        bound.matches(&p.version, compare)
+                    }
+                    _ => false,
+                },
+            }
+    }
+}
+
+/// Parses one target in a repo's syntax for relations.
+pub(super) type Parser = fn(&str) -> Result<Target, SolveError>;
+
+/// Describe the packages a solution added, for showing what a package pulled in.
+pub(super) fn describe(packages: &[(&str, &IndexEntry)]) -> String {
+    packages
+        .iter()
+        .map(|(name, entry)| format!("{} {}", name, entry.version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A package picked for the root, with the fields that later picks are checked against.
+struct Chosen<'a> {
+    name: &'a str,
+    entry: &'a IndexEntry,
+    provides: Vec<Target>,
+    conflicts: Vec<Target>,
+}
+
+impl Chosen<'_> {
+    fn describe(&self) -> String {
+        format!("{} {}", self.name, self.entry.version)
+    }
+
+    /// Whether this conflicts with (or breaks) another package. A package can conflict with
+    /// something it provides itself, to make sure it's the only provider.
+    fn conflicts_with(&self, other: &Chosen, compare: Compare) -> bool {
+        self.name != other.name
+            && self
+                .conflicts
+                .iter()
+                .any(|c| c.matches(other.name, &other.entry.version, &other.provides, compare))
+    }
+}
+
+/// A relation some chosen package still needs satisfied.
+#[derive(Clone)]
+struct Need<'a> {
+    by: usize,
+    clause: &'a str,
+}
+
+#[derive(Default)]
+struct State<'a> {
+    chosen: Vec<Chosen<'a>>,
+    pending: Vec<Need<'a>>,
+    steps: usize,
+}
+
+/// Finds a set of packages that satisfies a package's dependencies and conflicts. Entries'
+/// `depends`, `provides` and `conflicts` are read with the repo's parser, and alternatives in
+/// a dependency are separated by `|` (as in Debian's `libfoo | libbar`), and versions are
+/// ordered with the repo's `compare`.
+pub(super) struct Solver<'a> {
+    parse: Parser,
+    compare: Compare,
+    packages: HashMap<&'a str, Vec<&'a IndexEntry>>,
+    /// The packages providing each virtual package, and how they provide it.
+    providers: HashMap<String, Vec<(&'a str, &'a IndexEntry, Target)>>,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(index: &'a PackageIndex, parse: Parser, compare: Compare) -> Self {
+        let mut packages = HashMap::<_, Vec<_>>::new();
+        let mut providers = HashMap::<_, Vec<_>>::new();
+        for (name, entry) in index.iter() {
+            packages.entry(name).or_default().push(entry);
+            // a bad Provides shouldn't make every other package uninstallable
+            for provided in entry.provides.iter().filter_map(|p| parse(p).ok()) {
+                providers
+                    .entry(provided.name.clone())
+                    .or_default()
+                    .push((name, entry, provided));
+            }
+        }
+        let newest_first = |a: &Version, b: &Version| compare(b, a).then_with(|| b.cmp(a));
+        for entries in packages.values_mut() {
+            entries.sort_by(|a, b| newest_first(&a.version, &b.version));
+        }
+        // by package, like the index, and newest first within each
+        for entries in providers.values_mut() {
+            entries.sort_by(|a, b| {
+                a.0.cmp(b.0)
+                    .then_with(|| newest_first(&a.1.version, &b.1.version))
+            });
+        }
+
+        Self {
+            parse,
+            compare,
+            packages,
+            providers,
+        }
+    }
+
+    /// Every package that has to go into the root for `name` to work, starting with `name`.
+    pub fn solve(
+        &self,
+        name: &'a str,
+        entry: &'a IndexEntry,
+    ) -> Result<Vec<(&'a str, &'a IndexEntry)>, SolveError> {
+        let mut state = State::default();
+        self.choose(&mut state, name, entry)?;
+        self.search(&mut state)?;
+        Ok(state.chosen.iter().map(|c| (c.name, c.entry)).collect())
+    }
+
+    fn parse_all(&self, targets: &[String]) -> Result<Vec<Target>, SolveError> {
+        targets.iter().map(|target| (self.parse)(target)).collect()
+    }
+
+    fn choose(
+        &self,
+        state: &mut State<'a>,
+        name: &'a str,
+        entry: &'a IndexEntry,
+    ) -> Result<(), SolveError> {
+        state.chosen.push(Chosen {
+            name,
+            entry,
+            provides: self.parse_all(&entry.provides)?,
+            conflicts: self.parse_all(&entry.conflicts)?,
+        });
+        // pending is a stack, and the first dependency should be looked at first
+        let by = state.chosen.len() - 1;
+        state
+            .pending
+            .extend(entry.depends.iter().rev().map(|clause| Need { by, clause }));
+        Ok(())
+    }
+
+    /// Candidates for a clause, in order of preference: alternatives in the order they're
+    /// listed, real packages (newest first) before ones providing the name.
+    fn candidates(&self, clause: &[Target]) -> Vec<(&'a str, &'a IndexEntry)> {
+        let mut candidates: Vec<(&'a str, &'a IndexEntry)> = Vec::new();
+        for target in clause {
+            if let Some((&name, entries)) = self.packages.get_key_value(target.name.as_str()) {
+                candidates.extend(
+                    entries
+                        .iter()
+                        .filter(|e| target.matches(name, &e.version, &[], self.compare))
+                        .map(|&e| (name, e)),
+                );
+            }
+            for (name, entry, provided) in self.providers.get(&target.name).into_iter().flatten() {
+                if target.provided_by(provided, self.compare) {
+                    candidates.push((name, entry));
+                }
+            }
+        }
+
+        let mut seen = Vec::new();
+        candidates.retain(|&(name, entry)| {
+            let new = !seen.contains(&(name, &entry.version));
+            seen.push((name, &entry.version));
+            new
+        });
+        candidates
+    }
+
+    /// Why nothing in the repo can satisfy a clause at all.
+    fn missing(&self, clause: &[Target]) -> String {
+        clause
+            .iter()
+            .map(|target| match self.packages.get(target.name.as_str()) {
+                Some(entries) => format!(
+                    "the repo only has {} {}",
+                    target.name,
+                    entries
+                        .iter()
+                        .map(|e| e.version.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                None if self.providers.contains_key(&target.name) => format!(
+                    "nothing in the repo provides {} at a matching version",
+                    target.name
+                ),
+                None => format!("the repo has no package {}", target.name),
+            })
+            .collect::<Vec<_>>()
+            .join(", and ")
+    }
+
+    fn search(&self, state: &mut State<'a>) -> Result<(), SolveError> {
+        while let Some(need) = state.pending.pop() {
+            let clause = need
+                .clause
+                .split('|')
+                .map(self.parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            let satisfied = state.chosen.iter().any(|chosen| {
+                clause.iter().any(|t| {
+                    t.matches(
+                        chosen.name,
+                        &chosen.entry.version,
+                        &chosen.provides,
+                        self.compare,
+                    )
+                })
+            });
+            if satisfied {
+                continue;
+            }
+
+            let needed_by = state.chosen[need.by].describe();
+            let unsatisfiable = |reason: String| {
+                SolveError::Unsatisfiable(needed_by.clone(), need.clause.to_string(), reason)
+            };
+            let candidates = self.candidates(&clause);
+            if candidates.is_empty() {
+                return Err(unsatisfiable(self.missing(&clause)));
+            }
+
+            let mut first_err = None;
+            for (name, entry) in candidates {
+                if state.steps >= MAX_STEPS {
+                    break;
+                }
+                state.steps += 1;
+
+                if let Some(other) = state.chosen.iter().find(|c| c.name == name) {
+                    first_err.get_or_insert_with(|| {
+                        unsatisfiable(format!("{} was already picked", other.describe()))
+                    });
+                    continue;
+                }
+
+                let (chosen, pending) = (state.chosen.len(), state.pending.clone());
+                self.choose(state, name, entry)?;
+                let new = state.chosen.last().unwrap();
+                let conflict = state.chosen[..chosen].iter().find(|other| {
+                    new.conflicts_with(other, self.compare)
+                        || other.conflicts_with(new, self.compare)
+                });
+                let result = match conflict {
+                    Some(other) => Err(unsatisfiable(format!(
+                        "{} can't be installed alongside {}",
+                        new.describe(),
+                        other.describe()
+                    ))),
+                    None => self.search(state),
+                };
+
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        first_err.get_or_insert(e);
+                        state.chosen.truncate(chosen);
+                        state.pending = pending;
+                    }
+                }
+            }
+            return Err(first_err.unwrap_or_else(|| {
+                unsatisfiable("there were too many combinations to try".to_string())
+            }));
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,354 @@
+use nix::{
+    fcntl::{open, OFlag},
+    libc,
+    mount::{mount, MsFlags},
+    sched::{unshare, CloneFlags},
+    sys::{
+        signal::kill,
+        stat::Mode,
+        statvfs::{statvfs, FsFlags},
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{self, chdir, chroot, fork, getegid, geteuid, getpid, ForkResult},
+};
+use quick_error::quick_error;
 use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    os::unix::{fs::symlink, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+quick_error! {
+    #[derive(Debug)]
+    pub enum SandboxError {
+        Unsupported(sandbox: SandboxConfig) {
+            display("the {} sandbox can't run builds yet", sandbox)
+        }
+        SetupFailed(err: io::Error) {
+            display("couldn't set up the sandbox: {}", err)
+        }
+        Failed(status: ExitStatus) {
+            display("sandboxed command failed ({})", status)
+        }
+    }
+}
+
+/// Directories of the host system that sandboxed commands can see (but not change), so
+/// builds can use its compilers and tools.
+const SYSTEM_DIRS: &[&str] = &[
+    "bin", "etc", "lib", "lib32", "lib64", "libx32", "opt", "sbin", "usr",
+];
+
+/// The only devices sandboxed commands can use, bound from the host's read-only.
+const DEVICES: &[&str] = &["null", "zero", "urandom", "tty"];
+
+/// Links in /dev to the process's own file descriptors, which a fresh /proc provides.
+const FD_LINKS: &[(&str, &str)] = &[
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase", tag = "type")]
 pub enum SandboxConfig {
+    #[default]
     Chroot,
     Firecracker,
     CrosVM,
 }
 
-impl Default for SandboxConfig {
-    fn default() -> Self {
-        Self::Chroot
+impl Display for SandboxConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SandboxConfig::Chroot => "chroot",
+            SandboxConfig::Firecracker => "firecracker",
+            SandboxConfig::CrosVM => "crosvm",
+        })
+    }
+}
+
+/// A directory from outside the sandbox to make visible inside it.
+pub struct Mount<'a> {
+    pub source: &'a Path,
+    /// Where the directory appears in the sandbox, as an absolute path.
+    pub target: &'a str,
+    pub writable: bool,
+}
+
+/// A bind mount ready to be made in the child, where nothing can be looked up safely.
+struct Bind {
+    source: PathBuf,
+    target: PathBuf,
+    /// Flags for remounting the bind read-only, or `None` to leave it writable.
+    read_only: Option<MsFlags>,
+}
+
+impl Bind {
+    fn new(source: &Path, target: PathBuf, writable: bool) -> Result<Self, Box<dyn Error>> {
+        let read_only = if writable {
+            None
+        } else {
+            // a remount has to keep the flags it was mounted with, or user namespaces refuse it
+            let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+            let existing = statvfs(source)?.flags();
+            for (fs_flag, ms_flag) in [
+                (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+                (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+                (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+            ] {
+                if existing.contains(fs_flag) {
+                    flags |= ms_flag;
+                }
+            }
+            Some(flags)
+        };
+
+        Ok(Self {
+            source: source.to_path_buf(),
+            target,
+            read_only,
+        })
+    }
+
+    fn mount(&self) -> nix::Result<()> {
+        let flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+        mount(
+            Some(&self.source),
+            &self.target,
+            None::<&str>,
+            flags,
+            None::<&str>,
+        )?;
+        if let Some(flags) = self.read_only {
+            mount(
+                None::<&str>,
+                &self.target,
+                None::<&str>,
+                flags,
+                None::<&str>,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write all of `contents` to a file that already exists, without allocating.
+fn write_file(path: &str, contents: &[u8]) -> nix::Result<()> {
+    let fd = open(path, OFlag::O_WRONLY, Mode::empty())?;
+    let written = unistd::write(fd, contents);
+    unistd::close(fd)?;
+    match written? {
+        n if n == contents.len() => Ok(()),
+        _ => Err(nix::Error::Sys(nix::errno::Errno::EIO)),
+    }
+}
+
+/// Wait for the sandbox's first process and exit the way it did. This is all the process
+/// that forked it does, since it's outside the sandbox's PID namespace.
+fn wait_and_exit(child: unistd::Pid) -> ! {
+    let code = loop {
+        match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, code)) => break code,
+            Ok(WaitStatus::Signaled(_, signal, _)) => {
+                let _ = kill(getpid(), signal);
+                break 128 + signal as i32;
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) | Ok(_) => continue,
+            Err(_) => break 1,
+        }
+    };
+    unsafe { libc::_exit(code) }
+}
+
+fn to_io(e: nix::Error) -> io::Error {
+    match e.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::other(e),
+    }
+}
+
+impl SandboxConfig {
+    /// Run a command in the sandbox with `root` as its root directory. Anything in `root`
+    /// stays there afterwards, and `mounts` are the only other places it can write to.
+    pub fn run(
+        &self,
+        command: &mut Command,
+        root: &Path,
+        mounts: &[Mount],
+    ) -> Result<(), Box<dyn Error>> {
+        if *self != SandboxConfig::Chroot {
+            return Err(Box::new(SandboxError::Unsupported(self.clone())));
+        }
+
+        fs::create_dir_all(root)?;
+        let mut binds = Vec::new();
+        for dir in SYSTEM_DIRS {
+            let host = Path::new("/").join(dir);
+            let target = root.join(dir);
+            match fs::symlink_metadata(&host) {
+                // e.g. /bin -> usr/bin on merged-/usr systems
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    symlink(fs::read_link(&host)?, target)?;
+                }
+                Ok(meta) if meta.is_dir() => {
+                    fs::create_dir_all(&target)?;
+                    binds.push(Bind::new(&host, target, false)?);
+                }
+                _ => {}
+            }
+        }
+        // a fresh /dev with just a few devices, rather than the host's
+        let dev = root.join("dev");
+        fs::create_dir_all(&dev)?;
+        for device in DEVICES {
+            let target = dev.join(device);
+            fs::File::create(&target)?;
+            binds.push(Bind::new(&Path::new("/dev").join(device), target, false)?);
+        }
+        for (name, target) in FD_LINKS {
+            symlink(target, dev.join(name))?;
+        }
+        let proc = root.join("proc");
+        fs::create_dir_all(&proc)?;
+        for m in mounts {
+            let target = root.join(m.target.trim_start_matches('/'));
+            fs::create_dir_all(&target)?;
+            binds.push(Bind::new(m.source, target, m.writable)?);
+        }
+        let tmp = root.join("tmp");
+        fs::create_dir_all(&tmp)?;
+
+        // the child can't allocate, so everything it needs is worked out here
+        let root = root.to_path_buf();
+        let uid_map = format!("0 {} 1", geteuid());
+        let gid_map = format!("0 {} 1", getegid());
+        let setup = move || -> nix::Result<()> {
+            // even as root, the sandbox only gets capabilities over its own namespaces
+            unshare(
+                CloneFlags::CLONE_NEWUSER
+                    | CloneFlags::CLONE_NEWPID
+                    | CloneFlags::CLONE_NEWNS
+                    | CloneFlags::CLONE_NEWIPC
+                    | CloneFlags::CLONE_NEWUTS
+                    | CloneFlags::CLONE_NEWNET,
+            )?;
+            write_file("/proc/self/setgroups", b"deny")?;
+            write_file("/proc/self/uid_map", uid_map.as_bytes())?;
+            write_file("/proc/self/gid_map", gid_map.as_bytes())?;
+
+            // only children enter the new PID namespace, and /proc has to be mounted from it
+            if let ForkResult::Parent { child } = fork()? {
+                wait_and_exit(child);
+            }
+
+            // keep the mounts from leaking back out to the host
+            mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&str>,
+            )?;
+            for bind in &binds {
+                bind.mount()?;
+            }
+            mount(
+                Some("tmpfs"),
+                &tmp,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                None::<&str>,
+            )?;
+            mount(
+                Some("proc"),
+                &proc,
+                Some("proc"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                None::<&str>,
+            )?;
+
+            chroot(&root)?;
+            chdir("/")
+        };
+        // only the forked child runs this, right before the command is executed
+        unsafe {
+            command.pre_exec(move || setup().map_err(to_io));
+        }
+
+        let status = command
+            .status()
+            .map_err(|e| Box::new(SandboxError::SetupFailed(e)))?;
+        if !status.success() {
+            return Err(Box::new(SandboxError::Failed(status)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Run a shell script in a chroot sandbox with `out` mounted writable at /out.
+    fn run(script: &str, out: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = TempDir::new().unwrap();
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", script])
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin");
+        let mounts = [Mount {
+            source: out,
+            target: "/out",
+            writable: true,
+        }];
+        SandboxConfig::Chroot.run(&mut command, &tmp.path().join("root"), &mounts)
+    }
+
+    #[test]
+    fn isolates_processes_and_devices() {
+        let out = TempDir::new().unwrap();
+        let script =
+            "echo $$ > /out/pid; ls /dev > /out/dev; ls /proc | grep -c '^[0-9]' > /out/procs; \
+                      echo hi > /dev/null && head -c 4 /dev/urandom | wc -c > /out/random";
+        if let Err(e) = run(script, out.path()) {
+            if matches!(e.downcast_ref(), Some(SandboxError::SetupFailed(_))) {
+                eprintln!("skipping: namespaces aren't available here ({})", e);
+                return;
+            }
+            panic!("{}", e);
+        }
+
+        let read = |name| fs::read_to_string(out.path().join(name)).unwrap();
+        assert_eq!(read("pid").trim(), "1");
+        assert_eq!(
+            read("dev").split_whitespace().collect::<Vec<_>>(),
+            ["fd", "null", "stderr", "stdin", "stdout", "tty", "urandom", "zero"]
+        );
+        // the shell and ls, at most
+        assert!(read("procs").trim().parse::<u32>().unwrap() <= 3);
+        assert_eq!(read("random").trim(), "4");
+    }
+
+    #[test]
+    fn keeps_the_host_read_only() {
+        let out = TempDir::new().unwrap();
+        let script = "for dir in /usr /etc; do ! touch $dir/storm-test 2>/dev/null || exit 1; done";
+        let result = run(script, out.path());
+        match result {
+            Err(e) if matches!(e.downcast_ref(), Some(SandboxError::SetupFailed(_))) => {
+                eprintln!("skipping: namespaces aren't available here ({})", e);
+            }
+            result => result.unwrap(),
+        }
+        assert!(!Path::new("/usr/storm-test").exists());
+        assert!(!Path::new("/etc/storm-test").exists());
     }
 }
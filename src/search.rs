@@ -0,0 +1,84 @@
+use crate::{config::Config, index::PackageIndex, repo::RepoError, store::Store};
+use clap::{App, Arg, ArgMatches};
+use regex::RegexBuilder;
+use std::error::Error;
+
+fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("Search repositories for packages")
+        .arg(
+            Arg::with_name("pattern")
+                .required(true)
+                .index(1)
+                .help("Text to look for in package names (case-insensitive)"),
+        )
+        .arg(
+            Arg::with_name("repo")
+                .long("repo")
+                .short("r")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only search this repository (may be given more than once)"),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .long("regex")
+                .short("x")
+                .help("Treat the pattern as a regular expression"),
+        )
+        .arg(
+            Arg::with_name("description")
+                .long("description")
+                .short("d")
+                .help("Match package descriptions as well as names"),
+        )
+}
+
+fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+    let store = Store::from_args(args)?;
+
+    let pattern = args.value_of("pattern").unwrap();
+    let pattern = if args.is_present("regex") {
+        RegexBuilder::new(pattern)
+    } else {
+        RegexBuilder::new(&regex::escape(pattern))
+    }
+    .case_insensitive(true)
+    .build()?;
+    let description = args.is_present("description");
+    let matches = |s: &str| pattern.is_match(s);
+
+    let mut repos = config.repo.by_precedence();
+    if let Some(only) = args.values_of("repo") {
+        let only = only.collect::<Vec<_>>();
+        if let Some(missing) = only.iter().find(|r| !config.repo.contains(r)) {
+            return Err(Box::new(RepoError::NoSuchRepo(missing.to_string())));
+        }
+        repos.retain(|r| only.contains(r));
+    }
+
+    for repo in repos {
+        let index = match PackageIndex::load(store.index_path(repo))? {
+            Some(index) => index,
+            None => {
+                eprintln!("warning: {}", RepoError::NotSynced(repo.to_string()));
+                continue;
+            }
+        };
+
+        for (name, entry) in index.newest(config.repo.compare(repo)) {
+            if matches(name) || (description && matches(&entry.summary)) {
+                if entry.summary.is_empty() {
+                    println!("{}:{} {}", repo, name, entry.version);
+                } else {
+                    println!("{}:{} {} — {}", repo, name, entry.version, entry.summary);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
@@ -0,0 +1,230 @@
+use crate::{
+    package::{PackageSpec, Variants},
+    sandbox::SandboxConfig,
+    version::Version,
+};
+use clap::ArgMatches;
+use quick_error::quick_error;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum StoreError {
+        NoStorePath {
+            display("no package store was defined; specify --pkgstore or set $STORMPATH")
+        }
+    }
+}
+
+const DATABASE_FILE: &str = "packages.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub repo: String,
+    pub name: String,
+    pub version: Version,
+    #[serde(default, skip_serializing_if = "Variants::is_empty")]
+    pub variants: Variants,
+    #[serde(default)]
+    pub installed: bool,
+    /// Overrides the configured sandbox when running this package.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
+    /// The archive or recipe directory a local package was installed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// A hash of what a local package was built from, so rebuilding a changed archive with the
+    /// same name and version doesn't reuse the old build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+impl Record {
+    pub fn is(&self, repo: &str, name: &str) -> bool {
+        self.repo == repo && self.name == name
+    }
+
+    /// Whether this is a build of the package a spec names. Local packages are matched by the
+    /// file they were built from.
+    pub fn matches(&self, package: &PackageSpec) -> bool {
+        if let Some(path) = package.path() {
+            let source = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            return self.source.as_ref() == Some(&source);
+        }
+
+        package.repo().is_none_or(|repo| repo == self.repo)
+            && package.name() == self.name
+            && package.version().matches(&self.version)
+            && package.variants().is_subset(&self.variants)
+    }
+
+    /// Whether two records describe the same build.
+    pub fn same_build(&self, other: &Record) -> bool {
+        self.is(&other.repo, &other.name)
+            && self.version == other.version
+            && self.variants == other.variants
+            && self.source == other.source
+            && self.digest == other.digest
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.repo, self.name)?;
+        if !self.variants.is_empty() {
+            write!(f, "{}", self.variants)?;
+        }
+        write!(f, " {}", self.version)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Database {
+    #[serde(default, rename = "package")]
+    packages: Vec<Record>,
+}
+
+/// Total size of the files under a path, not following symlinks.
+pub fn disk_usage(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        fs::read_dir(path)?.try_fold(metadata.len(), |total, entry| {
+            Ok(total + disk_usage(&entry?.path())?)
+        })
+    } else {
+        Ok(metadata.len())
+    }
+}
+
+/// The package store: every build storm has made, and which of them are installed.
+#[derive(Debug)]
+pub struct Store {
+    root: PathBuf,
+    db: Database,
+}
+
+impl Store {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self, Box<dyn Error>> {
+        let root = root.as_ref().to_path_buf();
+        let db = match fs::read_to_string(root.join(DATABASE_FILE)) {
+            Ok(s) => toml::from_str(&s)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Database::default(),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        Ok(Self { root, db })
+    }
+
+    pub fn from_args(args: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        // main() validates (and creates) the store path before any subcommand runs
+        Self::open(
+            args.value_of_os("pkgstore")
+                .ok_or(StoreError::NoStorePath)?,
+        )
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        // write to a temporary file first so a crash can't leave a truncated database
+        let path = self.root.join(DATABASE_FILE);
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::File::create(&tmp_path)?.write_all(toml::to_string_pretty(&self.db)?.as_bytes())?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Where storm keeps its own data about a repo, such as its package index.
+    pub fn repo_dir(&self, repo: &str) -> PathBuf {
+        self.root.join("repos").join(repo)
+    }
+
+    pub fn index_path(&self, repo: &str) -> PathBuf {
+        // indexes of big repos are slow to load as TOML
+        self.repo_dir(repo).join("index.json")
+    }
+
+    pub fn build_dir(&self, record: &Record) -> PathBuf {
+        let mut build = record.version.to_string();
+        if !record.variants.is_empty() {
+            build.push_str(&record.variants.to_string());
+        }
+        if let Some(digest) = &record.digest {
+            build.push('@');
+            build.push_str(&digest[..digest.len().min(12)]);
+        }
+
+        self.root
+            .join("builds")
+            .join(&record.repo)
+            .join(&record.name)
+            .join(build)
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.db.packages
+    }
+
+    pub fn find(&self, record: &Record) -> Option<&Record> {
+        self.db.packages.iter().find(|r| r.same_build(record))
+    }
+
+    /// The installed build of a package, if any.
+    pub fn installed(&self, repo: &str, name: &str) -> Option<&Record> {
+        self.db
+            .packages
+            .iter()
+            .find(|r| r.installed && r.is(repo, name))
+    }
+
+    pub fn add_build(&mut self, record: Record) {
+        self.db.packages.push(record);
+    }
+
+    /// Mark one build as installed (with its sandbox override), replacing any other installed
+    /// build of the package.
+    pub fn install(&mut self, installed: &Record) {
+        for record in self
+            .db
+            .packages
+            .iter_mut()
+            .filter(|r| r.is(&installed.repo, &installed.name))
+        {
+            record.installed = record.same_build(installed);
+            if record.installed {
+                record.sandbox = installed.sandbox.clone();
+            }
+        }
+    }
+
+    pub fn uninstall<F: FnMut(&Record) -> bool>(&mut self, mut filter: F) -> Vec<Record> {
+        let mut removed = Vec::new();
+        for record in self.db.packages.iter_mut() {
+            if record.installed && filter(record) {
+                record.installed = false;
+                removed.push(record.clone());
+            }
+        }
+        removed
+    }
+
+    /// Delete every build that isn't installed.
+    pub fn clean(&mut self) -> Result<Vec<Record>, Box<dyn Error>> {
+        let (unused, kept) = self.db.packages.drain(..).partition(|r| !r.installed);
+        self.db.packages = kept;
+
+        for record in &unused {
+            match fs::remove_dir_all(self.build_dir(record)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Box::new(e)),
+                _ => {}
+            }
+        }
+
+        Ok(unused)
+    }
+}
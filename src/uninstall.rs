@@ -1,7 +1,17 @@
-use crate::package::Package;
+use crate::{package, store::Store};
 use clap::{App, Arg, ArgMatches};
+use quick_error::quick_error;
 use std::error::Error;
 
+quick_error! {
+    #[derive(Debug)]
+    pub enum UninstallError {
+        NotInstalled(package: String) {
+            display("'{}' is not installed", package)
+        }
+    }
+}
+
 fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
     app.about("Uninstall packages")
         .arg(
@@ -19,12 +29,32 @@ fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
 }
 
 fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let _packages = args
-        .values_of("package")
-        .unwrap()
-        .map(Package::parse)
-        .collect::<Vec<_>>();
-    Ok(())
+    let packages = package::parse_specs(args.values_of("package").into_iter().flatten())?;
+
+    let mut store = Store::from_args(args)?;
+
+    // nothing is saved (or printed) until every package is found to be installed
+    let mut uninstalled = Vec::new();
+    for package in &packages {
+        let removed = store.uninstall(|r| r.matches(package));
+
+        if removed.is_empty() {
+            return Err(Box::new(UninstallError::NotInstalled(package.to_string())));
+        }
+        uninstalled.extend(removed);
+    }
+
+    for record in uninstalled {
+        println!("uninstalled {}", record);
+    }
+
+    if args.is_present("clean") {
+        for record in store.clean()? {
+            println!("removed build {}", record);
+        }
+    }
+
+    store.save()
 }
 
 pub static CMD: crate::SubCommand<()> = crate::SubCommand { args, run };
@@ -0,0 +1,400 @@
+use quick_error::quick_error;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+quick_error! {
+    #[derive(Debug, Clone)]
+    pub enum VersionError {
+        Empty {
+            display("version is empty")
+        }
+        InvalidChar(c: char, version: String) {
+            display("invalid character '{}' in version '{}'", c, version)
+        }
+        MissingVersion(op: Op) {
+            display("operator '{}' is missing a version", op)
+        }
+    }
+}
+
+/// A package version as published by a repository.
+///
+/// Versions are compared segment by segment: runs of digits compare numerically,
+/// runs of letters compare lexically, and anything else separates segments. An
+/// optional `epoch:` prefix outranks the rest of the version, and `~` sorts before
+/// everything (even the end of the version), so `1.0~rc1` comes before `1.0`. Gentoo's
+/// (and Alpine's) `_alpha`, `_beta`, `_pre` and `_rc` suffixes sort the same way as `~`.
+#[derive(Debug, Clone)]
+pub struct Version(String);
+
+impl Version {
+    fn split_epoch(&self) -> (u64, &str) {
+        match self.0.find(':') {
+            Some(idx) => match self.0[..idx].parse() {
+                Ok(epoch) => (epoch, &self.0[idx + 1..]),
+                Err(_) => (0, &self.0),
+            },
+            None => (0, &self.0),
+        }
+    }
+}
+
+fn is_separator(c: u8) -> bool {
+    !c.is_ascii_alphanumeric() && c != b'~'
+}
+
+/// Whether a version continues with something that sorts before its end, like `~rc1`.
+fn is_prerelease(s: &[u8]) -> bool {
+    match s.split_first() {
+        Some((b'~', _)) => true,
+        Some((b'_', rest)) => [&b"alpha"[..], b"beta", b"pre", b"rc"]
+            .iter()
+            .any(|suffix| rest.starts_with(suffix)),
+        _ => false,
+    }
+}
+
+fn skip_separators(mut s: &[u8]) -> &[u8] {
+    while let Some((_, rest)) = s
+        .split_first()
+        .filter(|(&c, _)| is_separator(c) && !is_prerelease(s))
+    {
+        s = rest;
+    }
+    s
+}
+
+fn compare_segments(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    loop {
+        a = skip_separators(a);
+        b = skip_separators(b);
+
+        match (is_prerelease(a), is_prerelease(b)) {
+            (true, true) => {
+                a = &a[1..];
+                b = &b[1..];
+                continue;
+            }
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(_), Some(_)) => {}
+        }
+
+        let numeric = a[0].is_ascii_digit();
+        let run = |s: &[u8]| {
+            s.iter()
+                .position(|c| c.is_ascii_digit() != numeric || !c.is_ascii_alphanumeric())
+                .unwrap_or(s.len())
+        };
+        let (a_len, b_len) = (run(a), run(b));
+
+        // numeric segments are newer than alphabetic ones
+        if b_len == 0 {
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let (a_seg, b_seg) = (&a[..a_len], &b[..b_len]);
+        let ordering = if numeric {
+            let trim = |s: &[u8]| {
+                let zeros = s.iter().take_while(|&&c| c == b'0').count();
+                s[zeros..].to_vec()
+            };
+            let (a_seg, b_seg) = (trim(a_seg), trim(b_seg));
+            a_seg
+                .len()
+                .cmp(&b_seg.len())
+                .then_with(|| a_seg.cmp(&b_seg))
+        } else {
+            a_seg.cmp(b_seg)
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+}
+
+/// Orders versions for repos whose version scheme storm's own ordering doesn't fit, like
+/// Debian's.
+pub type Compare = fn(&Version, &Version) -> Ordering;
+
+impl Version {
+    /// Compare two versions the way repos do, where differently-written versions can be
+    /// equivalent (`1.0`, `1.00` and `1-0`). Constraints match with this.
+    pub fn compare(&self, other: &Self) -> Ordering {
+        let (epoch, version) = self.split_epoch();
+        let (other_epoch, other_version) = other.split_epoch();
+        epoch
+            .cmp(&other_epoch)
+            .then_with(|| compare_segments(version.as_bytes(), other_version.as_bytes()))
+    }
+}
+
+// equivalent versions are still different releases (and are built in different directories),
+// so they're ordered by how they're written to keep equality exact
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.compare(other).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Version {}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(VersionError::Empty);
+        }
+
+        match s
+            .chars()
+            .find(|&c| !c.is_ascii_alphanumeric() && !".-_+~:".contains(c))
+        {
+            Some(c) => Err(VersionError::InvalidChar(c, s.to_string())),
+            None => Ok(Self(s.to_string())),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    pub fn matches(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        })
+    }
+}
+
+/// A single comparison against a version, such as `>=4.2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub op: Op,
+    pub version: Version,
+}
+
+impl Constraint {
+    pub fn matches_by(&self, version: &Version, compare: Compare) -> bool {
+        self.op.matches(compare(version, &self.version))
+    }
+}
+
+impl FromStr for Constraint {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // longest operators first so ">=" isn't parsed as ">" followed by "=4.2"
+        let (op, version) = [
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            ("!=", Op::Ne),
+            ("==", Op::Eq),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+            ("=", Op::Eq),
+        ]
+        .iter()
+        .find(|(prefix, _)| s.starts_with(prefix))
+        .map(|(prefix, op)| (*op, &s[prefix.len()..]))
+        .unwrap_or((Op::Eq, s));
+
+        match version.parse() {
+            Ok(version) => Ok(Self { op, version }),
+            Err(VersionError::Empty) if version.len() != s.len() => {
+                Err(VersionError::MissingVersion(op))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.version)
+    }
+}
+
+/// A set of constraints that must all hold, written as a comma-separated list
+/// (e.g. `>=4.2,<5`). An empty set matches any version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionReq(Vec<Constraint>);
+
+impl VersionReq {
+    pub fn is_any(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.matches_by(version, Version::compare)
+    }
+
+    pub fn matches_by(&self, version: &Version, compare: Compare) -> bool {
+        self.0.iter().all(|c| c.matches_by(version, compare))
+    }
+
+    /// Pick the newest version satisfying every constraint, as ordered by `compare`.
+    pub fn best_by<'v, I: IntoIterator<Item = &'v Version>>(
+        &self,
+        versions: I,
+        compare: Compare,
+    ) -> Option<&'v Version> {
+        versions
+            .into_iter()
+            .filter(|v| self.matches_by(v, compare))
+            .max_by(|a, b| compare(a, b).then_with(|| a.cmp(b)))
+    }
+}
+
+impl std::iter::FromIterator<Constraint> for VersionReq {
+    fn from_iter<I: IntoIterator<Item = Constraint>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, constraint) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", constraint)?;
+        }
+        Ok(())
+    }
+}
+
+serde_via_str!(Version);
+serde_via_str!(VersionReq);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn orders_segments() {
+        let ordered = [
+            "0.9", "1.0~rc1", "1.0_rc2", "1.0", "1.0a", "1.0.1", "1.2", "1.10", "2", "1:0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn equivalent_versions_are_distinct() {
+        assert_eq!(v("1.0").compare(&v("1.00")), Ordering::Equal);
+        assert_eq!(v("1.0").compare(&v("1-0")), Ordering::Equal);
+        assert_ne!(v("1.0"), v("1.00"));
+        assert_ne!(v("1.0").cmp(&v("1.00")), Ordering::Equal);
+        assert_eq!(v("1.0"), v("1.0"));
+    }
+
+    #[test]
+    fn constraints_match_equivalent_versions() {
+        let c: Constraint = "=1.0".parse().unwrap();
+        assert!(c.matches_by(&v("1.00"), Version::compare));
+        let c: Constraint = "<1.0".parse().unwrap();
+        assert!(c.matches_by(&v("1.0~beta"), Version::compare));
+        assert!(!c.matches_by(&v("1.0"), Version::compare));
+    }
+
+    #[test]
+    fn parses_requirements() {
+        let req: VersionReq = ">=4.2,<5".parse().unwrap();
+        assert_eq!(req.to_string(), ">=4.2,<5");
+        let versions = [v("4.1"), v("4.2"), v("4.9"), v("5.0")];
+        assert_eq!(req.best_by(&versions, Version::compare), Some(&v("4.9")));
+        assert!(VersionReq::default().is_any());
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        assert!(matches!("".parse::<Version>(), Err(VersionError::Empty)));
+        assert!(matches!(
+            "1.0/2".parse::<Version>(),
+            Err(VersionError::InvalidChar('/', _))
+        ));
+        assert!(matches!(
+            ">=".parse::<Constraint>(),
+            Err(VersionError::MissingVersion(Op::Ge))
+        ));
+    }
+}